@@ -0,0 +1,245 @@
+use std::path::Path;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime};
+use std::sync::Arc;
+use anyhow::bail;
+use cpu_time::ProcessTime;
+use errors::Error::HookFailedError;
+use log::{debug, error, info, warn};
+use crate::copy::{copy_snapshot, CopyOptions, FailedFiles};
+use crate::destination::{Destination, REMOTE_COPY_WORKERS};
+use crate::device::{copy_workers, device_kind};
+use crate::hooks::{hook_environment, run_hooks, HookOutcome, HookPhase, HOOK_LOG_NAME};
+use crate::job::{BackupJob, DestinationPolicy};
+use crate::plan::{plan, PlannedFile};
+use crate::preflight::check_destination;
+use crate::progress::ProgressEvent;
+use crate::report::{BackupReport, DestinationReport, DestinationStatus};
+use crate::selection::select_sources;
+use crate::snapshot_report::{ReportedFile, SnapshotReport};
+use crate::staging::{finalize, metadata_path, recover, SNAPSHOT_DIR_NAME, STAGING_DIR_NAME};
+use crate::throttle::{lower_priority, Throttle};
+
+/// Runs `job` on a new thread. Progress events are received from the returned channel, which is closed
+/// when the job ends.
+pub fn start(job: BackupJob) -> (Receiver<ProgressEvent>, JoinHandle<anyhow::Result<BackupReport>>) {
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        if job.low_priority {
+            lower_priority();
+        }
+        run(&job, &sender)
+    });
+    (receiver, handle)
+}
+
+/// Runs `job` on the destinations following the destination policy, sending progress events to `events`.
+/// The pre-backup hooks run before the files are selected, the post-backup hooks once the backup is over.
+/// An error is returned if a pre-backup hook configured to abort failed or the files could not be selected,
+/// the report tells how the backup went on every destination (see `BackupReport::into_result`).
+pub fn run(job: &BackupJob, events: &Sender<ProgressEvent>) -> anyhow::Result<BackupReport> {
+    info!(target: "general", "Backup starting..");
+    let started_at = SystemTime::now();
+    let start = Instant::now();
+    let snapshot_id = chrono::DateTime::<chrono::Local>::from(started_at).format("%Y%m%d-%H%M%S").to_string();
+
+    let hook_destination = pre_hook_destination(job);
+    let mut hooks = run_hooks(&job.config.pre_backup_hooks, HookPhase::Pre, &hook_environment(HookPhase::Pre, hook_destination, None));
+    send_hook_events(&hooks, events);
+    if let Some(failed) = hooks.iter().find(|outcome| { outcome.aborts() }) {
+        run_post_hooks(job, hook_destination, false, events);
+        bail!(HookFailedError { hook: failed.command.clone(), reason: failed.error.clone().unwrap_or_default() })
+    }
+    let hook_log: String = hooks.iter().map(|outcome| { outcome.log_entry() }).collect();
+
+    let selections = match select_sources(&job.config) {
+        Ok(selections) => { selections }
+        Err(err) => {
+            run_post_hooks(job, hook_destination, false, events);
+            bail!(err)
+        }
+    };
+    let plan = plan(&selections, &job.config);
+    let bytes_selected: u64 = plan.iter().map(|planned| { planned.file.size }).sum();
+    let _ = events.send(ProgressEvent::Planned { files: plan.len(), bytes: bytes_selected });
+    let required_space = job.budget.bytes.map(|bytes| { bytes.min(bytes_selected) }).unwrap_or(bytes_selected);
+
+    let mut destinations: Vec<DestinationReport> = vec![];
+    let mut written_destinations: Vec<Arc<dyn Destination>> = vec![];
+    for destination in &job.destinations {
+        let name = destination.name();
+        let check = check_destination(job, destination.as_ref(), required_space, false);
+        if let Some(err) = check.error {
+            info!(target: "general", "Destination {} not used: {}", name, err);
+            let _ = events.send(ProgressEvent::DestinationRefused { destination: name.clone(), reason: err.to_string() });
+            destinations.push(DestinationReport {
+                destination: name.clone(),
+                status: DestinationStatus::Refused(err),
+                files_copied: 0,
+                bytes_copied: 0,
+                files_skipped: 0,
+                duration: Duration::ZERO,
+                warnings: vec![],
+                report: None,
+            });
+            continue;
+        }
+        for warning in &check.warnings {
+            warn!(target: "general", "Destination {}: {}", name, warning);
+            let _ = events.send(ProgressEvent::Warning { destination: name.clone(), message: warning.clone() });
+        }
+
+        match recover(destination.as_ref(), job.resume) {
+            Ok(actions) => {
+                for action in actions {
+                    let _ = events.send(ProgressEvent::Warning { destination: name.clone(), message: action });
+                }
+            }
+            Err(err) => { error!(target: "general", "Error recovering the interrupted backup on {}: {}", name, err) }
+        }
+
+        let _ = events.send(ProgressEvent::DestinationStarted { destination: name.clone() });
+        let mut report = destination_backup(job, &plan, destination.as_ref(), &snapshot_id, started_at, &hook_log, events);
+        report.warnings = check.warnings;
+        match &report.status {
+            DestinationStatus::Completed => { info!(target: "general", "Destination {}: backup done", name) }
+            DestinationStatus::Partial => { info!(target: "general", "Destination {}: partial backup done, {} files skipped", name, report.files_skipped) }
+            DestinationStatus::Refused(_) | DestinationStatus::Failed(_) => { error!(target: "general", "{}", report.summary()) }
+        }
+        let written = report.is_written();
+        let _ = events.send(ProgressEvent::DestinationFinished { report: report.clone() });
+        destinations.push(report);
+
+        if written {
+            written_destinations.push(destination.clone());
+            if job.policy == DestinationPolicy::FirstAvailable {
+                break;
+            }
+        }
+    }
+
+    let post_hooks = run_post_hooks(job, written_destinations.first().map(|destination| { destination.as_ref() }).or(hook_destination),
+                                    !written_destinations.is_empty(), events);
+    if !post_hooks.is_empty() {
+        let post_hook_log: String = post_hooks.iter().map(|outcome| { outcome.log_entry() }).collect();
+        for destination in &written_destinations {
+            match destination.append(&metadata_path(Path::new(SNAPSHOT_DIR_NAME)).join(HOOK_LOG_NAME), post_hook_log.as_bytes()) {
+                Ok(_) => { debug!(target: "general", "Post-backup hook output saved into {}", destination.name()) }
+                Err(err) => { error!(target: "general", "Error saving the post-backup hook output into {}: {}", destination.name(), err) }
+            }
+        }
+    }
+    hooks.extend(post_hooks);
+
+    Ok(BackupReport {
+        snapshot_id,
+        started_at,
+        duration: start.elapsed(),
+        policy: job.policy,
+        files_selected: plan.len(),
+        bytes_selected,
+        destinations,
+        hooks,
+    })
+}
+
+/// Destination whose snapshot path the pre-backup hooks get, also given to the post-backup hooks if no
+/// destination is written. With the 'first-available' policy it is the first reachable and writable one:
+/// its free space can only be checked once the files are selected, after the hooks, so a destination
+/// without enough space may still be replaced by the next one. Otherwise it is the first destination.
+fn pre_hook_destination(job: &BackupJob) -> Option<&dyn Destination> {
+    let first_destination = job.destinations.first().map(|destination| { destination.as_ref() });
+    let has_hooks = !job.config.pre_backup_hooks.is_empty() || !job.config.post_backup_hooks.is_empty();
+    if job.policy != DestinationPolicy::FirstAvailable || !has_hooks {
+        return first_destination;
+    }
+    job.destinations.iter()
+        .map(|destination| { destination.as_ref() })
+        .find(|destination| { destination.probe().is_ok() })
+        .or(first_destination)
+}
+
+/// Runs the post-backup hooks of `job`, `destination` being the one whose snapshot path they get
+fn run_post_hooks(job: &BackupJob, destination: Option<&dyn Destination>, success: bool, events: &Sender<ProgressEvent>) -> Vec<HookOutcome> {
+    let outcomes = run_hooks(&job.config.post_backup_hooks, HookPhase::Post, &hook_environment(HookPhase::Post, destination, Some(success)));
+    send_hook_events(&outcomes, events);
+    outcomes
+}
+
+fn send_hook_events(outcomes: &[HookOutcome], events: &Sender<ProgressEvent>) {
+    for outcome in outcomes {
+        let _ = events.send(ProgressEvent::HookFinished { outcome: outcome.clone() });
+    }
+}
+
+/// Writes the snapshot into a single destination, within the budget of the job. `hook_log` is the output
+/// of the pre-backup hooks, saved into the snapshot together with the `SnapshotReport`.
+fn destination_backup(job: &BackupJob, plan: &[PlannedFile], destination: &dyn Destination, snapshot_id: &str, started_at: SystemTime, hook_log: &str, events: &Sender<ProgressEvent>) -> DestinationReport {
+    let start = Instant::now();
+    let cpu_start = ProcessTime::now();
+    let name = destination.name();
+    let snapshot_report = SnapshotReport::new(snapshot_id, destination, started_at, plan, &job.config);
+
+    debug!(target: "general", "Backing up {} files into {}", plan.len(), name);
+    let workers = match destination.local_path() {
+        Some(destination_path) => {
+            let workers = copy_workers(job.config.copy_workers, destination_path);
+            debug!(target: "general", "Copying into {} with {} workers ({:?} device)", name, workers, device_kind(destination_path));
+            workers
+        }
+        None => {
+            let workers = job.config.copy_workers.map(|workers| { workers as usize }).unwrap_or(REMOTE_COPY_WORKERS);
+            debug!(target: "general", "Copying into {} with {} workers (remote destination)", name, workers);
+            workers
+        }
+    };
+    let throttle = job.bandwidth_limit.map(Throttle::new);
+    let options = CopyOptions { budget: &job.budget, workers, throttle: throttle.as_ref(), metadata: &job.metadata, snapshot_id };
+    let manifest = match copy_snapshot(plan, destination, Path::new(STAGING_DIR_NAME), &options, events)
+        .and_then(|manifest| {
+            if !hook_log.is_empty() {
+                destination.write(&metadata_path(Path::new(STAGING_DIR_NAME)).join(HOOK_LOG_NAME), hook_log.as_bytes())?;
+            }
+            finalize(destination)?;
+            Ok(manifest)
+        }) {
+        Ok(manifest) => { manifest }
+        Err(err) => {
+            let mut snapshot_report = snapshot_report.with_times(start.elapsed(), cpu_start.elapsed());
+            if let Some(FailedFiles(failed)) = err.downcast_ref::<FailedFiles>() {
+                snapshot_report.errors = failed.iter().map(ReportedFile::from).collect();
+            }
+            return DestinationReport {
+                destination: name,
+                status: DestinationStatus::Failed(err.to_string()),
+                files_copied: 0,
+                bytes_copied: 0,
+                files_skipped: 0,
+                duration: start.elapsed(),
+                warnings: vec![],
+                report: Some(Box::new(snapshot_report)),
+            }
+        }
+    };
+
+    // The CPU time covers the copy, the verification and the finalization of the snapshot
+    let snapshot_report = snapshot_report.with_manifest(&manifest).with_times(start.elapsed(), cpu_start.elapsed());
+    match snapshot_report.write(destination, Path::new(SNAPSHOT_DIR_NAME)) {
+        Ok(_) => { debug!(target: "general", "Snapshot report written into {}", name) }
+        Err(err) => { error!(target: "general", "Error writing the snapshot report into {}: {}", name, err) }
+    }
+
+    DestinationReport {
+        destination: name,
+        status: if manifest.partial { DestinationStatus::Partial } else { DestinationStatus::Completed },
+        files_copied: manifest.files.len(),
+        bytes_copied: manifest.copied_bytes(),
+        files_skipped: manifest.skipped.len(),
+        duration: start.elapsed(),
+        warnings: vec![],
+        report: Some(Box::new(snapshot_report)),
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{error, info, trace};
+use errors::Error::FileTransferError;
+use settings::settings::BackupConfig;
+use crate::destination::Destination;
+use crate::journal::{Journal, JournalEntry};
+use crate::metadata::MetadataPolicy;
+use crate::manifest::{Manifest, ManifestEntry, SkippedEntry};
+use crate::plan::PlannedFile;
+use crate::progress::{ProgressEvent, ProgressTracker};
+use crate::snapshot_report::error_kind;
+use crate::staging::metadata_path;
+use crate::throttle::Throttle;
+
+/// Reason of the files skipped once the time budget is over
+pub const TIME_BUDGET_REASON: &str = "time budget exhausted";
+
+/// Reason of the files skipped because they exceed the byte budget
+pub const BYTE_BUDGET_REASON: &str = "byte budget exhausted";
+
+/// Limits of a single snapshot copy
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub time: Option<Duration>,
+    pub bytes: Option<u64>,
+}
+
+impl Budget {
+    pub fn from_config(config: &BackupConfig) -> anyhow::Result<Self> {
+        Ok(Budget {
+            time: config.time_budget_duration()?,
+            bytes: config.byte_budget_bytes()?,
+        })
+    }
+
+    /// Reason a file of `size` bytes has to be skipped, given the time spent and the bytes copied so far
+    pub fn skip_reason(&self, elapsed: Duration, copied_bytes: u64, size: u64) -> Option<&'static str> {
+        if self.time.map(|time| { elapsed >= time }).unwrap_or(false) {
+            Some(TIME_BUDGET_REASON)
+        } else if self.bytes.map(|bytes| { copied_bytes + size > bytes }).unwrap_or(false) {
+            Some(BYTE_BUDGET_REASON)
+        } else {
+            None
+        }
+    }
+}
+
+/// How a snapshot is copied into a destination
+#[derive(Debug, Clone, Copy)]
+pub struct CopyOptions<'a> {
+    pub budget: &'a Budget,
+
+    /// Number of files copied at the same time
+    pub workers: usize,
+
+    /// Bandwidth limit shared by the workers
+    pub throttle: Option<&'a Throttle>,
+
+    pub metadata: &'a MetadataPolicy,
+
+    /// Id of the backup, written into the manifest
+    pub snapshot_id: &'a str,
+}
+
+/// File whose copy failed
+#[derive(Debug, Clone)]
+pub struct FailedFile {
+    /// Path relative to the snapshot root
+    pub path: PathBuf,
+    pub source: String,
+    pub size: u64,
+
+    /// See `snapshot_report::error_kind`
+    pub kind: String,
+    pub message: String,
+}
+
+/// Context of the `FileTransferError` returned by `copy_snapshot` when copies failed, get it with `downcast_ref`
+#[derive(Debug, Clone)]
+pub struct FailedFiles(pub Vec<FailedFile>);
+
+impl Display for FailedFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.0.first() {
+            Some(failed) => { write!(f, "{}: {} files not copied, {:?}: {}", FileTransferError, self.0.len(), failed.path, failed.message) }
+            None => { write!(f, "{}", FileTransferError) }
+        }
+    }
+}
+
+/// Copies the planned files into `snapshot_path` of `destination`, in plan order, until the budget is exhausted.
+/// Once the time budget is over every remaining file is skipped, while a file exceeding the byte budget
+/// is skipped and the following, smaller ones are still tried. The manifest is written in the metadata directory
+/// of the snapshot, apart from the copied files.
+/// A progress event is sent to `events` for every copied or skipped file.
+///
+/// Files are handed out in plan order to a pool of `workers` threads (one worker copies sequentially),
+/// the manifest lists them in plan order whatever order they complete in. With a `throttle` the workers
+/// share its bandwidth limit. Symbolic links and metadata are handled following the `metadata` policy.
+///
+/// Every copied file is synced, verified and recorded in the journal of the snapshot, files the journal
+/// of an interrupted run already lists are kept without being copied again. If a copy fails no more files
+/// are handed out and `FileTransferError` is returned, with the `FailedFiles` as context.
+pub fn copy_snapshot(plan: &[PlannedFile], destination: &dyn Destination, snapshot_path: &Path, options: &CopyOptions, events: &Sender<ProgressEvent>) -> anyhow::Result<Manifest> {
+    let destination_name = destination.name();
+    destination.create_dir_all(&metadata_path(snapshot_path))?;
+    let start = Instant::now();
+    let mut copied_bytes = 0u64;
+    let journal = Journal::open(destination, snapshot_path)?;
+    let already_copied: Vec<bool> = plan.iter().map(|planned| { journal.is_done(planned) }).collect();
+
+    if !journal.is_empty() {
+        for stale_path in journal.stale_paths(plan) {
+            let _ = destination.remove_all(&snapshot_path.join(stale_path));
+        }
+        let resumed = already_copied.iter().filter(|done| { **done }).count();
+        info!(target: "general", "Resuming the snapshot in {:?} on {}: {} of {} files already copied", snapshot_path, destination_name, resumed, plan.len());
+        let _ = events.send(ProgressEvent::Warning {
+            destination: destination_name.clone(),
+            message: format!("resuming an interrupted snapshot, {} of {} files already copied", resumed, plan.len()),
+        });
+    }
+
+    let mut state = CopyState {
+        plan,
+        destination: &destination_name,
+        events,
+        journal,
+        tracker: ProgressTracker::new(&destination_name, plan.len(), plan.iter().map(|planned| { planned.file.size }).sum()),
+        files: vec![],
+        skipped: vec![],
+        failed: vec![],
+    };
+
+    let workers = options.workers.max(1);
+    let (job_sender, job_receiver) = mpsc::sync_channel::<usize>(workers);
+    let job_receiver = Mutex::new(job_receiver);
+    let (result_sender, result_receiver) = mpsc::channel::<(usize, anyhow::Result<u64>)>();
+    let mut created_dirs: HashSet<PathBuf> = HashSet::new();
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        for _ in 0..workers {
+            let job_receiver = &job_receiver;
+            let result_sender = result_sender.clone();
+            scope.spawn(move || {
+                loop {
+                    let index = match job_receiver.lock() {
+                        Ok(receiver) => { receiver.recv() }
+                        Err(_) => { break }
+                    };
+                    let Ok(index) = index else { break };
+                    let planned = &plan[index];
+                    let result = destination.put_file(&planned.file.path, &snapshot_path.join(&planned.snapshot_path), options.metadata, options.throttle);
+                    if result_sender.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(result_sender);
+
+        for (index, planned) in plan.iter().enumerate() {
+            while let Ok((index, result)) = result_receiver.try_recv() {
+                state.file_copied(index, result)?;
+            }
+            if !state.failed.is_empty() {
+                break;
+            }
+
+            if already_copied[index] {
+                trace!(target: "general", "Already copied {:?}", planned.file.path);
+                copied_bytes += planned.file.size;
+                state.file_in_snapshot(index);
+                continue;
+            }
+
+            if let Some(reason) = options.budget.skip_reason(start.elapsed(), copied_bytes, planned.file.size) {
+                state.file_skipped(index, reason);
+                continue;
+            }
+
+            let _ = events.send(ProgressEvent::Progress(state.tracker.progress(Some(&planned.snapshot_path))));
+            if let Some(parent_path) = snapshot_path.join(&planned.snapshot_path).parent() {
+                // Every directory is created once, on remote destinations each creation is a round trip
+                if !created_dirs.contains(parent_path) {
+                    destination.create_dir_all(parent_path)?;
+                    created_dirs.insert(parent_path.to_path_buf());
+                }
+            }
+            // The bytes are counted when the file is handed out, so the budget holds for the copies in flight
+            copied_bytes += planned.file.size;
+            if job_sender.send(index).is_err() {
+                break;
+            }
+        }
+        drop(job_sender);
+
+        for (index, result) in result_receiver.iter() {
+            state.file_copied(index, result)?;
+        }
+        Ok(())
+    })?;
+
+    state.journal.flush()?;
+    if !state.failed.is_empty() {
+        return Err(anyhow::Error::new(FileTransferError).context(FailedFiles(state.failed)));
+    }
+    let _ = events.send(ProgressEvent::Progress(state.tracker.progress(None)));
+
+    let mut manifest = Manifest::new();
+    manifest.snapshot_id = Some(options.snapshot_id.to_string());
+    state.files.sort_by_key(|(index, _)| { *index });
+    manifest.files = state.files.into_iter().map(|(_, entry)| { entry }).collect();
+    manifest.skipped = state.skipped;
+    manifest.partial = !manifest.skipped.is_empty();
+    manifest.complete = true;
+    manifest.completed_at = Some(chrono::Local::now().to_rfc3339());
+    if manifest.partial {
+        info!(target: "general", "Partial snapshot in {:?} on {}: {} files copied, {} skipped", snapshot_path, destination_name, manifest.files.len(), manifest.skipped.len());
+    }
+    manifest.write(destination, snapshot_path)?;
+    Ok(manifest)
+}
+
+/// Outcome of the files processed so far, updated by the thread handing out the copies
+struct CopyState<'a> {
+    plan: &'a [PlannedFile],
+
+    destination: &'a str,
+
+    events: &'a Sender<ProgressEvent>,
+
+    journal: Journal<'a>,
+
+    tracker: ProgressTracker,
+
+    /// Copied files with their position in the plan
+    files: Vec<(usize, ManifestEntry)>,
+
+    skipped: Vec<SkippedEntry>,
+
+    /// Copies that failed, once there is one no more files are handed out
+    failed: Vec<FailedFile>,
+}
+
+impl CopyState<'_> {
+    fn file_copied(&mut self, index: usize, result: anyhow::Result<u64>) -> anyhow::Result<()> {
+        let planned = &self.plan[index];
+        if let Err(err) = result {
+            error!(target: "general", "Error copying {:?}: {}", planned.file.path, err);
+            self.failed.push(FailedFile {
+                path: planned.snapshot_path.clone(),
+                source: planned.source_name.clone(),
+                size: planned.file.size,
+                kind: error_kind(&err),
+                message: err.to_string(),
+            });
+            return Ok(());
+        }
+
+        trace!(target: "general", "Copied {:?}", planned.file.path);
+        self.journal.append(JournalEntry::from(planned))?;
+        self.file_in_snapshot(index);
+        Ok(())
+    }
+
+    /// Accounts for a file copied by this run or by the interrupted one it resumes
+    fn file_in_snapshot(&mut self, index: usize) {
+        let planned = &self.plan[index];
+        self.tracker.file_done(planned.file.size, true);
+        self.files.push((index, ManifestEntry {
+            path: planned.snapshot_path.clone(),
+            source: planned.source_name.clone(),
+            size: planned.file.size,
+        }));
+        let _ = self.events.send(ProgressEvent::FileCopied {
+            destination: self.destination.to_string(),
+            path: planned.snapshot_path.clone(),
+            size: planned.file.size,
+        });
+    }
+
+    fn file_skipped(&mut self, index: usize, reason: &str) {
+        let planned = &self.plan[index];
+        self.skipped.push(SkippedEntry {
+            path: planned.snapshot_path.clone(),
+            source: planned.source_name.clone(),
+            size: planned.file.size,
+            reason: reason.to_string(),
+        });
+        self.tracker.file_done(planned.file.size, false);
+        let _ = self.events.send(ProgressEvent::FileSkipped {
+            destination: self.destination.to_string(),
+            path: planned.snapshot_path.clone(),
+            size: planned.file.size,
+            reason: reason.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use errors::Error;
+    use tempfile::TempDir;
+    use crate::destination::LocalDestination;
+    use crate::manifest::MANIFEST_FILE_NAME;
+    use crate::selection::SelectedFile;
+    use super::*;
+
+    /// Local destination whose copies of some files are delayed or fail, recording the files handed out
+    /// to it and the order the copies complete in
+    #[derive(Debug)]
+    struct StubDestination {
+        local: LocalDestination,
+        delays: HashMap<String, Duration>,
+        failing: HashSet<String>,
+        started: Mutex<Vec<String>>,
+        completed: Mutex<Vec<String>>,
+    }
+
+    impl StubDestination {
+        fn new(root: &Path, delays: Vec<(String, u64)>, failing: &[&str]) -> Self {
+            StubDestination {
+                local: LocalDestination::new(root.to_path_buf()),
+                delays: delays.into_iter().map(|(name, millis)| { (name, Duration::from_millis(millis)) }).collect(),
+                failing: failing.iter().map(|name| { name.to_string() }).collect(),
+                started: Mutex::new(vec![]),
+                completed: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    impl Destination for StubDestination {
+        fn name(&self) -> String {
+            String::from("stub")
+        }
+
+        fn probe(&self) -> Result<(), Error> {
+            self.local.probe()
+        }
+
+        fn probe_read_only(&self) -> Result<(), Error> {
+            self.local.probe_read_only()
+        }
+
+        fn available_space(&self) -> Option<u64> {
+            None
+        }
+
+        fn file_size(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+            self.local.file_size(path)
+        }
+
+        fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+            self.local.exists(path)
+        }
+
+        fn list_files(&self, path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>> {
+            self.local.list_files(path)
+        }
+
+        fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+            self.local.create_dir_all(path)
+        }
+
+        fn put_file(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            self.started.lock().unwrap().push(name.clone());
+            thread::sleep(self.delays.get(&name).copied().unwrap_or_default());
+            if self.failing.contains(&name) {
+                anyhow::bail!("connection reset while writing {}", name);
+            }
+            let written = self.local.put_file(source, path, policy, throttle)?;
+            self.completed.lock().unwrap().push(name);
+            Ok(written)
+        }
+
+        fn get_file(&self, path: &Path, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<u64> {
+            self.local.get_file(path, target, policy)
+        }
+
+        fn read(&self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+            self.local.read(path)
+        }
+
+        fn write(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+            self.local.write(path, content)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+            self.local.rename(from, to)
+        }
+
+        fn remove_all(&self, path: &Path) -> anyhow::Result<()> {
+            self.local.remove_all(path)
+        }
+    }
+
+    /// Writes `size` bytes into the source file `name` and plans it under the source "source"
+    fn planned(source: &TempDir, name: &str, size: usize) -> PlannedFile {
+        let path = source.path().join(name);
+        fs::write(&path, vec![1u8; size]).unwrap();
+        let metadata = fs::metadata(&path).unwrap();
+        PlannedFile {
+            file: SelectedFile { path, relative_path: PathBuf::from(name), size: metadata.len(), modified: metadata.modified().unwrap() },
+            source_name: String::from("source"),
+            snapshot_path: Path::new("source").join(name),
+        }
+    }
+
+    fn copy(plan: &[PlannedFile], destination: &dyn Destination, budget: &Budget, workers: usize) -> anyhow::Result<Manifest> {
+        let metadata = MetadataPolicy::default();
+        let options = CopyOptions { budget, workers, throttle: None, metadata: &metadata, snapshot_id: "test" };
+        let (sender, _receiver) = mpsc::channel();
+        copy_snapshot(plan, destination, Path::new("snapshot"), &options, &sender)
+    }
+
+    fn names(paths: impl Iterator<Item = PathBuf>) -> Vec<String> {
+        paths.map(|path| { path.file_name().unwrap().to_string_lossy().to_string() }).collect()
+    }
+
+    #[test]
+    fn time_budget_skips_from_its_end_on() {
+        let budget = Budget { time: Some(Duration::from_secs(10)), bytes: None };
+
+        assert_eq!(budget.skip_reason(Duration::from_secs(9), 0, u64::MAX), None);
+        assert_eq!(budget.skip_reason(Duration::from_secs(10), 0, 0), Some(TIME_BUDGET_REASON));
+    }
+
+    #[test]
+    fn byte_budget_skips_the_files_that_do_not_fit() {
+        let budget = Budget { time: None, bytes: Some(100) };
+
+        assert_eq!(budget.skip_reason(Duration::ZERO, 60, 40), None);
+        assert_eq!(budget.skip_reason(Duration::ZERO, 60, 41), Some(BYTE_BUDGET_REASON));
+        assert_eq!(budget.skip_reason(Duration::ZERO, 60, 0), None);
+    }
+
+    #[test]
+    fn time_budget_is_checked_before_the_byte_budget() {
+        let budget = Budget { time: Some(Duration::from_secs(1)), bytes: Some(100) };
+
+        assert_eq!(budget.skip_reason(Duration::from_secs(2), 90, 20), Some(TIME_BUDGET_REASON));
+    }
+
+    #[test]
+    fn exhausted_time_budget_skips_every_remaining_file() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let plan = vec![planned(&source, "a", 4), planned(&source, "b", 5)];
+
+        let manifest = copy(&plan, &LocalDestination::new(target.path().to_path_buf()), &Budget { time: Some(Duration::ZERO), bytes: None }, 2).unwrap();
+
+        assert!(manifest.files.is_empty());
+        assert_eq!(names(manifest.skipped.iter().map(|skipped| { skipped.path.clone() })), ["a", "b"]);
+        assert!(manifest.skipped.iter().all(|skipped| { skipped.reason == TIME_BUDGET_REASON }));
+        assert!(manifest.partial);
+    }
+
+    #[test]
+    fn oversized_file_is_skipped_and_the_following_ones_are_copied() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let plan = vec![planned(&source, "small", 4), planned(&source, "large", 20), planned(&source, "smaller", 5)];
+
+        let manifest = copy(&plan, &LocalDestination::new(target.path().to_path_buf()), &Budget { time: None, bytes: Some(10) }, 1).unwrap();
+
+        assert_eq!(names(manifest.files.iter().map(|entry| { entry.path.clone() })), ["small", "smaller"]);
+        assert_eq!(names(manifest.skipped.iter().map(|skipped| { skipped.path.clone() })), ["large"]);
+        assert_eq!(manifest.skipped[0].reason, BYTE_BUDGET_REASON);
+        assert!(!target.path().join("snapshot/source/large").exists());
+        assert_eq!(fs::read(target.path().join("snapshot/source/smaller")).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn manifest_is_kept_apart_from_a_source_file_of_the_same_name() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let mut file = planned(&source, MANIFEST_FILE_NAME, 4);
+        file.snapshot_path = PathBuf::from(MANIFEST_FILE_NAME);
+
+        copy(&[file], &LocalDestination::new(target.path().to_path_buf()), &Budget::default(), 1).unwrap();
+
+        assert_eq!(fs::read(target.path().join("snapshot").join(MANIFEST_FILE_NAME)).unwrap(), vec![1u8; 4]);
+        let manifest = Manifest::read(&LocalDestination::new(target.path().to_path_buf()), Path::new("snapshot")).unwrap();
+        assert_eq!(manifest.files[0].path, Path::new(MANIFEST_FILE_NAME));
+    }
+
+    #[test]
+    fn source_file_named_like_a_journal_segment_is_not_removed_with_the_journal() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let destination = LocalDestination::new(target.path().to_path_buf());
+        let mut file = planned(&source, "journal-0.jsonl", 4);
+        file.snapshot_path = PathBuf::from("journal-0.jsonl");
+
+        copy(&[file], &destination, &Budget::default(), 1).unwrap();
+        assert_eq!(Journal::read(&destination, Path::new("snapshot")).unwrap().len(), 1);
+        Journal::remove(&destination, Path::new("snapshot")).unwrap();
+
+        assert_eq!(fs::read(target.path().join("snapshot/journal-0.jsonl")).unwrap(), vec![1u8; 4]);
+        assert!(Journal::read(&destination, Path::new("snapshot")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn manifest_follows_the_plan_whatever_order_the_workers_complete_in() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let plan: Vec<PlannedFile> = ["a", "b", "c", "d", "e", "f"].iter().map(|name| { planned(&source, name, 3) }).collect();
+        let destination = StubDestination::new(target.path(), vec![(String::from("a"), 300), (String::from("b"), 150)], &[]);
+
+        let manifest = copy(&plan, &destination, &Budget::default(), 3).unwrap();
+
+        let completed = destination.completed.lock().unwrap().clone();
+        assert_ne!(completed, ["a", "b", "c", "d", "e", "f"], "the copies completed in plan order, the test proves nothing");
+        assert_eq!(names(manifest.files.iter().map(|entry| { entry.path.clone() })), ["a", "b", "c", "d", "e", "f"]);
+        assert_eq!(Journal::read(&destination, Path::new("snapshot")).unwrap().len(), 6);
+    }
+
+    #[test]
+    fn first_failure_stops_handing_out_files() {
+        let source = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        let plan: Vec<PlannedFile> = (0..20).map(|index| { planned(&source, &format!("file-{:02}", index), 3) }).collect();
+        let delays: Vec<(String, u64)> = (0..20).filter(|index| { *index != 2 }).map(|index| { (format!("file-{:02}", index), 50) }).collect();
+        let destination = StubDestination::new(target.path(), delays, &["file-02"]);
+
+        let err = copy(&plan, &destination, &Budget::default(), 2).unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<Error>(), Some(FileTransferError)));
+        let failed = err.downcast_ref::<FailedFiles>().unwrap();
+        assert_eq!(failed.0.len(), 1);
+        assert_eq!(failed.0[0].path, Path::new("source").join("file-02"));
+        assert!(failed.0[0].message.contains("connection reset"));
+
+        // Only the files already handed out when the failure came back are copied
+        let started = destination.started.lock().unwrap().len();
+        assert!(started < plan.len(), "{} of {} files handed out", started, plan.len());
+        assert!(!target.path().join("snapshot/source/file-19").exists());
+        assert!(!metadata_path(&target.path().join("snapshot")).join(MANIFEST_FILE_NAME).exists());
+    }
+}
@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::bail;
+use log::{debug, info};
+use errors::Error;
+use errors::Error::{DestinationNotFoundError, DestinationNotWritableError};
+use settings::settings::{BackupDestination, RemoteDestination};
+use crate::metadata::{copy_entry, MetadataPolicy};
+use crate::preflight::{existing_ancestor, is_destination_available, is_dir_writable};
+use crate::removable::{find_volume, target_dir};
+use crate::s3::S3;
+use crate::sftp::Sftp;
+use crate::throttle::Throttle;
+use crate::webdav::WebDav;
+
+/// A place snapshots are written to. Paths are relative to the root of the destination.
+///
+/// The engine only goes through this trait to write, read back and rename snapshots, so that local
+/// directories and remote servers share the staging, journal and manifest logic.
+pub trait Destination: Debug + Send + Sync {
+    /// Name in logs, reports and commands (see `BackupDestination::name`)
+    fn name(&self) -> String;
+
+    /// Directory of a local destination. Checks that only make sense on a local file system
+    /// (same device as a source, kind of device) are skipped for the other destinations.
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Checks that the destination is reachable and writable
+    fn probe(&self) -> Result<(), Error>;
+
+    /// Checks like `probe` without writing anything, for the dry run. Writability is only checked where it
+    /// can be read without a write, e.g. from the permissions of a local directory.
+    fn probe_read_only(&self) -> Result<(), Error>;
+
+    /// Free space, `None` if it can not be read
+    fn available_space(&self) -> Option<u64>;
+
+    /// False for destinations without a storage limit to check, e.g. object storage buckets
+    fn has_capacity(&self) -> bool {
+        true
+    }
+
+    /// Size of the file (or of the link itself) at `path`, `None` if there is none
+    fn file_size(&self, path: &Path) -> anyhow::Result<Option<u64>>;
+
+    /// True if a file or a directory exists at `path`
+    fn exists(&self, path: &Path) -> anyhow::Result<bool>;
+
+    /// Sizes of the files (and links) in the directory tree at `path`, by path relative to it, empty if there
+    /// is nothing at `path`. A whole staging snapshot is checked with one listing instead of a request per file.
+    fn list_files(&self, path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>>;
+
+    /// True if `path` is a symbolic link kept by the 'preserve' policy
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+
+    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()>;
+
+    /// Writes the local file or link `source` to `path`, replacing it, with the metadata kept by `policy`
+    /// the destination supports. Returns the bytes written.
+    fn put_file(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64>;
+
+    /// Copies `path` to the local `target`, with the metadata kept by `policy`. Returns the bytes read.
+    fn get_file(&self, path: &Path, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<u64>;
+
+    /// Content of the file at `path`, `None` if there is none
+    fn read(&self, path: &Path) -> anyhow::Result<Option<Vec<u8>>>;
+
+    fn write(&self, path: &Path, content: &[u8]) -> anyhow::Result<()>;
+
+    /// Appends `content` to the file at `path`, creating it if needed. By default the file is rewritten.
+    fn append(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        let mut current = self.read(path)?.unwrap_or_default();
+        current.extend_from_slice(content);
+        self.write(path, &current)
+    }
+
+    /// Renames a file or a directory, `to` must not exist
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()>;
+
+    /// Removes a file or a whole directory tree, nothing happens if `path` does not exist
+    fn remove_all(&self, path: &Path) -> anyhow::Result<()>;
+
+    /// Removes what the destination keeps aside to resume interrupted copies, e.g. partial uploads on the
+    /// server. Called once the staging snapshot is finalized or discarded and nothing can resume them anymore.
+    fn remove_partial_uploads(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Files copied at the same time into a remote destination when `copy_workers` is not set
+pub const REMOTE_COPY_WORKERS: usize = 4;
+
+/// Opens the configured destination
+pub fn open(destination: &BackupDestination) -> Arc<dyn Destination> {
+    match destination {
+        BackupDestination::Path(path) => { Arc::new(LocalDestination::new(PathBuf::from(path))) }
+        BackupDestination::Remote(RemoteDestination::Sftp(sftp)) => { Arc::new(Sftp::new(sftp.clone())) }
+        BackupDestination::Remote(RemoteDestination::S3(s3)) => { Arc::new(S3::new(s3.clone())) }
+        BackupDestination::Remote(RemoteDestination::WebDav(webdav)) => { Arc::new(WebDav::new(webdav.clone())) }
+        BackupDestination::Remote(RemoteDestination::Removable(removable)) => {
+            match find_volume(removable) {
+                Some(volume) => {
+                    info!(target: "general", "Removable drive {} chosen for {}", volume.description(), destination.name());
+                    let name = format!("{} ({})", destination.name(), volume.description());
+                    Arc::new(LocalDestination::on_volume(target_dir(&volume, removable), name))
+                }
+                None => {
+                    info!(target: "general", "No removable drive plugged in for {}", destination.name());
+                    Arc::new(UnavailableDestination { name: destination.name() })
+                }
+            }
+        }
+    }
+}
+
+/// Path of a file named in the configuration, a leading '~/' standing for the home directory
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(relative_path), Some(home)) => { Path::new(&home).join(relative_path) }
+        _ => { PathBuf::from(path) }
+    }
+}
+
+/// Directory on a local file system: an internal disk, a mounted drive or a network share
+#[derive(Debug, Clone)]
+pub struct LocalDestination {
+    root: PathBuf,
+
+    /// Name replacing the path in reports, e.g. for a removable drive chosen when the backup starts
+    name: Option<String>,
+
+    /// A missing root is created by the first snapshot written, e.g. the directory of a removable drive
+    create_root: bool,
+}
+
+impl LocalDestination {
+    pub fn new(root: PathBuf) -> Self {
+        LocalDestination { root, name: None, create_root: false }
+    }
+
+    pub fn with_name(root: PathBuf, name: String) -> Self {
+        LocalDestination { root, name: Some(name), create_root: false }
+    }
+
+    /// Directory `root` of a removable drive, created only once a backup writes into it, so that a dry run
+    /// or a preview leaves the drive untouched
+    pub fn on_volume(root: PathBuf, name: String) -> Self {
+        LocalDestination { root, name: Some(name), create_root: true }
+    }
+
+    /// Directory the checks run on: the root, or its closest existing parent if the root is created later
+    fn checked_dir(&self) -> Option<&Path> {
+        match self.create_root {
+            true => { existing_ancestor(&self.root) }
+            false => { Some(&self.root) }
+        }
+    }
+}
+
+impl Destination for LocalDestination {
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or(self.root.to_string_lossy().to_string())
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+
+    /// The destination must be an existing directory where a file can be created. A root created later
+    /// needs its closest existing parent to be writable, checked without writing into it.
+    fn probe(&self) -> Result<(), Error> {
+        if self.create_root && !self.root.exists() {
+            self.probe_read_only()
+        } else if !self.root.is_dir() {
+            Err(DestinationNotFoundError(self.name()))
+        } else if !is_destination_available(&self.root) {
+            Err(DestinationNotWritableError(self.name()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writability is read from the permissions and the mount flags of the directory
+    fn probe_read_only(&self) -> Result<(), Error> {
+        match self.checked_dir() {
+            Some(dir) if dir.is_dir() && is_dir_writable(dir) => { Ok(()) }
+            Some(dir) if dir.is_dir() => { Err(DestinationNotWritableError(self.name())) }
+            _ => { Err(DestinationNotFoundError(self.name())) }
+        }
+    }
+
+    fn available_space(&self) -> Option<u64> {
+        let dir = self.checked_dir()?;
+        match fs4::available_space(dir) {
+            Ok(available_space) => { Some(available_space) }
+            Err(err) => { debug!(target: "general", "Error reading free space of {:?}: {}", dir, err); None }
+        }
+    }
+
+    fn file_size(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+        match fs::symlink_metadata(self.root.join(path)) {
+            Ok(metadata) => { Ok(Some(metadata.len())) }
+            Err(err) if err.kind() == ErrorKind::NotFound => { Ok(None) }
+            Err(err) => { Err(err.into()) }
+        }
+    }
+
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(fs::symlink_metadata(self.root.join(path)).is_ok())
+    }
+
+    fn list_files(&self, path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>> {
+        let root = self.root.join(path);
+        let mut files = HashMap::new();
+        let mut pending: Vec<PathBuf> = vec![PathBuf::new()];
+        while let Some(dir) = pending.pop() {
+            let entries = match fs::read_dir(root.join(&dir)) {
+                Ok(entries) => { entries }
+                Err(err) if err.kind() == ErrorKind::NotFound => { continue }
+                Err(err) => { return Err(err.into()) }
+            };
+            for entry in entries {
+                let entry = entry?;
+                let metadata = fs::symlink_metadata(entry.path())?;
+                if metadata.is_dir() {
+                    pending.push(dir.join(entry.file_name()));
+                } else {
+                    files.insert(dir.join(entry.file_name()), metadata.len());
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        fs::symlink_metadata(self.root.join(path)).map(|metadata| { metadata.file_type().is_symlink() }).unwrap_or(false)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+        Ok(fs::create_dir_all(self.root.join(path))?)
+    }
+
+    fn put_file(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+        copy_entry(source, &self.root.join(path), policy, throttle)
+    }
+
+    fn get_file(&self, path: &Path, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<u64> {
+        copy_entry(&self.root.join(path), target, policy, None)
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        match fs::read(self.root.join(path)) {
+            Ok(content) => { Ok(Some(content)) }
+            Err(err) if err.kind() == ErrorKind::NotFound => { Ok(None) }
+            Err(err) => { Err(err.into()) }
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        Ok(fs::write(self.root.join(path), content)?)
+    }
+
+    /// Appends and syncs, so that the content is on disk when the call returns
+    fn append(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(self.root.join(path))?;
+        file.write_all(content)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        Ok(fs::rename(self.root.join(from), self.root.join(to))?)
+    }
+
+    fn remove_all(&self, path: &Path) -> anyhow::Result<()> {
+        let path = self.root.join(path);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => { Ok(fs::remove_dir_all(path)?) }
+            Ok(_) => { Ok(fs::remove_file(path)?) }
+            Err(err) if err.kind() == ErrorKind::NotFound => { Ok(()) }
+            Err(err) => { Err(err.into()) }
+        }
+    }
+}
+
+/// Destination that could not be opened, e.g. a removable drive that is not plugged in.
+/// It is refused by the pre-flight checks and never holds a snapshot.
+#[derive(Debug, Clone)]
+pub struct UnavailableDestination {
+    name: String,
+}
+
+impl Destination for UnavailableDestination {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn probe(&self) -> Result<(), Error> {
+        Err(DestinationNotFoundError(self.name()))
+    }
+
+    fn probe_read_only(&self) -> Result<(), Error> {
+        self.probe()
+    }
+
+    fn available_space(&self) -> Option<u64> {
+        None
+    }
+
+    fn file_size(&self, _path: &Path) -> anyhow::Result<Option<u64>> {
+        Ok(None)
+    }
+
+    fn exists(&self, _path: &Path) -> anyhow::Result<bool> {
+        Ok(false)
+    }
+
+    fn list_files(&self, _path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>> {
+        Ok(HashMap::new())
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> anyhow::Result<()> {
+        bail!(DestinationNotFoundError(self.name()))
+    }
+
+    fn put_file(&self, _source: &Path, _path: &Path, _policy: &MetadataPolicy, _throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+        bail!(DestinationNotFoundError(self.name()))
+    }
+
+    fn get_file(&self, _path: &Path, _target: &Path, _policy: &MetadataPolicy) -> anyhow::Result<u64> {
+        bail!(DestinationNotFoundError(self.name()))
+    }
+
+    fn read(&self, _path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn write(&self, _path: &Path, _content: &[u8]) -> anyhow::Result<()> {
+        bail!(DestinationNotFoundError(self.name()))
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> anyhow::Result<()> {
+        bail!(DestinationNotFoundError(self.name()))
+    }
+
+    fn remove_all(&self, _path: &Path) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::Path;
+#[cfg(target_os = "linux")] use std::path::PathBuf;
+
+/// Maximum number of copy workers chosen automatically
+pub const MAX_AUTO_WORKERS: usize = 8;
+
+/// Kind of storage a destination is on, as far as the copy concurrency is concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceKind {
+    /// SSD, NVMe or anything random access
+    Solid,
+
+    /// Spinning disk
+    Rotational,
+
+    /// Drive attached to a USB 2 (or slower) port
+    Usb2,
+
+    /// Not detected, treated as a solid device
+    Unknown,
+}
+
+impl DeviceKind {
+    /// Concurrent reads and writes slow down spinning disks and saturate USB 2 links
+    pub fn is_sequential(&self) -> bool {
+        matches!(self, DeviceKind::Rotational | DeviceKind::Usb2)
+    }
+}
+
+/// Number of copy workers for `destination`: the configured one if any, otherwise one per CPU
+/// (at most `MAX_AUTO_WORKERS`), or one on devices that must be written sequentially
+pub fn copy_workers(configured: Option<u32>, destination: &Path) -> usize {
+    match configured {
+        Some(workers) => { workers.max(1) as usize }
+        None if device_kind(destination).is_sequential() => { 1 }
+        None => {
+            std::thread::available_parallelism().map(|cpus| { cpus.get() }).unwrap_or(1).min(MAX_AUTO_WORKERS)
+        }
+    }
+}
+
+/// Kind of the disk holding `path`, detected through sysfs
+#[cfg(target_os = "linux")]
+pub fn device_kind(path: &Path) -> DeviceKind {
+    let disk_path = match disk_sysfs_path(path) {
+        Some(disk_path) => { disk_path }
+        None => { return DeviceKind::Unknown }
+    };
+
+    // The USB device the disk is attached to is an ancestor exposing the link speed in Mb/s
+    let usb_speed = disk_path.ancestors()
+        .find(|ancestor| { ancestor.join("speed").exists() && ancestor.join("idVendor").exists() })
+        .and_then(|usb_device| { fs::read_to_string(usb_device.join("speed")).ok() })
+        .and_then(|speed| { speed.trim().parse::<f64>().ok() });
+    if usb_speed.map(|speed| { speed <= 480.0 }).unwrap_or(false) {
+        return DeviceKind::Usb2;
+    }
+
+    match fs::read_to_string(disk_path.join("queue").join("rotational")).map(|rotational| { rotational.trim().to_string() }) {
+        Ok(rotational) if rotational == "1" => { DeviceKind::Rotational }
+        Ok(_) => { DeviceKind::Solid }
+        Err(_) => { DeviceKind::Unknown }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn device_kind(_path: &Path) -> DeviceKind {
+    DeviceKind::Unknown
+}
+
+/// Identifier of the device holding `path`: the physical disk (Linux), the file system (other Unix)
+/// or the volume (Windows)
+#[cfg(target_family = "unix")]
+pub(crate) fn device_id(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let dev = fs::metadata(path).ok()?.dev();
+    Some(physical_device(dev).unwrap_or_else(|| { dev.to_string() }))
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn device_id(path: &Path) -> Option<String> {
+    use std::path::Component;
+    match fs::canonicalize(path).ok()?.components().next() {
+        Some(Component::Prefix(prefix)) => { Some(prefix.as_os_str().to_string_lossy().to_uppercase()) }
+        _ => { None }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn physical_device(dev: u64) -> Option<String> {
+    Some(dev_sysfs_path(dev)?.to_string_lossy().to_string())
+}
+
+#[cfg(all(target_family = "unix", not(target_os = "linux")))]
+fn physical_device(_dev: u64) -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn disk_sysfs_path(path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+    dev_sysfs_path(fs::metadata(path).ok()?.dev())
+}
+
+/// Disk holding the block device `dev`: partitions are resolved to their parent disk through sysfs
+#[cfg(target_os = "linux")]
+fn dev_sysfs_path(dev: u64) -> Option<PathBuf> {
+    let major = ((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff);
+    let minor = ((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff);
+    let device_path = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)).ok()?;
+    if device_path.join("partition").exists() { Some(device_path.parent()?.to_path_buf()) } else { Some(device_path) }
+}
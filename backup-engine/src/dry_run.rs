@@ -0,0 +1,81 @@
+use std::time::Duration;
+use crate::job::{BackupJob, DestinationPolicy};
+use crate::plan::{plan, PlannedFile};
+use crate::preflight::{check_destination, DestinationCheck};
+use crate::selection::select_sources;
+
+/// Copy throughput assumed to estimate the duration of a backup
+pub const ESTIMATED_BYTES_PER_SEC: u64 = 50 * 1024 * 1024;
+
+/// Fixed cost assumed for every copied file (open, create, metadata)
+pub const ESTIMATED_FILE_OVERHEAD: Duration = Duration::from_millis(1);
+
+/// What a backup would do under the current configuration, nothing is written
+#[derive(Debug, Clone)]
+pub struct DryRun {
+    /// Files that would be copied, in copy order
+    pub copied: Vec<PlannedFile>,
+
+    /// Files that would be left out by the budget, with the reason
+    pub skipped: Vec<(PlannedFile, String)>,
+
+    /// Pre-flight checks, one per configured destination
+    pub checks: Vec<DestinationCheck>,
+}
+
+impl DryRun {
+    pub fn total_size(&self) -> u64 {
+        self.copied.iter().map(|planned| { planned.file.size }).sum()
+    }
+
+    pub fn estimated_duration(&self) -> Duration {
+        self.copied.iter().map(|planned| { estimate_duration(planned.file.size) }).sum()
+    }
+
+    /// A backup would succeed if at least one destination is used and every used destination passed the checks
+    pub fn is_ready(&self) -> bool {
+        let mut used = self.checks.iter().filter(|check| { check.used }).peekable();
+        used.peek().is_some() && used.all(|check| { check.is_ok() })
+    }
+}
+
+/// Estimated time needed to copy a single file of `size` bytes
+pub fn estimate_duration(size: u64) -> Duration {
+    ESTIMATED_FILE_OVERHEAD + Duration::from_secs_f64(size as f64 / ESTIMATED_BYTES_PER_SEC as f64)
+}
+
+/// Runs the same selection, ordering and budget logic of a real backup without writing anything.
+/// The time budget is applied on the estimated copy duration.
+pub fn dry_run(job: &BackupJob) -> anyhow::Result<DryRun> {
+    let selections = select_sources(&job.config)?;
+    let mut copied: Vec<PlannedFile> = vec![];
+    let mut skipped: Vec<(PlannedFile, String)> = vec![];
+    let mut elapsed = Duration::ZERO;
+    let mut copied_bytes = 0u64;
+
+    for planned in plan(&selections, &job.config) {
+        match job.budget.skip_reason(elapsed, copied_bytes, planned.file.size) {
+            Some(reason) => { skipped.push((planned, reason.to_string())) }
+            None => {
+                elapsed += estimate_duration(planned.file.size);
+                copied_bytes += planned.file.size;
+                copied.push(planned);
+            }
+        }
+    }
+
+    let mut checks: Vec<DestinationCheck> = job.destinations.iter()
+        .map(|destination| { check_destination(job, destination.as_ref(), copied_bytes, true) })
+        .collect();
+
+    match job.policy {
+        DestinationPolicy::FirstAvailable => {
+            if let Some(check) = checks.iter_mut().find(|check| { check.is_ok() }) {
+                check.used = true;
+            }
+        }
+        DestinationPolicy::All => { checks.iter_mut().for_each(|check| { check.used = true }) }
+    }
+
+    Ok(DryRun { copied, skipped, checks })
+}
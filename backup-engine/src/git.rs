@@ -0,0 +1,189 @@
+use std::fs;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use anyhow::bail;
+use ignore::WalkBuilder;
+use log::{debug, error, info};
+use tempfile::TempDir;
+use errors::Error::GitCommandError;
+use settings::settings::{BackupConfig, BackupSource};
+use crate::selection::{SelectedFile, SourceRules};
+
+/// Commits of the local branches and of HEAD not on any remote branch
+pub const BUNDLE_FILE_NAME: &str = "unpushed.bundle";
+
+/// Staged and unstaged changes of the tracked files, against HEAD
+pub const DIFF_FILE_NAME: &str = "working-tree.diff";
+
+/// Untracked files not ignored by git, copied as they are
+pub const UNTRACKED_DIR_NAME: &str = "untracked";
+
+/// One patch per stash entry, `stash-0.diff` being the most recent
+pub const STASH_DIR_NAME: &str = "stash";
+
+/// Branch, remotes and stash list of the repository
+pub const STATUS_FILE_NAME: &str = "status.txt";
+
+/// Selects the uncommitted work of the git repositories found under `source`: for each repository a bundle
+/// of the unpushed commits, the working tree diff, the untracked files and the stash entries, in a directory
+/// named after the path of the repository relative to the source. The bundles, diffs and patches are
+/// generated into the returned temporary directory, which must outlive the copy.
+///
+/// Directories ignored by git and the exclude rules of the source are not searched, the exclude rules and the
+/// size and modification time filters also apply to the untracked files. An error saving a repository is
+/// logged and the repository left out.
+pub fn select_git_work(source: &BackupSource, config: &BackupConfig) -> anyhow::Result<(Vec<SelectedFile>, TempDir)> {
+    match Command::new("git").arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status() {
+        Ok(status) if status.success() => {}
+        _ => { error!(target: "general", "The git command is not available"); bail!(GitCommandError(String::from("git is not installed or not in the PATH"))) }
+    }
+
+    let root = PathBuf::from(&source.path);
+    let rules = Arc::new(SourceRules::new(source, config)?);
+    let generated = tempfile::Builder::new().prefix("emergency-backup-git-").tempdir()?;
+    let mut selected: Vec<SelectedFile> = vec![];
+    for repository in find_repositories(&root, rules.clone()) {
+        let relative_path = repository.strip_prefix(&root).map(Path::to_path_buf).unwrap_or_default();
+        match save_repository(&repository, &relative_path, generated.path(), &rules) {
+            Ok(files) => {
+                debug!(target: "general", "{} files saved from the git repository {:?}", files.len(), repository);
+                selected.extend(files);
+            }
+            Err(err) => { error!(target: "general", "Error saving the git repository {:?}: {}", repository, err) }
+        }
+    }
+    info!(target: "general", "{} files of uncommitted work selected from {:?}", selected.len(), source.path);
+    Ok((selected, generated))
+}
+
+/// Directories under `root` holding a `.git` directory or file (worktrees and submodules), `root` included
+fn find_repositories(root: &Path, rules: Arc<SourceRules>) -> Vec<PathBuf> {
+    let walker_root = root.to_path_buf();
+    let mut walker = WalkBuilder::new(root);
+    walker.standard_filters(false)
+        .git_ignore(true)
+        .git_exclude(true)
+        .follow_links(false)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().map(|file_type| { file_type.is_dir() }).unwrap_or(false);
+            match entry.path().strip_prefix(&walker_root) {
+                Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+                    is_dir && entry.file_name() != ".git" && !rules.is_excluded(relative_path, true)
+                }
+                _ => { true }
+            }
+        });
+
+    let mut repositories: Vec<PathBuf> = vec![];
+    for entry in walker.build().flatten() {
+        if entry.path().join(".git").exists() {
+            repositories.push(entry.path().to_path_buf());
+        }
+    }
+    repositories
+}
+
+/// Generates the work of `repository` into `generated` and lists it, paths relative to the source root
+fn save_repository(repository: &Path, relative_path: &Path, generated: &Path, rules: &SourceRules) -> anyhow::Result<Vec<SelectedFile>> {
+    let output_dir = generated.join(relative_path);
+    fs::create_dir_all(&output_dir)?;
+    let mut generated_files: Vec<PathBuf> = vec![];
+
+    let mut status = git(repository, &["status", "--branch", "--short"])?;
+    status.extend(git(repository, &["remote", "--verbose"])?);
+    status.extend(git(repository, &["stash", "list"])?);
+    fs::write(output_dir.join(STATUS_FILE_NAME), status)?;
+    generated_files.push(PathBuf::from(STATUS_FILE_NAME));
+
+    let has_head = git(repository, &["rev-parse", "--verify", "--quiet", "HEAD"]).is_ok();
+    if has_head {
+        let unpushed = git(repository, &["rev-list", "--count", "HEAD", "--branches", "--not", "--remotes"])?;
+        if String::from_utf8_lossy(&unpushed).trim() != "0" {
+            let bundle = output_dir.join(BUNDLE_FILE_NAME);
+            git(repository, &["bundle", "create", "--quiet", &bundle.to_string_lossy(), "HEAD", "--branches", "--not", "--remotes"])?;
+            generated_files.push(PathBuf::from(BUNDLE_FILE_NAME));
+        }
+    }
+
+    // Without commits the staged files are the whole work
+    let diff_args: &[&str] = if has_head { &["diff", "--binary", "--no-color", "--no-ext-diff", "HEAD"] } else { &["diff", "--binary", "--no-color", "--no-ext-diff", "--cached"] };
+    if git_to_file(repository, diff_args, &output_dir.join(DIFF_FILE_NAME))? {
+        generated_files.push(PathBuf::from(DIFF_FILE_NAME));
+    } else {
+        fs::remove_file(output_dir.join(DIFF_FILE_NAME))?;
+    }
+
+    let stashes = String::from_utf8_lossy(&git(repository, &["stash", "list", "--format=%gd"])?).to_string();
+    for (index, stash) in stashes.lines().enumerate() {
+        fs::create_dir_all(output_dir.join(STASH_DIR_NAME))?;
+        let patch = Path::new(STASH_DIR_NAME).join(format!("stash-{}.diff", index));
+        // --include-untracked needs git 2.32
+        let saved = git_to_file(repository, &["stash", "show", "--patch", "--binary", "--no-color", "--include-untracked", stash], &output_dir.join(&patch))
+            .or_else(|_| { git_to_file(repository, &["stash", "show", "--patch", "--binary", "--no-color", stash], &output_dir.join(&patch)) })?;
+        if saved {
+            generated_files.push(patch);
+        }
+    }
+
+    let mut selected: Vec<SelectedFile> = vec![];
+    for file in generated_files {
+        let metadata = fs::metadata(output_dir.join(&file))?;
+        selected.push(SelectedFile {
+            path: output_dir.join(&file),
+            relative_path: relative_path.join(&file),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+
+    let untracked = git(repository, &["ls-files", "--others", "--exclude-standard", "-z"])?;
+    for path in String::from_utf8_lossy(&untracked).split('\0') {
+        // Nested repositories are listed as directories, they are saved on their own
+        if path.is_empty() || path.ends_with('/') {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(repository.join(path)) {
+            Ok(metadata) if metadata.is_file() => { metadata }
+            Ok(_) => { continue }
+            Err(err) => { error!(target: "general", "Error reading metadata of {:?}: {}", repository.join(path), err); continue }
+        };
+        let modified = metadata.modified()?;
+        if rules.is_excluded(&relative_path.join(path), false) || !rules.is_in_range(metadata.len(), modified) {
+            continue;
+        }
+        selected.push(SelectedFile {
+            path: repository.join(path),
+            relative_path: relative_path.join(UNTRACKED_DIR_NAME).join(path),
+            size: metadata.len(),
+            modified,
+        });
+    }
+    Ok(selected)
+}
+
+fn git_command(repository: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new("git");
+    // The backup must not take the index lock a running git command of the user may need
+    command.arg("--no-optional-locks").arg("-C").arg(repository).args(args).stdin(Stdio::null());
+    command
+}
+
+/// Runs git in `repository` and returns its standard output
+fn git(repository: &Path, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    let output = git_command(repository, args).output()?;
+    if !output.status.success() {
+        bail!(GitCommandError(format!("git {} ({}): {}", args.join(" "), output.status, String::from_utf8_lossy(&output.stderr).trim())))
+    }
+    Ok(output.stdout)
+}
+
+/// Runs git in `repository` writing its standard output into `target`, returns false if the output is empty
+fn git_to_file(repository: &Path, args: &[&str], target: &Path) -> anyhow::Result<bool> {
+    let output = git_command(repository, args).stdout(File::create(target)?).stderr(Stdio::piped()).output()?;
+    if !output.status.success() {
+        bail!(GitCommandError(format!("git {} ({}): {}", args.join(" "), output.status, String::from_utf8_lossy(&output.stderr).trim())))
+    }
+    Ok(fs::metadata(target)?.len() > 0)
+}
@@ -0,0 +1,284 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::Duration;
+use anyhow::bail;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use settings::settings::get_config_dir;
+use crate::{format_duration, format_size};
+use crate::report::{BackupReport, DestinationStatus};
+use crate::snapshot_report::SnapshotReport;
+
+/// Name of the backup history file, in the config directory. Every line is a JSON `HistoryEntry`.
+pub const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// What started the backup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// Mouse command detected by `mouse_tracker`
+    Gesture,
+
+    /// "Backup now" button of embgui
+    Gui,
+
+    /// `embctl backup now`
+    Embctl,
+}
+
+impl Display for Trigger {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::Gesture => { write!(f, "gesture") }
+            Trigger::Gui => { write!(f, "gui") }
+            Trigger::Embctl => { write!(f, "embctl") }
+        }
+    }
+}
+
+/// Outcome of the confirmation asked after the first mouse command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Confirmation {
+    /// The second mouse command was given in time
+    Confirmed,
+
+    /// The second mouse command was not given within the tracking window
+    TimedOut,
+
+    /// The user pressed cancel
+    Cancelled,
+
+    /// Manual backups are not confirmed
+    NotRequired,
+}
+
+impl Display for Confirmation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Confirmation::Confirmed => { write!(f, "confirmed") }
+            Confirmation::TimedOut => { write!(f, "timed out") }
+            Confirmation::Cancelled => { write!(f, "cancelled") }
+            Confirmation::NotRequired => { write!(f, "manual") }
+        }
+    }
+}
+
+/// How the triggered backup ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Outcome {
+    Success,
+
+    /// Written with some files skipped because of the budget
+    Partial,
+
+    Failed,
+
+    /// Not confirmed, no backup ran
+    NotRun,
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Success => { write!(f, "success") }
+            Outcome::Partial => { write!(f, "partial") }
+            Outcome::Failed => { write!(f, "failed") }
+            Outcome::NotRun => { write!(f, "not run") }
+        }
+    }
+}
+
+/// Outcome of the backup on a destination
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryDestination {
+    pub destination: String,
+
+    /// 'completed', 'partial', 'refused' or 'failed'
+    pub status: String,
+
+    pub files_copied: usize,
+    pub bytes_copied: u64,
+    pub files_skipped: usize,
+    pub duration_ms: u64,
+
+    /// Why the destination was refused or failed
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// A backup trigger and what followed, a line of the history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// RFC 3339 time of the trigger
+    pub triggered_at: String,
+
+    pub trigger: Trigger,
+
+    pub confirmation: Confirmation,
+
+    pub outcome: Outcome,
+
+    /// Id written into the manifest of the snapshots, `None` if the copy did not start
+    #[serde(default)]
+    pub snapshot_id: Option<String>,
+
+    #[serde(default)]
+    pub files_selected: usize,
+
+    #[serde(default)]
+    pub bytes_selected: u64,
+
+    /// One entry per destination tried
+    #[serde(default)]
+    pub destinations: Vec<HistoryDestination>,
+
+    #[serde(default)]
+    pub duration_ms: u64,
+
+    /// Errors of the destinations and of the hooks, or the error that stopped the backup
+    #[serde(default)]
+    pub errors: Vec<String>,
+
+    /// Reports of the destinations the copy started on, the same written into the snapshots
+    #[serde(default)]
+    pub reports: Vec<SnapshotReport>,
+}
+
+impl HistoryEntry {
+    /// Entry of a trigger that did not lead to a backup
+    pub fn not_run(trigger: Trigger, confirmation: Confirmation) -> Self {
+        HistoryEntry {
+            triggered_at: chrono::Local::now().to_rfc3339(),
+            trigger,
+            confirmation,
+            outcome: Outcome::NotRun,
+            snapshot_id: None,
+            files_selected: 0,
+            bytes_selected: 0,
+            destinations: vec![],
+            duration_ms: 0,
+            errors: vec![],
+            reports: vec![],
+        }
+    }
+
+    /// Entry of a backup, `result` being the one returned by `backup::run` (before `BackupReport::into_result`)
+    pub fn from_result(trigger: Trigger, confirmation: Confirmation, result: &anyhow::Result<BackupReport>) -> Self {
+        let report = match result {
+            Ok(report) => { report }
+            Err(err) => {
+                return HistoryEntry { outcome: Outcome::Failed, errors: vec![err.to_string()], ..HistoryEntry::not_run(trigger, confirmation) };
+            }
+        };
+
+        let destinations: Vec<HistoryDestination> = report.destinations.iter()
+            .map(|destination| {
+                let error = match &destination.status {
+                    DestinationStatus::Completed | DestinationStatus::Partial => { None }
+                    DestinationStatus::Refused(err) => { Some(err.to_string()) }
+                    DestinationStatus::Failed(err) => { Some(err.clone()) }
+                };
+                HistoryDestination {
+                    destination: destination.destination.clone(),
+                    status: destination.status.name().to_string(),
+                    files_copied: destination.files_copied,
+                    bytes_copied: destination.bytes_copied,
+                    files_skipped: destination.files_skipped,
+                    duration_ms: destination.duration.as_millis() as u64,
+                    error,
+                }
+            })
+            .collect();
+        let outcome = if !report.is_success() {
+            Outcome::Failed
+        } else if report.destinations.iter().any(|destination| { destination.status == DestinationStatus::Partial }) {
+            Outcome::Partial
+        } else {
+            Outcome::Success
+        };
+        let errors = destinations.iter()
+            .filter_map(|destination| { destination.error.as_ref().map(|err| { format!("{}: {}", destination.destination, err) }) })
+            .chain(report.hooks.iter().filter(|outcome| { !outcome.is_success() }).map(|outcome| { outcome.summary() }))
+            .collect();
+
+        HistoryEntry {
+            triggered_at: chrono::DateTime::<chrono::Local>::from(report.started_at).to_rfc3339(),
+            trigger,
+            confirmation,
+            outcome,
+            snapshot_id: Some(report.snapshot_id.clone()),
+            files_selected: report.files_selected,
+            bytes_selected: report.bytes_selected,
+            destinations,
+            duration_ms: report.duration.as_millis() as u64,
+            errors,
+            reports: report.destinations.iter().filter_map(|destination| { destination.report.as_deref().cloned() }).collect(),
+        }
+    }
+
+    /// Bytes written into the destinations
+    pub fn bytes_copied(&self) -> u64 {
+        self.destinations.iter().map(|destination| { destination.bytes_copied }).sum()
+    }
+
+    /// Local time of the trigger, e.g. '2024-06-12 18:30:05'
+    pub fn date(&self) -> String {
+        chrono::DateTime::parse_from_rfc3339(&self.triggered_at)
+            .map(|triggered_at| { triggered_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M:%S").to_string() })
+            .unwrap_or(self.triggered_at.clone())
+    }
+
+    /// Snapshot written, e.g. '20240612-183005 on /mnt/usb, 12.30 MiB in 5s', `None` if no backup ran
+    pub fn snapshot_summary(&self) -> Option<String> {
+        let snapshot_id = self.snapshot_id.as_ref()?;
+        let written: Vec<&str> = self.destinations.iter()
+            .filter(|destination| { destination.status == "completed" || destination.status == "partial" })
+            .map(|destination| { destination.destination.as_str() })
+            .collect();
+        Some(format!("{} on {}, {} in {}", snapshot_id, if written.is_empty() { String::from("-") } else { written.join(", ") },
+                     format_size(self.bytes_copied()), format_duration(Duration::from_millis(self.duration_ms))))
+    }
+
+    /// One line description, e.g. '2024-06-12 18:30:05  gesture (confirmed)  success  20240612-183005 on /mnt/usb, 12.30 MiB in 5s'
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}  {} ({})  {}", self.date(), self.trigger, self.confirmation, self.outcome);
+        if let Some(snapshot_summary) = self.snapshot_summary() {
+            summary.push_str(format!("  {}", snapshot_summary).as_str());
+        }
+        summary
+    }
+}
+
+/// Appends `entry` to the history file of the config directory. Each entry is written with a single
+/// append, so the daemon, embctl and embgui can record at the same time.
+pub fn record(entry: &HistoryEntry) -> anyhow::Result<()> {
+    let history_path = get_config_dir()?.join(HISTORY_FILE_NAME);
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    match OpenOptions::new().create(true).append(true).open(&history_path).and_then(|mut file| { file.write_all(line.as_bytes()) }) {
+        Ok(_) => { debug!(target: "general", "Backup history entry recorded"); Ok(()) }
+        Err(err) => { error!(target: "general", "Error recording the backup history into {:?}: {}", history_path, err); bail!(err) }
+    }
+}
+
+/// Reads the history, oldest entry first. Lines that cannot be parsed are left out.
+pub fn read_history() -> anyhow::Result<Vec<HistoryEntry>> {
+    let history_path = get_config_dir()?.join(HISTORY_FILE_NAME);
+    if !history_path.exists() {
+        return Ok(vec![]);
+    }
+    let content = fs::read_to_string(history_path)?;
+    Ok(content.lines()
+        .filter(|line| { !line.trim().is_empty() })
+        .filter_map(|line| {
+            match serde_json::from_str(line) {
+                Ok(entry) => { Some(entry) }
+                Err(err) => { debug!(target: "general", "Invalid backup history line: {}", err); None }
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,224 @@
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, error, info, warn};
+use settings::settings::BackupHook;
+use crate::destination::Destination;
+use crate::format_duration;
+use crate::staging::SNAPSHOT_DIR_NAME;
+
+/// Seconds a hook may run when its timeout is not configured
+pub const DEFAULT_HOOK_TIMEOUT_SEC: u64 = 60;
+
+/// Log of the hooks, in the metadata directory of the snapshot (see `staging::metadata_path`)
+pub const HOOK_LOG_NAME: &str = "hooks.log";
+
+/// Output kept for each hook, the rest is dropped
+const MAX_OUTPUT_SIZE: usize = 1024 * 1024;
+
+/// Interval the end of a hook is checked at
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// When a hook runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPhase {
+    Pre,
+    Post,
+}
+
+impl Display for HookPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookPhase::Pre => { write!(f, "pre-backup") }
+            HookPhase::Post => { write!(f, "post-backup") }
+        }
+    }
+}
+
+/// Result of a hook
+#[derive(Debug, Clone)]
+pub struct HookOutcome {
+    pub command: String,
+    pub phase: HookPhase,
+
+    /// Why the hook failed, e.g. 'exit status: 1' or 'timed out after 1m', `None` on success
+    pub error: Option<String>,
+
+    /// Standard output and error, interleaved
+    pub output: String,
+
+    pub duration: Duration,
+
+    /// The failure of the hook stops the backup (pre-backup hooks) or the following hooks
+    pub abort_on_failure: bool,
+}
+
+impl HookOutcome {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// The hook failed and was configured to abort
+    pub fn aborts(&self) -> bool {
+        !self.is_success() && self.abort_on_failure
+    }
+
+    /// One line description, e.g. "pre-backup hook 'pg_dump app': failed (exit status: 1)"
+    pub fn summary(&self) -> String {
+        match &self.error {
+            None => { format!("{} hook '{}': done in {}", self.phase, self.command, format_duration(self.duration)) }
+            Some(err) => { format!("{} hook '{}': failed ({})", self.phase, self.command, err) }
+        }
+    }
+
+    /// Entry of the hook log: date, summary and output of the hook
+    pub fn log_entry(&self) -> String {
+        let mut entry = format!("[{}] {}\n", chrono::Local::now().format("%Y-%m-%d %H:%M:%S"), self.summary());
+        entry.push_str(&self.output);
+        if !self.output.is_empty() && !self.output.ends_with('\n') {
+            entry.push('\n');
+        }
+        entry
+    }
+}
+
+/// Variables set for the hooks: the phase, the destination and the snapshot directory, on the local file
+/// system for local destinations. Post-backup hooks also get the outcome of the backup.
+pub fn hook_environment(phase: HookPhase, destination: Option<&dyn Destination>, success: Option<bool>) -> Vec<(String, String)> {
+    let mut environment = vec![(String::from("EMERGENCY_BACKUP_HOOK"), match phase { HookPhase::Pre => { String::from("pre") } HookPhase::Post => { String::from("post") } })];
+    if let Some(destination) = destination {
+        let snapshot = match destination.local_path() {
+            Some(local_path) => { local_path.join(SNAPSHOT_DIR_NAME).to_string_lossy().to_string() }
+            None => { format!("{}/{}", destination.name().trim_end_matches('/'), SNAPSHOT_DIR_NAME) }
+        };
+        environment.push((String::from("EMERGENCY_BACKUP_DESTINATION"), destination.name()));
+        environment.push((String::from("EMERGENCY_BACKUP_SNAPSHOT"), snapshot));
+    }
+    if let Some(success) = success {
+        environment.push((String::from("EMERGENCY_BACKUP_STATUS"), String::from(if success { "success" } else { "failure" })));
+    }
+    environment
+}
+
+/// Runs `hooks` in order, stopping after the first failing hook configured to abort
+pub fn run_hooks(hooks: &[BackupHook], phase: HookPhase, environment: &[(String, String)]) -> Vec<HookOutcome> {
+    let mut outcomes: Vec<HookOutcome> = vec![];
+    for hook in hooks {
+        let outcome = run_hook(hook, phase, environment);
+        match &outcome.error {
+            None => { info!(target: "general", "{}", outcome.summary()) }
+            Some(_) => { error!(target: "general", "{}", outcome.summary()) }
+        }
+        let aborts = outcome.aborts();
+        outcomes.push(outcome);
+        if aborts {
+            break;
+        }
+    }
+    outcomes
+}
+
+/// Runs a single hook with the shell, killing it once its timeout elapses
+pub fn run_hook(hook: &BackupHook, phase: HookPhase, environment: &[(String, String)]) -> HookOutcome {
+    let start = Instant::now();
+    let timeout = Duration::from_secs(hook.timeout_sec.unwrap_or(DEFAULT_HOOK_TIMEOUT_SEC));
+    let outcome = |error: Option<String>, output: String| -> HookOutcome {
+        HookOutcome { command: hook.command.clone(), phase, error, output, duration: start.elapsed(), abort_on_failure: hook.abort_on_failure }
+    };
+
+    debug!(target: "general", "Running {} hook '{}'", phase, hook.command);
+    let mut child = match shell(&hook.command)
+        .envs(environment.iter().map(|(key, value)| { (key, value) }))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn() {
+        Ok(child) => { child }
+        Err(err) => { return outcome(Some(format!("cannot be started: {}", err)), String::new()) }
+    };
+
+    // Both pipes are drained while the hook runs, a full pipe would block it
+    let output = Arc::new(Mutex::new(Vec::<u8>::new()));
+    let (done_sender, done_receiver) = mpsc::channel();
+    let readers: Vec<Box<dyn Read + Send>> = [
+        child.stdout.take().map(|stdout| { Box::new(stdout) as Box<dyn Read + Send> }),
+        child.stderr.take().map(|stderr| { Box::new(stderr) as Box<dyn Read + Send> }),
+    ].into_iter().flatten().collect();
+    let reader_count = readers.len();
+    for mut reader in readers {
+        let output = output.clone();
+        let done_sender = done_sender.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 8192];
+            while let Ok(read) = reader.read(&mut buffer) {
+                if read == 0 {
+                    break;
+                }
+                if let Ok(mut output) = output.lock() {
+                    let kept = read.min(MAX_OUTPUT_SIZE.saturating_sub(output.len()));
+                    output.extend_from_slice(&buffer[..kept]);
+                }
+            }
+            let _ = done_sender.send(());
+        });
+    }
+
+    let error = loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => { break None }
+            Ok(Some(status)) => { break Some(status.to_string()) }
+            Ok(None) if start.elapsed() >= timeout => {
+                warn!(target: "general", "{} hook '{}' timed out, killing it", phase, hook.command);
+                kill(&mut child);
+                break Some(format!("timed out after {}", format_duration(timeout)))
+            }
+            Ok(None) => { thread::sleep(POLL_INTERVAL) }
+            Err(err) => { kill(&mut child); break Some(err.to_string()) }
+        }
+    };
+
+    // Processes started in background by the hook may keep the pipes open, their output is not waited for
+    let grace_deadline = Instant::now() + Duration::from_secs(1);
+    for _ in 0..reader_count {
+        if done_receiver.recv_timeout(grace_deadline.saturating_duration_since(Instant::now())).is_err() {
+            break;
+        }
+    }
+    let output = output.lock().map(|output| { String::from_utf8_lossy(&output).to_string() }).unwrap_or_default();
+    outcome(error, output)
+}
+
+#[cfg(target_family = "unix")]
+fn shell(command: &str) -> Command {
+    use std::os::unix::process::CommandExt;
+
+    let mut shell = Command::new("sh");
+    // In its own process group, so that a timeout also kills the processes it started
+    shell.arg("-c").arg(command).process_group(0);
+    shell
+}
+
+#[cfg(target_family = "windows")]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(target_family = "unix")]
+fn kill(child: &mut Child) {
+    // A negative pid targets the process group of the hook
+    if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+        let _ = child.kill();
+    }
+    let _ = child.wait();
+}
+
+#[cfg(target_family = "windows")]
+fn kill(child: &mut Child) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
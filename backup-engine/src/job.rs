@@ -0,0 +1,103 @@
+use std::str::FromStr;
+use std::sync::Arc;
+use anyhow::bail;
+use errors::Error::{BackupPathNotConfigured, DestinationPolicyNotRecognizedError, SameDevicePolicyNotRecognizedError};
+use settings::settings::{BackupConfig, DESTINATION_POLICIES, SAME_DEVICE_POLICIES};
+use crate::copy::Budget;
+use crate::destination::{open, Destination};
+use crate::metadata::MetadataPolicy;
+
+/// How the destinations of a job are used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DestinationPolicy {
+    /// The snapshot is written into the first reachable and writable destination, falling back to the
+    /// next one if the copy fails
+    FirstAvailable,
+
+    /// The snapshot is written into every destination
+    All,
+}
+
+impl FromStr for DestinationPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let [first_available, all] = DESTINATION_POLICIES;
+        match policy.to_lowercase().as_str() {
+            policy if policy == first_available => { Ok(DestinationPolicy::FirstAvailable) }
+            policy if policy == all => { Ok(DestinationPolicy::All) }
+            _ => { bail!(DestinationPolicyNotRecognizedError) }
+        }
+    }
+}
+
+/// What to do when a destination is on the same physical device as a source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameDevicePolicy {
+    /// The destination is used, the backup reports a warning
+    Warn,
+
+    /// The destination is not used
+    Refuse,
+}
+
+impl FromStr for SameDevicePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        let [warn, refuse] = SAME_DEVICE_POLICIES;
+        match policy.to_lowercase().as_str() {
+            policy if policy == warn => { Ok(SameDevicePolicy::Warn) }
+            policy if policy == refuse => { Ok(SameDevicePolicy::Refuse) }
+            _ => { bail!(SameDevicePolicyNotRecognizedError) }
+        }
+    }
+}
+
+/// Everything needed to run a backup, validated when the job is built
+#[derive(Debug, Clone)]
+pub struct BackupJob {
+    /// Sources, filters and copy order
+    pub config: BackupConfig,
+
+    /// Destinations in configuration order
+    pub destinations: Vec<Arc<dyn Destination>>,
+
+    pub policy: DestinationPolicy,
+
+    pub same_device_policy: SameDevicePolicy,
+
+    pub budget: Budget,
+
+    /// Symbolic links and metadata carried over
+    pub metadata: MetadataPolicy,
+
+    /// Maximum bytes written per second on each destination
+    pub bandwidth_limit: Option<u64>,
+
+    /// Run the backup thread with low CPU and I/O priority
+    pub low_priority: bool,
+
+    /// Resume the incomplete snapshot left by an interrupted run instead of starting over
+    pub resume: bool,
+}
+
+impl BackupJob {
+    pub fn from_config(config: &BackupConfig) -> anyhow::Result<Self> {
+        if config.backup_sources.is_empty() || config.backup_destinations.is_empty() {
+            bail!(BackupPathNotConfigured)
+        }
+
+        Ok(BackupJob {
+            config: config.clone(),
+            destinations: config.backup_destinations.iter().map(open).collect(),
+            policy: DestinationPolicy::from_str(&config.destination_policy)?,
+            same_device_policy: SameDevicePolicy::from_str(&config.same_device_policy)?,
+            budget: Budget::from_config(config)?,
+            metadata: MetadataPolicy::from_config(config)?,
+            bandwidth_limit: config.bandwidth_limit_bytes()?,
+            low_priority: config.low_priority,
+            resume: false,
+        })
+    }
+}
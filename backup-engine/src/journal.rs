@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use anyhow::bail;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use crate::destination::Destination;
+use settings::settings::SNAPSHOT_METADATA_DIR_NAME;
+use crate::plan::PlannedFile;
+use crate::staging::metadata_path;
+
+/// Journal segments are named `journal-<n>.jsonl` in the metadata directory of the staging snapshot while it
+/// is being written
+const SEGMENT_PREFIX: &str = "journal-";
+
+const SEGMENT_EXTENSION: &str = ".jsonl";
+
+/// A file copied and verified in the staging snapshot
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    /// Path inside the snapshot
+    pub path: PathBuf,
+
+    pub size: u64,
+
+    /// Modification time of the source file, in milliseconds since the epoch
+    pub modified: u128,
+}
+
+impl JournalEntry {
+    pub fn from(planned: &PlannedFile) -> Self {
+        JournalEntry {
+            path: planned.snapshot_path.clone(),
+            size: planned.file.size,
+            modified: epoch_millis(planned.file.modified),
+        }
+    }
+}
+
+/// Number of entries written at once into the journal of a remote destination
+pub const REMOTE_JOURNAL_BATCH: usize = 32;
+
+/// Longest time entries wait to be written into the journal of a remote destination
+pub const REMOTE_JOURNAL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Append-only record of the files already copied into a staging snapshot, one JSON entry per line, split
+/// in numbered segments. On a local destination every entry is appended to the segment of the run and synced
+/// to disk before the next file is copied, so after a power loss the journal lists only files that are
+/// entirely on the destination. On a remote destination entries are written as a new segment every
+/// `REMOTE_JOURNAL_BATCH` entries or `REMOTE_JOURNAL_INTERVAL`, so that no object is ever rewritten, and an
+/// interruption costs at most a batch of copies again.
+pub struct Journal<'a> {
+    destination: &'a dyn Destination,
+
+    staging_path: PathBuf,
+
+    entries: HashMap<PathBuf, JournalEntry>,
+
+    /// Sizes of the files in the staging snapshot when the journal was opened
+    staged: HashMap<PathBuf, u64>,
+
+    /// Lines not written to the destination yet
+    pending: Vec<String>,
+
+    batch: usize,
+
+    /// Number of the segment written next
+    segment: u64,
+
+    last_flush: Instant,
+}
+
+impl<'a> Journal<'a> {
+    /// Opens the journal of `staging_path` in `destination`, loading the entries of an interrupted run if any.
+    /// The staging snapshot is listed once, for the segments and the sizes of the staged files. Its metadata
+    /// directory (see `staging::metadata_path`) must exist.
+    pub fn open(destination: &'a dyn Destination, staging_path: &Path) -> anyhow::Result<Self> {
+        let loaded = destination.list_files(staging_path)
+            .and_then(|staged| { Ok((Self::read_segments(destination, staging_path, &staged)?, staged)) });
+        let (entries, staged) = match loaded {
+            Ok(loaded) => { loaded }
+            Err(err) => { error!(target: "general", "Error opening the backup journal in {:?} on {}: {}", staging_path, destination.name(), err); bail!(err) }
+        };
+        let segment = staged.keys().filter_map(|path| { segment_number(path) }).max().map(|last| { last + 1 }).unwrap_or(0);
+        Ok(Journal {
+            destination,
+            staging_path: staging_path.to_path_buf(),
+            entries,
+            staged,
+            pending: vec![],
+            batch: if destination.local_path().is_some() { 1 } else { REMOTE_JOURNAL_BATCH },
+            segment,
+            last_flush: Instant::now(),
+        })
+    }
+
+    /// Entries of the journal of `staging_path`, empty if there is none
+    pub fn read(destination: &dyn Destination, staging_path: &Path) -> anyhow::Result<HashMap<PathBuf, JournalEntry>> {
+        Self::read_segments(destination, staging_path, &destination.list_files(staging_path)?)
+    }
+
+    /// Entries of the segments among the `staged` files, in segment order.
+    /// A truncated last line, left by an interruption while it was being written, is ignored.
+    fn read_segments(destination: &dyn Destination, staging_path: &Path, staged: &HashMap<PathBuf, u64>) -> anyhow::Result<HashMap<PathBuf, JournalEntry>> {
+        let mut segments: Vec<(u64, &PathBuf)> = staged.keys().filter_map(|path| { segment_number(path).map(|number| { (number, path) }) }).collect();
+        segments.sort();
+
+        let mut entries = HashMap::new();
+        for (_, segment) in segments {
+            let segment_path = staging_path.join(segment);
+            let Some(content) = destination.read(&segment_path)? else { continue };
+            for line in String::from_utf8_lossy(&content).lines() {
+                match serde_json::from_str::<JournalEntry>(line) {
+                    Ok(entry) => { entries.insert(entry.path.clone(), entry); }
+                    Err(err) => { debug!(target: "general", "Ignoring a malformed journal line in {:?}: {}", segment_path, err) }
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes the segments of the journal of `staging_path`
+    pub fn remove(destination: &dyn Destination, staging_path: &Path) -> anyhow::Result<()> {
+        for path in destination.list_files(staging_path)?.keys() {
+            if segment_number(path).is_some() {
+                destination.remove_all(&staging_path.join(path))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// True if `planned` was already copied into the staging snapshot by an interrupted run and neither the
+    /// source file nor the staged copy changed since
+    pub fn is_done(&self, planned: &PlannedFile) -> bool {
+        match self.entries.get(&planned.snapshot_path) {
+            Some(entry) => { *entry == JournalEntry::from(planned) && self.staged.get(&planned.snapshot_path) == Some(&entry.size) }
+            None => { false }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Staged files of an interrupted run that are not part of `plan` anymore
+    pub fn stale_paths(&self, plan: &[PlannedFile]) -> Vec<PathBuf> {
+        let planned: HashSet<&PathBuf> = plan.iter().map(|planned| { &planned.snapshot_path }).collect();
+        self.entries.keys().filter(|path| { !planned.contains(path) }).cloned().collect()
+    }
+
+    /// Records a copied file, the entry is written to the destination once the batch is full
+    pub fn append(&mut self, entry: JournalEntry) -> anyhow::Result<()> {
+        self.pending.push(serde_json::to_string(&entry)?);
+        self.entries.insert(entry.path.clone(), entry);
+        if self.pending.len() >= self.batch || self.last_flush.elapsed() >= REMOTE_JOURNAL_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the pending entries to the destination: appended to the segment of the run on a local
+    /// destination, as a new segment on a remote one
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut content = self.pending.join("\n");
+        content.push('\n');
+        let segment_path = metadata_path(&self.staging_path).join(format!("{}{}{}", SEGMENT_PREFIX, self.segment, SEGMENT_EXTENSION));
+        if self.destination.local_path().is_some() {
+            self.destination.append(&segment_path, content.as_bytes())?;
+        } else {
+            self.destination.write(&segment_path, content.as_bytes())?;
+            self.segment += 1;
+        }
+        self.pending.clear();
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// Number of the journal segment at `path` in the staging snapshot, `None` for the other files, a source file
+/// named like a segment included
+fn segment_number(path: &Path) -> Option<u64> {
+    if path.parent() != Some(Path::new(SNAPSHOT_METADATA_DIR_NAME)) {
+        return None;
+    }
+    path.file_name()?.to_str()?.strip_prefix(SEGMENT_PREFIX)?.strip_suffix(SEGMENT_EXTENSION)?.parse().ok()
+}
+
+fn epoch_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|duration| { duration.as_millis() }).unwrap_or(0)
+}
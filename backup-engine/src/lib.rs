@@ -0,0 +1,41 @@
+use std::time::Duration;
+use byte_unit::{Byte, UnitType};
+
+pub mod selection;
+pub mod git;
+pub mod plan;
+pub mod manifest;
+pub mod copy;
+pub mod staging;
+pub mod destination;
+pub mod sftp;
+pub mod s3;
+pub mod webdav;
+pub mod removable;
+pub mod journal;
+pub mod hooks;
+pub mod preflight;
+pub mod device;
+pub mod throttle;
+pub mod metadata;
+pub mod dry_run;
+pub mod job;
+pub mod progress;
+pub mod report;
+pub mod snapshot_report;
+pub mod history;
+pub mod backup;
+pub mod restore;
+
+/// Human readable size, e.g. '12.30 MiB'
+pub fn format_size(bytes: u64) -> String {
+    format!("{:.2}", Byte::from_u64(bytes).get_appropriate_unit(UnitType::Binary))
+}
+
+/// Human readable duration rounded to the second, e.g. '2m 5s'
+pub fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        0 => { String::from("less than 1s") }
+        secs => { humantime::format_duration(Duration::from_secs(secs)).to_string() }
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::{Path, PathBuf};
+use anyhow::bail;
+use log::error;
+use serde::{Deserialize, Serialize};
+use crate::destination::Destination;
+use crate::staging::metadata_path;
+
+/// Name of the manifest file, written in the metadata directory of the snapshot (see `staging::metadata_path`)
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// File copied into the snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Path relative to the snapshot root
+    pub path: PathBuf,
+    pub source: String,
+    pub size: u64,
+}
+
+/// File selected for the backup but left out of the snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkippedEntry {
+    /// Path relative to the snapshot root
+    pub path: PathBuf,
+    pub source: String,
+    pub size: u64,
+    pub reason: String,
+}
+
+/// Description of a snapshot, a snapshot is partial if some selected files were skipped
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+
+    /// Id of the backup that wrote the snapshot, the same on every destination and in the backup history
+    #[serde(default)]
+    pub snapshot_id: Option<String>,
+
+    /// RFC 3339 creation time
+    pub created_at: String,
+
+    pub partial: bool,
+
+    /// Completion marker, set once every file of the plan has been processed. A snapshot without it
+    /// comes from an interrupted backup.
+    #[serde(default)]
+    pub complete: bool,
+
+    /// RFC 3339 completion time
+    #[serde(default)]
+    pub completed_at: Option<String>,
+
+    pub files: Vec<ManifestEntry>,
+
+    pub skipped: Vec<SkippedEntry>,
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Manifest {
+            version: MANIFEST_VERSION,
+            snapshot_id: None,
+            created_at: chrono::Local::now().to_rfc3339(),
+            partial: false,
+            complete: false,
+            completed_at: None,
+            files: vec![],
+            skipped: vec![],
+        }
+    }
+
+    pub fn copied_bytes(&self) -> u64 {
+        self.files.iter().map(|entry| { entry.size }).sum()
+    }
+
+    /// Writes the manifest into `snapshot_path`, relative to the root of `destination`
+    pub fn write(&self, destination: &dyn Destination, snapshot_path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        match destination.write(&metadata_path(snapshot_path).join(MANIFEST_FILE_NAME), content.as_bytes()) {
+            Ok(_) => { Ok(()) }
+            Err(err) => { error!(target: "general", "Error writing the snapshot manifest into {:?} on {}: {}", snapshot_path, destination.name(), err); bail!(err) }
+        }
+    }
+
+    /// Reads the manifest of `snapshot_path`, relative to the root of `destination`
+    pub fn read(destination: &dyn Destination, snapshot_path: &Path) -> anyhow::Result<Self> {
+        match destination.read(&metadata_path(snapshot_path).join(MANIFEST_FILE_NAME))? {
+            Some(content) => { Ok(serde_json::from_slice(&content)?) }
+            None => { bail!("No manifest in {:?} on {}", snapshot_path, destination.name()) }
+        }
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
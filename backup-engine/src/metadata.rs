@@ -0,0 +1,170 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use anyhow::bail;
+use filetime::FileTime;
+#[cfg(target_family = "unix")] use log::warn;
+use errors::Error::SymlinkPolicyNotRecognizedError;
+use settings::settings::BackupConfig;
+use crate::throttle::{copy_throttled, Throttle};
+
+/// Extended attributes holding the POSIX ACLs on Linux
+#[cfg(target_family = "unix")]
+const ACL_XATTRS: [&str; 2] = ["system.posix_acl_access", "system.posix_acl_default"];
+
+/// How symbolic links are backed up and restored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// The file or directory the link points to is copied
+    Follow,
+
+    /// The link itself is recreated, with the same target
+    Preserve,
+
+    /// Links are left out
+    Skip,
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(policy: &str) -> Result<Self, Self::Err> {
+        match policy.to_lowercase().as_str() {
+            "follow" => { Ok(SymlinkPolicy::Follow) }
+            "preserve" => { Ok(SymlinkPolicy::Preserve) }
+            "skip" => { Ok(SymlinkPolicy::Skip) }
+            _ => { bail!(SymlinkPolicyNotRecognizedError) }
+        }
+    }
+}
+
+/// Metadata carried over by backups and restores, the same policy applies in both directions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetadataPolicy {
+    pub symlinks: SymlinkPolicy,
+
+    pub permissions: bool,
+
+    /// Access and modification time
+    pub mtime: bool,
+
+    pub xattrs: bool,
+
+    pub acls: bool,
+}
+
+impl MetadataPolicy {
+    pub fn from_config(config: &BackupConfig) -> anyhow::Result<Self> {
+        Ok(MetadataPolicy {
+            symlinks: SymlinkPolicy::from_str(&config.symlink_policy)?,
+            permissions: config.preserve_permissions,
+            mtime: config.preserve_mtime,
+            xattrs: config.preserve_xattrs,
+            acls: config.preserve_acls,
+        })
+    }
+}
+
+impl Default for MetadataPolicy {
+    fn default() -> Self {
+        MetadataPolicy { symlinks: SymlinkPolicy::Follow, permissions: true, mtime: true, xattrs: false, acls: false }
+    }
+}
+
+/// Copies the file or symbolic link `source` to `target` following `policy`, replacing an existing `target`.
+/// Returns the bytes copied, zero for a recreated link.
+pub fn copy_entry(source: &Path, target: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+    let _ = fs::remove_file(target);
+    let is_symlink = fs::symlink_metadata(source)?.file_type().is_symlink();
+
+    let copied = if is_symlink && policy.symlinks == SymlinkPolicy::Preserve {
+        create_symlink(&fs::read_link(source)?, target)?;
+        0
+    } else {
+        let copied = match throttle {
+            Some(throttle) => { copy_throttled(source, target, throttle)? }
+            None => { io::copy(&mut File::open(source)?, &mut File::create(target)?)? }
+        };
+        let file = File::open(target)?;
+        file.sync_all()?;
+        let size = file.metadata()?.len();
+        if size != copied {
+            bail!("{:?} is {} bytes long, {} were copied", target, size, copied)
+        }
+        copied
+    };
+
+    apply_metadata(source, target, policy, is_symlink && policy.symlinks == SymlinkPolicy::Preserve)?;
+    Ok(copied)
+}
+
+/// Applies the metadata of `source` kept by `policy` to `target`. With `link` both are symbolic links
+/// and only their own times and attributes are touched.
+fn apply_metadata(source: &Path, target: &Path, policy: &MetadataPolicy, link: bool) -> anyhow::Result<()> {
+    let metadata = if link { fs::symlink_metadata(source)? } else { fs::metadata(source)? };
+
+    #[cfg(target_family = "unix")]
+    if policy.xattrs || policy.acls {
+        copy_xattrs(source, target, policy, link);
+    }
+
+    // Link permissions can not be changed on Linux and are ignored everywhere else
+    if policy.permissions && !link {
+        fs::set_permissions(target, metadata.permissions())?;
+    }
+
+    if policy.mtime {
+        let atime = FileTime::from_last_access_time(&metadata);
+        let mtime = FileTime::from_last_modification_time(&metadata);
+        if link {
+            filetime::set_symlink_file_times(target, atime, mtime)?;
+        } else {
+            filetime::set_file_times(target, atime, mtime)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the extended attributes of `source` to `target`: the ACL ones with `policy.acls`, the others
+/// with `policy.xattrs`. Attributes the target file system does not support are reported and left out.
+#[cfg(target_family = "unix")]
+fn copy_xattrs(source: &Path, target: &Path, policy: &MetadataPolicy, link: bool) {
+    let names = match xattr::list(source) {
+        Ok(names) => { names }
+        Err(err) => { warn!(target: "general", "Error listing the extended attributes of {:?}: {}", source, err); return }
+    };
+
+    for name in names {
+        let is_acl = ACL_XATTRS.iter().any(|acl| { name == *acl });
+        if (is_acl && (!policy.acls || link)) || (!is_acl && !policy.xattrs) {
+            continue;
+        }
+        let result = xattr::get(source, &name).and_then(|value| {
+            match value {
+                Some(value) => { xattr::set(target, &name, &value) }
+                None => { Ok(()) }
+            }
+        });
+        if let Err(err) = result {
+            warn!(target: "general", "Extended attribute {:?} of {:?} not copied: {}", name, source, err);
+        }
+    }
+}
+
+#[cfg(target_family = "unix")]
+fn create_symlink(link_target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(link_target, link)
+}
+
+#[cfg(target_family = "windows")]
+fn create_symlink(link_target: &Path, link: &Path) -> io::Result<()> {
+    // The kind of link depends on the target, resolved from the directory of the link if relative
+    let resolved = link.parent().map(|parent| { parent.join(link_target) }).unwrap_or(link_target.to_path_buf());
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(link_target, link)
+    } else {
+        std::os::windows::fs::symlink_file(link_target, link)
+    }
+}
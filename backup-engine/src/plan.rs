@@ -0,0 +1,134 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use settings::settings::BackupConfig;
+use crate::selection::{SelectedFile, SourceSelection};
+
+/// A selected file together with its position inside the snapshot
+#[derive(Debug, Clone)]
+pub struct PlannedFile {
+    pub file: SelectedFile,
+
+    /// Name of the source the file comes from
+    pub source_name: String,
+
+    /// Path of the copy, relative to the snapshot root
+    pub snapshot_path: PathBuf,
+}
+
+/// Flattens the selections into the list of files to copy, sorted following `BackupConfig::priority`.
+/// The sort is stable, so files with the same priority keep the walk order.
+pub fn plan(selections: &[SourceSelection], config: &BackupConfig) -> Vec<PlannedFile> {
+    let mut planned: Vec<PlannedFile> = vec![];
+    for selection in selections {
+        let is_file_source = selection.source.mode.to_lowercase() == "file";
+        for file in &selection.files {
+            let snapshot_path = if is_file_source {
+                PathBuf::from(&selection.name)
+            } else {
+                Path::new(&selection.name).join(&file.relative_path)
+            };
+            planned.push(PlannedFile { file: file.clone(), source_name: selection.name.clone(), snapshot_path });
+        }
+    }
+
+    planned.sort_by(|a, b| {
+        config.priority.iter()
+            .map(|criterion| {
+                match criterion.as_str() {
+                    "paths" => { priority_rank(&a.file, &config.priority_paths).cmp(&priority_rank(&b.file, &config.priority_paths)) }
+                    "recent" => { b.file.modified.cmp(&a.file.modified) }
+                    "smallest" => { a.file.size.cmp(&b.file.size) }
+                    _ => { Ordering::Equal }
+                }
+            })
+            .find(|ordering| { *ordering != Ordering::Equal })
+            .unwrap_or(Ordering::Equal)
+    });
+    planned
+}
+
+/// Index of the first priority path containing `file`, files outside every priority path come last
+fn priority_rank(file: &SelectedFile, priority_paths: &[String]) -> usize {
+    priority_paths.iter()
+        .position(|priority_path| {
+            let priority_path = Path::new(priority_path);
+            if priority_path.is_absolute() {
+                file.path.starts_with(priority_path)
+            } else {
+                file.relative_path.starts_with(priority_path)
+            }
+        })
+        .unwrap_or(priority_paths.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+    use settings::settings::BackupSource;
+    use super::*;
+
+    const SOURCE_ROOT: &str = "/home/user/project";
+
+    fn file(relative_path: &str, size: u64, modified_secs: u64) -> SelectedFile {
+        SelectedFile {
+            path: Path::new(SOURCE_ROOT).join(relative_path),
+            relative_path: PathBuf::from(relative_path),
+            size,
+            modified: UNIX_EPOCH + Duration::from_secs(modified_secs),
+        }
+    }
+
+    /// Folder source named "project" holding `files`, in walk order
+    fn selection(files: Vec<SelectedFile>) -> Vec<SourceSelection> {
+        let source = BackupSource { path: SOURCE_ROOT.to_string(), mode: String::from("Folder"), ..BackupSource::default() };
+        vec![SourceSelection { name: String::from("project"), source, files, generated: None }]
+    }
+
+    fn planned_order(selections: &[SourceSelection], priority: &[&str], priority_paths: &[&str]) -> Vec<String> {
+        let config = BackupConfig {
+            priority: priority.iter().map(|criterion| { criterion.to_string() }).collect(),
+            priority_paths: priority_paths.iter().map(|path| { path.to_string() }).collect(),
+            ..BackupConfig::default()
+        };
+        plan(selections, &config).iter().map(|planned| { planned.file.relative_path.to_string_lossy().to_string() }).collect()
+    }
+
+    fn sample() -> Vec<SourceSelection> {
+        selection(vec![
+            file("notes.txt", 300, 20),
+            file("src/main.rs", 100, 10),
+            file("docs/guide.md", 200, 30),
+            file("src/lib.rs", 100, 40),
+            file("docs/api.md", 50, 30),
+        ])
+    }
+
+    #[test]
+    fn without_priority_files_keep_the_walk_order() {
+        assert_eq!(planned_order(&sample(), &[], &[]), ["notes.txt", "src/main.rs", "docs/guide.md", "src/lib.rs", "docs/api.md"]);
+    }
+
+    #[test]
+    fn paths_priority_follows_the_order_of_the_priority_paths() {
+        let absolute_docs = format!("{}/docs", SOURCE_ROOT);
+
+        // Files outside the priority paths come last, each group keeps the walk order
+        assert_eq!(planned_order(&sample(), &["paths"], &["src", &absolute_docs]), ["src/main.rs", "src/lib.rs", "docs/guide.md", "docs/api.md", "notes.txt"]);
+    }
+
+    #[test]
+    fn recent_priority_copies_the_last_modified_files_first() {
+        assert_eq!(planned_order(&sample(), &["recent"], &[]), ["src/lib.rs", "docs/guide.md", "docs/api.md", "notes.txt", "src/main.rs"]);
+    }
+
+    #[test]
+    fn smallest_priority_copies_the_smallest_files_first() {
+        assert_eq!(planned_order(&sample(), &["smallest"], &[]), ["docs/api.md", "src/main.rs", "src/lib.rs", "docs/guide.md", "notes.txt"]);
+    }
+
+    #[test]
+    fn later_criteria_break_the_ties_of_the_earlier_ones() {
+        assert_eq!(planned_order(&sample(), &["paths", "smallest"], &["docs"]), ["docs/api.md", "docs/guide.md", "src/main.rs", "src/lib.rs", "notes.txt"]);
+        assert_eq!(planned_order(&sample(), &["smallest", "recent"], &[]), ["docs/api.md", "src/lib.rs", "src/main.rs", "docs/guide.md", "notes.txt"]);
+    }
+}
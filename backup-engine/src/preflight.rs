@@ -0,0 +1,175 @@
+use std::fs;
+use std::fs::File;
+use std::path::Path;
+use log::debug;
+use errors::Error;
+use errors::Error::{NotEnoughSpaceError, SameDeviceError};
+use crate::destination::Destination;
+use crate::device::device_id;
+use crate::format_size;
+use crate::job::{BackupJob, SameDevicePolicy};
+use crate::metadata::SymlinkPolicy;
+
+/// Result of the checks run on a destination before a backup
+#[derive(Debug, Clone)]
+pub struct DestinationCheck {
+    pub destination: String,
+
+    /// The destination is an existing directory where a file can be created
+    pub writable: bool,
+
+    /// Free space on the destination file system, `None` if it could not be read
+    pub available_space: Option<u64>,
+
+    /// Bytes the backup would write into the destination
+    pub required_space: u64,
+
+    /// Sources stored on the same physical device of the destination
+    pub same_device_sources: Vec<String>,
+
+    /// Reason the destination can not be used
+    pub error: Option<Error>,
+
+    /// Issues that do not prevent the backup, e.g. a source on the same device with the 'warn' policy
+    pub warnings: Vec<String>,
+
+    /// The destination would receive the snapshot under the current destination policy
+    pub used: bool,
+}
+
+impl DestinationCheck {
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// One line description, e.g. '/mnt/usb: writable, 12.00 GiB free, 3.20 MiB required'
+    pub fn summary(&self) -> String {
+        let available_space = match self.available_space {
+            Some(available_space) => { format!("{} free", format_size(available_space)) }
+            None => { String::from("free space unknown") }
+        };
+        let mut summary = format!("{}: {}, {}, {} required",
+                                  self.destination,
+                                  if self.writable { "writable" } else { "NOT writable" },
+                                  available_space,
+                                  format_size(self.required_space));
+        if let Some(err) = &self.error {
+            summary.push_str(format!(" - {}", err).as_str());
+        }
+        for warning in &self.warnings {
+            summary.push_str(format!(" - warning: {}", warning).as_str());
+        }
+        if !self.used {
+            summary.push_str(" (not used)");
+        }
+        summary
+    }
+}
+
+/// Runs the pre-flight checks on `destination`: it must exist, be writable and have `required_space`
+/// bytes free. Sources on the same physical device are refused or reported depending on the job policy,
+/// remote destinations are only probed through the network. With `read_only` nothing is written on the
+/// destination (see `Destination::probe_read_only`), as a dry run requires.
+pub fn check_destination(job: &BackupJob, destination: &dyn Destination, required_space: u64, read_only: bool) -> DestinationCheck {
+    let name = destination.name();
+    let available_space = destination.available_space();
+    let same_device_sources: Vec<String> = match destination.local_path() {
+        Some(destination_path) => {
+            job.config.backup_sources.iter()
+                .filter(|source| { is_same_device(Path::new(&source.path), destination_path) })
+                .map(|source| { source.path.clone() })
+                .collect()
+        }
+        None => { vec![] }
+    };
+    let probe = if read_only { destination.probe_read_only() } else { destination.probe() };
+
+    let mut check = DestinationCheck {
+        destination: name.clone(),
+        writable: probe.is_ok(),
+        available_space,
+        required_space,
+        same_device_sources,
+        error: None,
+        warnings: vec![],
+        used: false,
+    };
+
+    if let Err(err) = probe {
+        check.error = Some(err);
+    } else if available_space.map(|available_space| { available_space < required_space }).unwrap_or(false) {
+        check.error = Some(NotEnoughSpaceError {
+            destination: name.clone(),
+            required: format_size(required_space),
+            available: format_size(available_space.unwrap_or_default()),
+        });
+    } else if let Some(source_path) = check.same_device_sources.first() {
+        match job.same_device_policy {
+            SameDevicePolicy::Refuse => {
+                check.error = Some(SameDeviceError { destination: name.clone(), source_path: source_path.clone() });
+            }
+            SameDevicePolicy::Warn => {
+                check.warnings = check.same_device_sources.iter()
+                    .map(|source_path| { format!("same device as the source '{}'", source_path) })
+                    .collect();
+            }
+        }
+    }
+
+    if destination.local_path().is_none() {
+        if job.metadata.xattrs || job.metadata.acls {
+            check.warnings.push(String::from("extended attributes and ACLs are not kept on remote destinations"));
+        }
+        if job.metadata.symlinks == SymlinkPolicy::Preserve {
+            check.warnings.push(String::from("symbolic links are copied as regular files on remote destinations"));
+        }
+    }
+    if available_space.is_none() && destination.has_capacity() && check.error.is_none() {
+        check.warnings.push(String::from("free space unknown"));
+    }
+    check
+}
+
+/// A destination is available if it is an existing directory where a file can be created
+pub fn is_destination_available(destination: &Path) -> bool {
+    if !destination.is_dir() {
+        return false;
+    }
+
+    let probe_path = destination.join(".emergency-backup-probe");
+    match File::create(&probe_path) {
+        Ok(_) => { let _ = fs::remove_file(probe_path); true }
+        Err(err) => { debug!(target: "general", "Destination {:?} not writable: {:?}", destination, err); false }
+    }
+}
+
+/// A directory is writable if its permissions and its file system allow creating files in it, nothing is written
+#[cfg(target_family = "unix")]
+pub fn is_dir_writable(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // access() also fails with EROFS on a file system mounted read-only
+    path.is_dir() && unsafe { libc::access(c_path.as_ptr(), libc::W_OK | libc::X_OK) } == 0
+}
+
+#[cfg(target_family = "windows")]
+pub fn is_dir_writable(path: &Path) -> bool {
+    fs::metadata(path).map(|metadata| { metadata.is_dir() && !metadata.permissions().readonly() }).unwrap_or(false)
+}
+
+/// `path` itself if it exists, its closest existing parent otherwise
+pub fn existing_ancestor(path: &Path) -> Option<&Path> {
+    path.ancestors().find(|ancestor| { ancestor.exists() })
+}
+
+/// Two paths are on the same device if they share the physical disk (Linux), the file system (other Unix)
+/// or the volume (Windows). Paths that can not be read are considered on different devices.
+pub fn is_same_device(path: &Path, other: &Path) -> bool {
+    match (device_id(path), device_id(other)) {
+        (Some(device), Some(other_device)) => { device == other_device }
+        _ => { false }
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use anyhow::bail;
+use log::error;
+use serde::{Deserialize, Serialize};
+use settings::settings::get_config_dir;
+use crate::{format_duration, format_size};
+use crate::hooks::HookOutcome;
+use crate::report::DestinationReport;
+
+/// Name of the file the daemon publishes its progress into, in the config directory
+pub const PROGRESS_FILE_NAME: &str = "progress.json";
+
+/// Events sent while a job runs. File and progress events are sent once per destination,
+/// with counters restarting from zero on every destination.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// Selection done, the totals are the ones copied into each destination if no budget applies
+    Planned { files: usize, bytes: u64 },
+
+    DestinationStarted { destination: String },
+
+    /// The destination did not pass the pre-flight checks and has not been used
+    DestinationRefused { destination: String, reason: String },
+
+    /// A pre-flight issue that does not prevent the backup, e.g. a source on the same device
+    Warning { destination: String, message: String },
+
+    /// Sent before every file copy and once all files are processed
+    Progress(Progress),
+
+    FileCopied { destination: String, path: PathBuf, size: u64 },
+
+    /// The file was left out of the snapshot, e.g. because of the budget
+    FileSkipped { destination: String, path: PathBuf, size: u64, reason: String },
+
+    DestinationFinished { report: DestinationReport },
+
+    /// A pre-backup or post-backup hook ended
+    HookFinished { outcome: HookOutcome },
+}
+
+/// Progress of the copy into a destination
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Progress {
+    pub destination: String,
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+
+    /// File being copied, relative to the snapshot root
+    pub current_file: Option<PathBuf>,
+
+    /// Bytes copied per second since the destination started
+    pub throughput: f64,
+
+    /// Estimated seconds left, unknown until something has been copied
+    pub eta_secs: Option<u64>,
+}
+
+impl Progress {
+    /// e.g. '12/40 files, 1.20 MiB/4.00 MiB, 2.10 MiB/s, ETA 2s, docs/report.pdf'
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}/{} files, {}/{}, {}/s",
+                                  self.files_done, self.files_total,
+                                  format_size(self.bytes_done), format_size(self.bytes_total),
+                                  format_size(self.throughput as u64));
+        if let Some(eta_secs) = self.eta_secs {
+            summary.push_str(format!(", ETA {}", format_duration(Duration::from_secs(eta_secs))).as_str());
+        }
+        if let Some(current_file) = &self.current_file {
+            summary.push_str(format!(", {}", current_file.display()).as_str());
+        }
+        summary
+    }
+
+    /// Fraction of the bytes processed, between 0 and 1
+    pub fn fraction(&self) -> f32 {
+        if self.bytes_total == 0 {
+            return if self.files_done == self.files_total { 1f32 } else { 0f32 };
+        }
+        self.bytes_done as f32 / self.bytes_total as f32
+    }
+}
+
+/// Keeps the counters of a destination copy and builds the `Progress` values
+pub(crate) struct ProgressTracker {
+    destination: String,
+    start: Instant,
+    files_done: usize,
+    files_total: usize,
+    bytes_done: u64,
+    bytes_total: u64,
+    bytes_copied: u64,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(destination: &str, files_total: usize, bytes_total: u64) -> Self {
+        ProgressTracker {
+            destination: destination.to_string(),
+            start: Instant::now(),
+            files_done: 0,
+            files_total,
+            bytes_done: 0,
+            bytes_total,
+            bytes_copied: 0,
+        }
+    }
+
+    /// A file has been processed, `copied` is false if it was skipped
+    pub(crate) fn file_done(&mut self, size: u64, copied: bool) {
+        self.files_done += 1;
+        self.bytes_done += size;
+        if copied {
+            self.bytes_copied += size;
+        }
+    }
+
+    pub(crate) fn progress(&self, current_file: Option<&Path>) -> Progress {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let throughput = if elapsed > 0f64 { self.bytes_copied as f64 / elapsed } else { 0f64 };
+        let eta_secs = if throughput > 0f64 {
+            Some(((self.bytes_total - self.bytes_done) as f64 / throughput).ceil() as u64)
+        } else {
+            None
+        };
+
+        Progress {
+            destination: self.destination.clone(),
+            files_done: self.files_done,
+            files_total: self.files_total,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            current_file: current_file.map(Path::to_path_buf),
+            throughput,
+            eta_secs,
+        }
+    }
+}
+
+/// State of the last backup run by the daemon
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedProgress {
+    /// 'running', 'done' or 'failed'
+    pub state: String,
+
+    pub updated_at: SystemTime,
+
+    pub progress: Option<Progress>,
+
+    /// Outcome of the backup, once it is over
+    pub summary: Option<String>,
+}
+
+/// Writes `published` into the progress file of the config directory. The file is replaced atomically,
+/// so readers never see a partial content.
+pub fn publish(published: &PublishedProgress) -> anyhow::Result<()> {
+    let config_dir = get_config_dir()?;
+    let tmp_path = config_dir.join(format!("{}.tmp", PROGRESS_FILE_NAME));
+    match fs::write(&tmp_path, serde_json::to_string_pretty(published)?) {
+        Ok(_) => { Ok(fs::rename(tmp_path, config_dir.join(PROGRESS_FILE_NAME))?) }
+        Err(err) => { error!(target: "general", "Error publishing the backup progress: {}", err); bail!(err) }
+    }
+}
+
+/// Reads the progress published by the daemon, `None` if no backup ran yet
+pub fn read_published() -> anyhow::Result<Option<PublishedProgress>> {
+    let progress_path = get_config_dir()?.join(PROGRESS_FILE_NAME);
+    if !progress_path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&fs::read_to_string(progress_path)?)?))
+}
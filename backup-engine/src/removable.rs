@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::PathBuf;
+#[cfg(target_os = "linux")] use std::path::Path;
+use log::debug;
+use settings::settings::RemovableDestination;
+use crate::preflight::{existing_ancestor, is_dir_writable};
+
+/// File marking a drive as a backup target, in the root of the drive
+pub const MARKER_FILE_NAME: &str = ".emergency-backup-target";
+
+/// Mounted removable drive
+#[derive(Debug, Clone, PartialEq)]
+pub struct Volume {
+    /// Block device, e.g. '/dev/sdb1'
+    pub device: String,
+
+    pub mount_point: PathBuf,
+
+    pub label: Option<String>,
+
+    pub uuid: Option<String>,
+}
+
+impl Volume {
+    pub fn has_marker(&self) -> bool {
+        self.mount_point.join(MARKER_FILE_NAME).is_file()
+    }
+
+    /// True if the volume matches every criterion of `destination`. Labels and UUIDs are compared ignoring
+    /// the case, FAT file systems store labels in upper case.
+    pub fn matches(&self, destination: &RemovableDestination) -> bool {
+        let same = |expected: &Option<String>, actual: &Option<String>| -> bool {
+            match (expected, actual) {
+                (None, _) => { true }
+                (Some(expected), Some(actual)) => { expected.eq_ignore_ascii_case(actual) }
+                (Some(_), None) => { false }
+            }
+        };
+        same(&destination.label, &self.label) && same(&destination.uuid, &self.uuid) && (!destination.marker || self.has_marker())
+    }
+
+    /// Description in reports, e.g. '/dev/sdb1 "KINGSTON" on /media/alice/KINGSTON'
+    pub fn description(&self) -> String {
+        match &self.label {
+            Some(label) => { format!("{} \"{}\" on {}", self.device, label, self.mount_point.display()) }
+            None => { format!("{} on {}", self.device, self.mount_point.display()) }
+        }
+    }
+}
+
+/// Line of /proc/self/mountinfo
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub major: u32,
+
+    pub minor: u32,
+
+    /// Directory of the file system that is mounted, '/' unless it is a bind mount
+    pub root: String,
+
+    pub mount_point: PathBuf,
+
+    pub fs_type: String,
+
+    /// Mounted device, e.g. '/dev/sdb1', or a name for virtual file systems
+    pub source: String,
+}
+
+/// Parses the content of /proc/self/mountinfo, lines that can not be parsed are left out
+pub fn parse_mountinfo(content: &str) -> Vec<Mount> {
+    content.lines().filter_map(|line| {
+        // <id> <parent id> <major:minor> <root> <mount point> <options> [<optional fields>...] - <type> <source> <super options>
+        let (mount, file_system) = line.split_once(" - ")?;
+        let fields: Vec<&str> = mount.split(' ').collect();
+        let (major, minor) = fields.get(2)?.split_once(':')?;
+        let mut file_system = file_system.split(' ');
+        Some(Mount {
+            major: major.parse().ok()?,
+            minor: minor.parse().ok()?,
+            root: unescape_octal(fields.get(3)?),
+            mount_point: PathBuf::from(unescape_octal(fields.get(4)?)),
+            fs_type: file_system.next()?.to_string(),
+            source: unescape_octal(file_system.next()?),
+        })
+    }).collect()
+}
+
+/// Spaces, tabs, newlines and backslashes are written as octal escapes (e.g. '\040') in mountinfo
+fn unescape_octal(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped: Vec<u8> = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes.get(index + 1..index + 4)
+            .filter(|digits| { bytes[index] == b'\\' && digits.iter().all(|digit| { (b'0'..=b'7').contains(digit) }) })
+            .and_then(|digits| { u8::from_str_radix(std::str::from_utf8(digits).ok()?, 8).ok() });
+        match escape {
+            Some(byte) => { unescaped.push(byte); index += 4 }
+            None => { unescaped.push(bytes[index]); index += 1 }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+/// Characters other than ASCII letters and digits are written as hex escapes (e.g. '\x20') in the names
+/// of the /dev/disk/by-label links
+#[cfg(target_os = "linux")]
+fn unescape_hex(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut unescaped: Vec<u8> = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        let escape = bytes.get(index..index + 4)
+            .filter(|escape| { escape.starts_with(b"\\x") })
+            .and_then(|escape| { u8::from_str_radix(std::str::from_utf8(&escape[2..]).ok()?, 16).ok() });
+        match escape {
+            Some(byte) => { unescaped.push(byte); index += 4 }
+            None => { unescaped.push(bytes[index]); index += 1 }
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+/// Mounted removable drives, in mount order. A device mounted more than once is listed at its first mount point.
+#[cfg(target_os = "linux")]
+pub fn removable_volumes() -> Vec<Volume> {
+    let content = match fs::read_to_string("/proc/self/mountinfo") {
+        Ok(content) => { content }
+        Err(err) => { debug!(target: "general", "Error reading /proc/self/mountinfo: {}", err); return vec![] }
+    };
+    let labels = disk_links("/dev/disk/by-label");
+    let uuids = disk_links("/dev/disk/by-uuid");
+
+    let mut volumes: Vec<Volume> = vec![];
+    for mount in parse_mountinfo(&content) {
+        // Bind mounts expose a directory of a file system mounted elsewhere
+        if mount.root != "/" || !mount.source.starts_with("/dev/") || !is_removable(mount.major, mount.minor) {
+            continue;
+        }
+        let device = fs::canonicalize(&mount.source).unwrap_or(PathBuf::from(&mount.source));
+        if volumes.iter().any(|volume| { Path::new(&volume.device) == device }) {
+            continue;
+        }
+        volumes.push(Volume {
+            device: device.to_string_lossy().to_string(),
+            mount_point: mount.mount_point,
+            label: find_link(&labels, &device),
+            uuid: find_link(&uuids, &device),
+        });
+    }
+    volumes
+}
+
+/// Removable drives are only detected on Linux
+#[cfg(not(target_os = "linux"))]
+pub fn removable_volumes() -> Vec<Volume> {
+    vec![]
+}
+
+/// Disk of the block device `major:minor` seen as removable: flagged as removable by the kernel (USB sticks,
+/// card readers), attached to a USB port (external disks) or an SD card
+#[cfg(target_os = "linux")]
+fn is_removable(major: u32, minor: u32) -> bool {
+    let Ok(device_path) = fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)) else {
+        return false;
+    };
+    let disk_path = match device_path.join("partition").exists() {
+        true => { device_path.parent().map(|parent| { parent.to_path_buf() }).unwrap_or(device_path) }
+        false => { device_path }
+    };
+    let read = |path: &Path| -> String { fs::read_to_string(path).map(|value| { value.trim().to_string() }).unwrap_or_default() };
+    read(&disk_path.join("removable")) == "1"
+        || disk_path.components().any(|component| { component.as_os_str().to_string_lossy().starts_with("usb") })
+        || read(&disk_path.join("device").join("type")) == "SD"
+}
+
+/// Names of the links in `dir` with the device they point to
+#[cfg(target_os = "linux")]
+fn disk_links(dir: &str) -> Vec<(PathBuf, String)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    entries.flatten()
+        .filter_map(|entry| {
+            let device = fs::canonicalize(entry.path()).ok()?;
+            Some((device, unescape_hex(&entry.file_name().to_string_lossy())))
+        })
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn find_link(links: &[(PathBuf, String)], device: &Path) -> Option<String> {
+    links.iter().find(|(link_device, _)| { link_device == device }).map(|(_, name)| { name.clone() })
+}
+
+/// First mounted drive matching `destination` whose target directory is writable, see `choose_volume`
+pub fn find_volume(destination: &RemovableDestination) -> Option<Volume> {
+    choose_volume(removable_volumes(), destination)
+}
+
+/// First of `volumes` matching `destination` whose target directory is writable, the first one matching
+/// if none is writable (the pre-flight checks then report it), `None` if no drive matches.
+/// Nothing is written on the drives: a missing `dir` is created by the backup, it only needs its closest
+/// existing parent to be writable.
+pub fn choose_volume(volumes: Vec<Volume>, destination: &RemovableDestination) -> Option<Volume> {
+    let matching: Vec<Volume> = volumes.into_iter().filter(|volume| { volume.matches(destination) }).collect();
+    let writable = matching.iter().find(|volume| {
+        let target = target_dir(volume, destination);
+        let available = existing_ancestor(&target).map(is_dir_writable).unwrap_or(false);
+        if !available {
+            debug!(target: "general", "Removable drive {} not writable", volume.description());
+        }
+        available
+    });
+    writable.or(matching.first()).cloned()
+}
+
+/// Directory the snapshot is written in on `volume`
+pub fn target_dir(volume: &Volume, destination: &RemovableDestination) -> PathBuf {
+    match &destination.dir {
+        Some(dir) => { volume.mount_point.join(dir.trim_start_matches('/')) }
+        None => { volume.mount_point.clone() }
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, SystemTime};
+use anyhow::bail;
+use errors::Error;
+use errors::Error::{FileTransferError, NoDestinationAvailableError};
+use crate::hooks::HookOutcome;
+use crate::job::DestinationPolicy;
+use crate::snapshot_report::SnapshotReport;
+
+/// How the backup ended on a single destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationStatus {
+    Completed,
+
+    /// Some selected files were left out of the snapshot because of the budget
+    Partial,
+
+    /// The destination did not pass the pre-flight checks
+    Refused(Error),
+
+    Failed(String),
+}
+
+impl DestinationStatus {
+    /// 'completed', 'partial', 'refused' or 'failed'
+    pub fn name(&self) -> &'static str {
+        match self {
+            DestinationStatus::Completed => { "completed" }
+            DestinationStatus::Partial => { "partial" }
+            DestinationStatus::Refused(_) => { "refused" }
+            DestinationStatus::Failed(_) => { "failed" }
+        }
+    }
+}
+
+/// Result of the backup on a single destination
+#[derive(Debug, Clone)]
+pub struct DestinationReport {
+    pub destination: String,
+    pub status: DestinationStatus,
+    pub files_copied: usize,
+    pub bytes_copied: u64,
+    pub files_skipped: usize,
+    pub duration: Duration,
+
+    /// Pre-flight issues that did not prevent the backup
+    pub warnings: Vec<String>,
+
+    /// Report written into the snapshot, `None` if the destination was refused
+    pub report: Option<Box<SnapshotReport>>,
+}
+
+impl DestinationReport {
+    /// The snapshot has been written, even if partial
+    pub fn is_written(&self) -> bool {
+        matches!(self.status, DestinationStatus::Completed | DestinationStatus::Partial)
+    }
+
+    /// One line description, e.g. '/mnt/usb: partial, 3 files skipped'
+    pub fn summary(&self) -> String {
+        let mut summary = match &self.status {
+            DestinationStatus::Completed => { format!("{}: done", self.destination) }
+            DestinationStatus::Partial => { format!("{}: partial, {} files skipped", self.destination, self.files_skipped) }
+            DestinationStatus::Refused(err) => { format!("{}: not used ({})", self.destination, err) }
+            DestinationStatus::Failed(err) => { format!("{}: failed ({})", self.destination, err) }
+        };
+        for warning in &self.warnings {
+            summary.push_str(format!(" - warning: {}", warning).as_str());
+        }
+        summary
+    }
+}
+
+/// Result of a backup job
+#[derive(Debug, Clone)]
+pub struct BackupReport {
+    /// Id of the snapshot written, the start time, e.g. '20240612-183005'
+    pub snapshot_id: String,
+
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub policy: DestinationPolicy,
+
+    /// Files and bytes selected for the backup
+    pub files_selected: usize,
+    pub bytes_selected: u64,
+
+    /// One report per destination tried, in configuration order
+    pub destinations: Vec<DestinationReport>,
+
+    /// Pre-backup then post-backup hooks, in the order they ran
+    pub hooks: Vec<HookOutcome>,
+}
+
+impl BackupReport {
+    /// The snapshot has been written into at least one destination
+    pub fn is_success(&self) -> bool {
+        self.destinations.iter().any(|destination| { destination.is_written() })
+    }
+
+    /// One line per destination and per failed hook, used as notification body
+    pub fn summary(&self) -> String {
+        self.destinations.iter()
+            .map(|destination| { destination.summary() })
+            .chain(self.hooks.iter().filter(|outcome| { !outcome.is_success() }).map(|outcome| { outcome.summary() }))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Turns a report where the snapshot has not been written anywhere into an error. With a single
+    /// destination the error is the one of the destination, e.g. `NotEnoughSpaceError`, otherwise the
+    /// error message lists the outcome of every destination.
+    pub fn into_result(self) -> anyhow::Result<Self> {
+        if self.is_success() {
+            return Ok(self);
+        }
+
+        if let [destination] = self.destinations.as_slice() {
+            match &destination.status {
+                DestinationStatus::Refused(err) => { bail!(err.clone()) }
+                _ => { bail!(FileTransferError) }
+            }
+        }
+
+        let err = match self.policy {
+            DestinationPolicy::FirstAvailable => { NoDestinationAvailableError }
+            DestinationPolicy::All => { FileTransferError }
+        };
+        let message = format!("{}\n{}", err, self.summary());
+        Err(anyhow::Error::new(err).context(message))
+    }
+}
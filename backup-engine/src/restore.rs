@@ -0,0 +1,101 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use anyhow::bail;
+use log::{debug, error, info};
+use errors::Error::FileTransferError;
+use crate::destination::Destination;
+use crate::manifest::Manifest;
+use crate::metadata::{MetadataPolicy, SymlinkPolicy};
+use crate::staging::SNAPSHOT_DIR_NAME;
+
+/// Outcome of a restore
+#[derive(Debug, Clone, Default)]
+pub struct RestoreReport {
+    pub files_restored: usize,
+
+    pub bytes_restored: u64,
+
+    /// Symbolic links left out by the 'skip' policy
+    pub files_skipped: usize,
+
+    /// Snapshot paths that could not be restored, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+impl RestoreReport {
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} files restored ({} bytes)", self.files_restored, self.bytes_restored);
+        if self.files_skipped > 0 {
+            summary.push_str(&format!(", {} links skipped", self.files_skipped));
+        }
+        if !self.failed.is_empty() {
+            summary.push_str(&format!(", {} failed", self.failed.len()));
+        }
+        summary
+    }
+
+    /// The report if every file was restored, `FileTransferError` otherwise
+    pub fn into_result(self) -> anyhow::Result<Self> {
+        if self.failed.is_empty() {
+            Ok(self)
+        } else {
+            bail!(FileTransferError)
+        }
+    }
+}
+
+/// First destination in `destinations` holding a complete snapshot
+pub fn find_snapshot(destinations: &[Arc<dyn Destination>]) -> Option<Arc<dyn Destination>> {
+    destinations.iter()
+        .find(|destination| { Manifest::read(destination.as_ref(), Path::new(SNAPSHOT_DIR_NAME)).map(|manifest| { manifest.complete }).unwrap_or(false) })
+        .cloned()
+}
+
+/// Copies the files listed in the manifest of the snapshot of `destination` into `target`, rebuilding the
+/// snapshot tree and reapplying the metadata kept by `policy`. A file that can not be restored is reported
+/// and the restore goes on with the next one.
+pub fn restore(destination: &dyn Destination, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<RestoreReport> {
+    let snapshot_path = Path::new(SNAPSHOT_DIR_NAME);
+    let manifest = match Manifest::read(destination, snapshot_path) {
+        Ok(manifest) => { manifest }
+        Err(err) => { error!(target: "general", "Error reading the manifest of {}: {}", destination.name(), err); bail!(err) }
+    };
+    info!(target: "general", "Restoring {} files from {} into {:?}", manifest.files.len(), destination.name(), target);
+
+    let mut report = RestoreReport::default();
+    for entry in &manifest.files {
+        // The manifest lives on the destination, it must not lead outside the target
+        if entry.path.components().any(|component| { !matches!(component, Component::Normal(_)) }) {
+            report.failed.push((entry.path.clone(), String::from("invalid path")));
+            continue;
+        }
+
+        let source = snapshot_path.join(&entry.path);
+        if policy.symlinks == SymlinkPolicy::Skip && destination.is_symlink(&source) {
+            report.files_skipped += 1;
+            continue;
+        }
+
+        let target_path = target.join(&entry.path);
+        let result = match target_path.parent() {
+            Some(parent_path) => { fs::create_dir_all(parent_path).map_err(anyhow::Error::from) }
+            None => { Ok(()) }
+        }.and_then(|_| { destination.get_file(&source, &target_path, policy) });
+
+        match result {
+            Ok(bytes) => {
+                debug!(target: "general", "Restored {:?}", entry.path);
+                report.files_restored += 1;
+                report.bytes_restored += bytes;
+            }
+            Err(err) => {
+                error!(target: "general", "Error restoring {:?}: {}", entry.path, err);
+                report.failed.push((entry.path.clone(), err.to_string()));
+            }
+        }
+    }
+
+    info!(target: "general", "Restore done: {}", report.summary());
+    Ok(report)
+}
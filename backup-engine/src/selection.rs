@@ -0,0 +1,381 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::SystemTime;
+use anyhow::bail;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::WalkBuilder;
+use log::{debug, error};
+use errors::Error::{BackupSourceError, GlobRuleFormatError};
+use settings::settings::{BackupConfig, BackupSource, IGNORE_FILE_NAME};
+use tempfile::TempDir;
+use crate::git::select_git_work;
+use crate::metadata::SymlinkPolicy;
+
+/// A file chosen for the backup
+#[derive(Debug, Clone)]
+pub struct SelectedFile {
+    /// Absolute path of the file to copy
+    pub path: PathBuf,
+
+    /// Path of the file relative to the source root, used to rebuild the tree inside the snapshot
+    pub relative_path: PathBuf,
+
+    pub size: u64,
+
+    pub modified: SystemTime,
+}
+
+/// Files selected from a single source
+#[derive(Debug, Clone)]
+pub struct SourceSelection {
+    /// Name of the source inside the snapshot
+    pub name: String,
+
+    pub source: BackupSource,
+
+    pub files: Vec<SelectedFile>,
+
+    /// Directory holding the files generated for the source (git bundles and diffs), removed once the
+    /// selection and its clones are dropped
+    pub generated: Option<Arc<TempDir>>,
+}
+
+impl SourceSelection {
+    pub fn total_size(&self) -> u64 {
+        self.files.iter().map(|file| { file.size }).sum()
+    }
+}
+
+/// Include and exclude rules of a single source
+pub(crate) struct SourceRules {
+    include: Option<GlobSet>,
+    exclude: Gitignore,
+    max_file_size: Option<u64>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+}
+
+impl SourceRules {
+    pub(crate) fn new(source: &BackupSource, config: &BackupConfig) -> anyhow::Result<Self> {
+        let include_rules = source.include_rules(config);
+        let include = if include_rules.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for rule in include_rules {
+                // Same as gitignore: a rule without a separator matches the file name at any depth
+                let rule = if rule.contains('/') { rule.trim_start_matches('/').to_string() } else { format!("**/{}", rule) };
+                match Glob::new(&rule) {
+                    Ok(glob) => { builder.add(glob); }
+                    Err(err) => { error!(target: "general", "Invalid include rule {:?}: {}", rule, err); bail!(GlobRuleFormatError) }
+                }
+            }
+            Some(builder.build()?)
+        };
+
+        let mut exclude_builder = GitignoreBuilder::new(&source.path);
+        for rule in source.exclude_rules(config) {
+            if let Err(err) = exclude_builder.add_line(None, &rule) {
+                error!(target: "general", "Invalid exclude rule {:?}: {}", rule, err);
+                bail!(GlobRuleFormatError)
+            }
+        }
+
+        Ok(SourceRules {
+            include,
+            exclude: exclude_builder.build()?,
+            max_file_size: config.max_file_size_bytes()?,
+            modified_after: config.modified_after()?,
+            modified_before: config.modified_before()?,
+        })
+    }
+
+    pub(crate) fn is_excluded(&self, relative_path: &Path, is_dir: bool) -> bool {
+        self.exclude.matched_path_or_any_parents(relative_path, is_dir).is_ignore()
+    }
+
+    fn is_included(&self, relative_path: &Path) -> bool {
+        match &self.include {
+            Some(include) => { include.is_match(relative_path) }
+            None => { true }
+        }
+    }
+
+    /// Size and modification time filters
+    pub(crate) fn is_in_range(&self, size: u64, modified: SystemTime) -> bool {
+        self.max_file_size.map(|max_file_size| { size <= max_file_size }).unwrap_or(true) &&
+            self.modified_after.map(|modified_after| { modified >= modified_after }).unwrap_or(true) &&
+            self.modified_before.map(|modified_before| { modified <= modified_before }).unwrap_or(true)
+    }
+}
+
+/// Runs the selection on every configured source, in the same order of `BackupConfig::backup_sources`
+pub fn select_sources(config: &BackupConfig) -> anyhow::Result<Vec<SourceSelection>> {
+    let mut selections: Vec<SourceSelection> = vec![];
+    for (source, name) in config.backup_sources.iter().zip(config.snapshot_names()) {
+        if source.mode.to_lowercase() == "git" {
+            let (files, generated) = select_git_work(source, config)?;
+            selections.push(SourceSelection { name, source: source.clone(), files, generated: Some(Arc::new(generated)) });
+        } else {
+            let files = select_files(source, config)?;
+            selections.push(SourceSelection { name, source: source.clone(), files, generated: None });
+        }
+    }
+    Ok(selections)
+}
+
+/// Returns the files of `source` that have to be copied under the current rules.
+/// A single file source is always selected (unless it is a link and links are skipped), filters only apply
+/// to directories. Symbolic links are followed, kept or skipped following `BackupConfig::symlink_policy`,
+/// special files (FIFOs, sockets, devices) are never selected.
+pub fn select_files(source: &BackupSource, config: &BackupConfig) -> anyhow::Result<Vec<SelectedFile>> {
+    let source_path = PathBuf::from(&source.path);
+    let symlinks = SymlinkPolicy::from_str(&config.symlink_policy)?;
+
+    if source.mode.to_lowercase() == "file" {
+        let file_name = match source_path.file_name() {
+            Some(file_name) => { PathBuf::from(file_name) }
+            None => { error!("Error to retrieve backup source file name"); bail!(BackupSourceError) }
+        };
+        let is_symlink = source_path.symlink_metadata()?.file_type().is_symlink();
+        if is_symlink && symlinks == SymlinkPolicy::Skip {
+            return Ok(vec![]);
+        }
+        let metadata = if is_symlink && symlinks == SymlinkPolicy::Preserve { source_path.symlink_metadata()? } else { source_path.metadata()? };
+        let modified = metadata.modified()?;
+        return Ok(vec![SelectedFile { path: source_path, relative_path: file_name, size: metadata.len(), modified }]);
+    }
+
+    let rules = Arc::new(SourceRules::new(source, config)?);
+    let walker_rules = rules.clone();
+    let root = source_path.clone();
+    let mut walker = WalkBuilder::new(&source_path);
+    walker.standard_filters(false)
+        .follow_links(symlinks == SymlinkPolicy::Follow)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().map(|file_type| { file_type.is_dir() }).unwrap_or(false);
+            match entry.path().strip_prefix(&root) {
+                Ok(relative_path) if !relative_path.as_os_str().is_empty() => {
+                    !walker_rules.is_excluded(relative_path, is_dir)
+                }
+                _ => { true }
+            }
+        });
+
+    let mut selected: Vec<SelectedFile> = vec![];
+    for entry in walker.build() {
+        let entry = match entry {
+            Ok(entry) => { entry }
+            Err(err) => { error!(target: "general", "Error walking {:?}: {}", source.path, err); continue }
+        };
+
+        match entry.file_type() {
+            Some(file_type) if file_type.is_dir() => { continue }
+            Some(file_type) if file_type.is_symlink() && symlinks == SymlinkPolicy::Skip => { continue }
+            Some(file_type) if !file_type.is_file() && !file_type.is_symlink() => {
+                debug!(target: "general", "Skipping special file {:?}", entry.path());
+                continue
+            }
+            _ => {}
+        }
+
+        let relative_path = match entry.path().strip_prefix(&source_path) {
+            Ok(relative_path) => { relative_path.to_path_buf() }
+            Err(_) => { continue }
+        };
+
+        if relative_path.file_name().map(|name| { name == IGNORE_FILE_NAME }).unwrap_or(false) || !rules.is_included(&relative_path) {
+            continue;
+        }
+
+        let (size, modified) = match entry.metadata().map(|metadata| { (metadata.len(), metadata.modified()) }) {
+            Ok((size, Ok(modified))) => { (size, modified) }
+            Ok((_, Err(err))) => { error!(target: "general", "Error reading modification time of {:?}: {}", entry.path(), err); continue }
+            Err(err) => { error!(target: "general", "Error reading metadata of {:?}: {}", entry.path(), err); continue }
+        };
+
+        if !rules.is_in_range(size, modified) {
+            continue;
+        }
+        selected.push(SelectedFile { path: entry.path().to_path_buf(), relative_path, size, modified });
+    }
+
+    debug!(target: "general", "{} files selected from {:?}", selected.len(), source.path);
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, UNIX_EPOCH};
+    use filetime::FileTime;
+    use settings::settings::{EXCLUDE_PRESETS, SNAPSHOT_METADATA_DIR_NAME};
+    use super::*;
+
+    /// Temporary source tree of files with their content
+    fn tree(files: &[(&str, &str)]) -> TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, content).unwrap();
+        }
+        dir
+    }
+
+    fn folder(dir: &TempDir) -> BackupSource {
+        BackupSource {
+            path: dir.path().to_string_lossy().to_string(),
+            mode: String::from("Folder"),
+            ..BackupSource::default()
+        }
+    }
+
+    /// Relative paths of the selected files, sorted
+    fn selected(source: &BackupSource, config: &BackupConfig) -> Vec<String> {
+        let mut paths: Vec<String> = select_files(source, config).unwrap().iter()
+            .map(|file| { file.relative_path.to_string_lossy().replace('\\', "/") })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn include_rules_match_names_at_any_depth_and_paths_from_the_root() {
+        let dir = tree(&[("main.rs", ""), ("src/lib.rs", ""), ("src/notes.txt", ""), ("docs/guide.txt", ""), ("src/docs/old.txt", "")]);
+        let config = BackupConfig { include: vec![String::from("*.rs"), String::from("docs/**")], ..BackupConfig::default() };
+
+        assert_eq!(selected(&folder(&dir), &config), ["docs/guide.txt", "main.rs", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn exclude_rules_of_the_config_and_of_the_source_are_applied() {
+        let dir = tree(&[("keep.txt", ""), ("draft.tmp", ""), ("build/out.txt", ""), ("src/build/out.txt", ""), ("secret.txt", ""), ("src/main.rs", "")]);
+        let config = BackupConfig { exclude: vec![String::from("*.tmp"), String::from("build/")], ..BackupConfig::default() };
+        let source = BackupSource { exclude: Some(vec![String::from("/secret.txt")]), ..folder(&dir) };
+
+        assert_eq!(selected(&source, &config), ["keep.txt", "src/main.rs"]);
+    }
+
+    #[test]
+    fn exclude_rules_win_over_include_rules() {
+        let dir = tree(&[("main.rs", ""), ("generated.rs", "")]);
+        let config = BackupConfig { include: vec![String::from("*.rs")], exclude: vec![String::from("generated.rs")], ..BackupConfig::default() };
+
+        assert_eq!(selected(&folder(&dir), &config), ["main.rs"]);
+    }
+
+    #[test]
+    fn ebignore_files_apply_to_their_directory_and_are_not_copied() {
+        let dir = tree(&[
+            (IGNORE_FILE_NAME, "*.log\n"),
+            ("app.log", ""),
+            ("app.txt", ""),
+            ("data/raw.txt", ""),
+            ("sub/.ebignore", "data/\n!debug.log\n"),
+            ("sub/data/raw.txt", ""),
+            ("sub/debug.log", ""),
+            ("sub/trace.log", ""),
+            ("sub/notes.txt", ""),
+        ]);
+
+        assert_eq!(selected(&folder(&dir), &BackupConfig::default()), ["app.txt", "data/raw.txt", "sub/debug.log", "sub/notes.txt"]);
+    }
+
+    #[test]
+    fn extension_filter_includes_only_the_listed_extensions() {
+        let dir = tree(&[("main.rs", ""), ("README.md", ""), ("src/lib.rs", ""), ("Cargo.toml", ""), ("src/lib.rs.bak", ""), ("rs", "")]);
+        let config = BackupConfig { extension_only: true, extension_type: vec![String::from("rs"), String::from("md")], ..BackupConfig::default() };
+
+        assert_eq!(selected(&folder(&dir), &config), ["README.md", "main.rs", "src/lib.rs"]);
+    }
+
+    /// An extension is matched as a whole: 'rs' must not select a '.rsrc' file
+    #[test]
+    fn extension_filter_does_not_match_longer_extensions() {
+        let dir = tree(&[("icons.rsrc", ""), ("main.rs", "")]);
+        let source = BackupSource { extension_only: Some(true), extension_type: Some(vec![String::from("rs")]), ..folder(&dir) };
+
+        assert_eq!(selected(&source, &BackupConfig::default()), ["main.rs"]);
+    }
+
+    #[test]
+    fn every_exclude_preset_skips_its_directories_only() {
+        let files = [
+            ("src/target.rs", ""),
+            ("target/debug/app", ""),
+            ("crates/core/target/release/lib.rlib", ""),
+            ("web/node_modules/react/index.js", ""),
+            ("web/index.js", ""),
+            ("repo/.git/objects/ab/cdef", ""),
+            ("repo/.git/HEAD", ""),
+        ];
+        let expected: [(&str, &[&str]); 3] = [
+            ("rust-target", &["repo/.git/HEAD", "repo/.git/objects/ab/cdef", "src/target.rs", "web/index.js", "web/node_modules/react/index.js"]),
+            ("node-modules", &["crates/core/target/release/lib.rlib", "repo/.git/HEAD", "repo/.git/objects/ab/cdef", "src/target.rs", "target/debug/app", "web/index.js"]),
+            ("git-objects", &["crates/core/target/release/lib.rlib", "repo/.git/HEAD", "src/target.rs", "target/debug/app", "web/index.js", "web/node_modules/react/index.js"]),
+        ];
+        assert_eq!(expected.len(), EXCLUDE_PRESETS.len());
+
+        let dir = tree(&files);
+        assert_eq!(selected(&folder(&dir), &BackupConfig::default()).len(), files.len());
+        for (preset, expected) in expected {
+            let config = BackupConfig { exclude_presets: vec![preset.to_string()], ..BackupConfig::default() };
+            assert_eq!(selected(&folder(&dir), &config), expected, "preset {}", preset);
+        }
+    }
+
+    /// `max_file_size` is inclusive
+    #[test]
+    fn no_source_is_stored_in_the_metadata_directory() {
+        let dir = tree(&[(&format!("{}/notes.txt", SNAPSHOT_METADATA_DIR_NAME), "")]);
+        let source = BackupSource { path: dir.path().join(SNAPSHOT_METADATA_DIR_NAME).to_string_lossy().to_string(), ..folder(&dir) };
+        let config = BackupConfig { backup_sources: vec![source], ..BackupConfig::default() };
+
+        let selections = select_sources(&config).unwrap();
+
+        assert_eq!(selections[0].name, format!("{}-2", SNAPSHOT_METADATA_DIR_NAME));
+        assert_eq!(selections[0].files.len(), 1);
+    }
+
+    #[test]
+    fn max_file_size_keeps_files_of_exactly_that_size() {
+        let dir = tree(&[("999", &"x".repeat(999)), ("1000", &"x".repeat(1000)), ("1001", &"x".repeat(1001))]);
+        let config = BackupConfig { max_file_size: Some(String::from("1000")), ..BackupConfig::default() };
+
+        assert_eq!(selected(&folder(&dir), &config), ["1000", "999"]);
+    }
+
+    /// `min_mtime` and `max_mtime` are inclusive
+    #[test]
+    fn modification_time_window_keeps_files_on_its_bounds() {
+        // 2024-01-01T00:00:00Z and 2024-01-02T00:00:00Z
+        let start = 1_704_067_200;
+        let end = 1_704_153_600;
+        let dir = tree(&[("before", ""), ("start", ""), ("inside", ""), ("end", ""), ("after", "")]);
+        for (name, modified) in [("before", start - 1), ("start", start), ("inside", start + 3600), ("end", end), ("after", end + 1)] {
+            filetime::set_file_mtime(dir.path().join(name), FileTime::from_unix_time(modified, 0)).unwrap();
+        }
+        let config = BackupConfig {
+            min_mtime: Some(String::from("2024-01-01T00:00:00Z")),
+            max_mtime: Some(String::from("2024-01-02T00:00:00Z")),
+            ..BackupConfig::default()
+        };
+
+        assert_eq!(selected(&folder(&dir), &config), ["end", "inside", "start"]);
+    }
+
+    #[test]
+    fn modified_within_skips_older_files() {
+        let dir = tree(&[("old", ""), ("new", "")]);
+        let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 3600);
+        let old = two_hours_ago.duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        filetime::set_file_mtime(dir.path().join("old"), FileTime::from_unix_time(old, 0)).unwrap();
+        let config = BackupConfig { modified_within: Some(String::from("1h")), ..BackupConfig::default() };
+
+        assert_eq!(selected(&folder(&dir), &config), ["new"]);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::bail;
+use log::{debug, error, trace};
+use tempfile::{NamedTempFile, TempDir};
+use errors::Error;
+use errors::Error::{DestinationNotFoundError, DestinationNotWritableError, DestinationUnreachableError, HostKeyVerificationError};
+use settings::settings::{BackupDestination, RemoteDestination, SftpDestination};
+use crate::destination::Destination;
+use crate::metadata::MetadataPolicy;
+use crate::throttle::Throttle;
+
+/// Seconds the SSH connection shared by the sftp sessions of a backup stays open once unused
+const CONTROL_PERSIST_SECS: u32 = 60;
+
+/// Mode of the files written when permissions are not kept
+const DEFAULT_FILE_MODE: u32 = 0o644;
+
+/// Directory on an SSH server, written through the OpenSSH `sftp` client in batch mode.
+///
+/// Every operation runs a short sftp session, the sessions share a single SSH connection through
+/// OpenSSH connection multiplexing. Host keys are always verified against the known hosts file,
+/// an unknown or changed key fails the connection.
+#[derive(Debug)]
+pub struct Sftp {
+    config: SftpDestination,
+
+    /// Directory of the control socket of the shared connection
+    control_dir: Option<TempDir>,
+}
+
+/// Output of an sftp session that reached the server
+struct SftpOutput {
+    success: bool,
+
+    /// Output lines, without the echo of the commands
+    stdout: Vec<String>,
+
+    stderr: String,
+}
+
+impl SftpOutput {
+    fn not_found(&self) -> bool {
+        !self.success && (self.stderr.contains("not found") || self.stderr.contains("No such file"))
+    }
+}
+
+impl Sftp {
+    pub fn new(config: SftpDestination) -> Self {
+        let control_dir = match tempfile::Builder::new().prefix("emb-ssh").tempdir() {
+            Ok(control_dir) => { Some(control_dir) }
+            Err(err) => { debug!(target: "general", "Error creating the SSH control directory, connections will not be shared: {}", err); None }
+        };
+        Sftp { config, control_dir }
+    }
+
+    fn remote_path(&self, path: &Path) -> PathBuf {
+        Path::new(&self.config.remote_dir).join(path)
+    }
+
+    fn ssh_options(&self) -> Vec<String> {
+        let known_hosts = match &self.config.known_hosts {
+            Some(known_hosts) => { known_hosts.clone() }
+            None => { String::from("~/.ssh/known_hosts") }
+        };
+        let mut options = vec![
+            String::from("BatchMode=yes"),
+            String::from("StrictHostKeyChecking=yes"),
+            format!("UserKnownHostsFile={}", known_hosts),
+            String::from("IdentitiesOnly=yes"),
+            String::from("ConnectTimeout=15"),
+            String::from("ServerAliveInterval=15"),
+            format!("User={}", self.config.user),
+        ];
+        if let Some(control_dir) = &self.control_dir {
+            options.push(String::from("ControlMaster=auto"));
+            options.push(format!("ControlPath={}", control_dir.path().join("%C").to_string_lossy()));
+            options.push(format!("ControlPersist={}", CONTROL_PERSIST_SECS));
+        }
+        options
+    }
+
+    /// Error of a probe, connection failures make the destination unreachable
+    fn probe_error(&self, err: anyhow::Error) -> Error {
+        match err.downcast::<Error>() {
+            Ok(err) => { err }
+            Err(err) => { DestinationUnreachableError { destination: self.name(), reason: err.to_string() } }
+        }
+    }
+
+    /// Runs the sftp `commands` in a single session. The session stops at the first failing command,
+    /// unless it starts with '-'. Connection failures are returned as errors.
+    fn session(&self, commands: &[String]) -> anyhow::Result<SftpOutput> {
+        self.limited_session(commands, None)
+    }
+
+    /// Like `session`, sftp transfers no faster than `throttle` allows
+    fn limited_session(&self, commands: &[String], throttle: Option<&Throttle>) -> anyhow::Result<SftpOutput> {
+        let mut command = Command::new("sftp");
+        command.arg("-b").arg("-");
+        if let Some(throttle) = throttle {
+            // Limit in Kbit/s
+            command.arg("-l").arg((throttle.bytes_per_sec() * 8 / 1000).max(1).to_string());
+        }
+        for option in self.ssh_options() {
+            command.arg("-o").arg(option);
+        }
+        command.arg("-i").arg(&self.config.key_path)
+            .arg("-P").arg(self.config.port.unwrap_or(22).to_string())
+            .arg(&self.config.host)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = match command.spawn() {
+            Ok(child) => { child }
+            Err(err) => { error!(target: "general", "Error running sftp: {}", err); bail!(err) }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let mut script = commands.join("\n");
+            script.push('\n');
+            trace!(target: "general", "sftp session on {}:\n{}", self.config.host, script);
+            // The session may end before reading every command, e.g. when the connection fails
+            let _ = stdin.write_all(script.as_bytes());
+        }
+        let output = child.wait_with_output()?;
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+        // sftp exits with 255 when the SSH connection fails or breaks, with 1 when a command fails
+        if output.status.code() == Some(255) || output.status.code().is_none() {
+            let name = BackupDestination::Remote(RemoteDestination::Sftp(self.config.clone())).name();
+            if stderr.contains("Host key verification failed") || stderr.contains("REMOTE HOST IDENTIFICATION HAS CHANGED") {
+                error!(target: "general", "Host key verification of {} failed: {}", self.config.host, stderr.trim());
+                bail!(HostKeyVerificationError(self.config.host.clone()))
+            }
+            let reason = stderr.lines().find(|line| { !line.trim().is_empty() }).unwrap_or("connection closed").trim().to_string();
+            bail!(DestinationUnreachableError { destination: name, reason })
+        }
+
+        Ok(SftpOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).lines()
+                .filter(|line| { !line.starts_with("sftp> ") })
+                .map(|line| { line.to_string() })
+                .collect(),
+            stderr,
+        })
+    }
+
+    /// Runs the sftp `commands` in a single session, failing if any of them fails
+    fn run(&self, commands: &[String]) -> anyhow::Result<Vec<String>> {
+        self.limited_run(commands, None)
+    }
+
+    fn limited_run(&self, commands: &[String], throttle: Option<&Throttle>) -> anyhow::Result<Vec<String>> {
+        let output = self.limited_session(commands, throttle)?;
+        if !output.success {
+            bail!("sftp command failed on {}: {}", self.config.host, output.stderr.trim())
+        }
+        Ok(output.stdout)
+    }
+
+    /// Long listing of `path` (relative to the remote directory): the file itself or the content of a directory
+    fn list(&self, path: &Path) -> anyhow::Result<Option<Vec<ListEntry>>> {
+        let output = self.session(&[format!("ls -lan {}", quote(&self.remote_path(path))?)])?;
+        if output.not_found() {
+            return Ok(None);
+        }
+        if !output.success {
+            bail!("sftp command failed on {}: {}", self.config.host, output.stderr.trim())
+        }
+        Ok(Some(output.stdout.iter().filter_map(|line| { ListEntry::parse(line) }).collect()))
+    }
+
+    /// Writes the local file `source` to the remote `path`, then reads back its size
+    fn upload(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+        let remote = quote(&self.remote_path(path))?;
+        let mut commands = vec![format!("-rm {}", remote)];
+        // 'put -p' sets mode, access and modification time, a plain 'put' creates the file with the
+        // local mode masked by the server umask
+        if policy.mtime {
+            commands.push(format!("put -p {} {}", quote(source)?, remote));
+        } else {
+            commands.push(format!("put {} {}", quote(source)?, remote));
+        }
+        if !policy.permissions {
+            commands.push(format!("chmod {:o} {}", DEFAULT_FILE_MODE, remote));
+        } else if !policy.mtime {
+            commands.push(format!("chmod {:o} {}", file_mode(source)?, remote));
+        }
+        commands.push(format!("ls -lan {}", remote));
+
+        let output = self.limited_run(&commands, throttle)?;
+        match output.iter().rev().find_map(|line| { ListEntry::parse(line) }) {
+            Some(entry) => { Ok(entry.size) }
+            None => { bail!("sftp listing of {:?} on {} not readable", path, self.config.host) }
+        }
+    }
+
+    /// Files and directories under `path`
+    fn tree(&self, path: &Path) -> anyhow::Result<Tree> {
+        let mut files: Vec<(PathBuf, u64)> = vec![];
+        let mut dirs: Vec<PathBuf> = vec![];
+        let mut pending: Vec<PathBuf> = vec![path.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for entry in self.list(&dir)?.unwrap_or_default() {
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                let entry_path = dir.join(&entry.name);
+                if entry.is_dir {
+                    pending.push(entry_path);
+                } else {
+                    files.push((entry_path, entry.size));
+                }
+            }
+            dirs.push(dir);
+        }
+        dirs.reverse();
+        Ok((files, dirs))
+    }
+}
+
+impl Drop for Sftp {
+    /// Closes the shared connection, the control socket directory is removed right after
+    fn drop(&mut self) {
+        let Some(control_dir) = &self.control_dir else { return };
+        if fs::read_dir(control_dir.path()).map(|mut entries| { entries.next().is_none() }).unwrap_or(true) {
+            return;
+        }
+        let _ = Command::new("ssh")
+            .arg("-o").arg(format!("ControlPath={}", control_dir.path().join("%C").to_string_lossy()))
+            .arg("-o").arg(format!("User={}", self.config.user))
+            .arg("-p").arg(self.config.port.unwrap_or(22).to_string())
+            .arg("-O").arg("exit")
+            .arg(&self.config.host)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+}
+
+impl Destination for Sftp {
+    fn name(&self) -> String {
+        BackupDestination::Remote(RemoteDestination::Sftp(self.config.clone())).name()
+    }
+
+    /// The remote directory must exist and a file must be writable in it
+    fn probe(&self) -> Result<(), Error> {
+        self.probe_read_only()?;
+
+        let to_error = |err: anyhow::Error| -> Error { self.probe_error(err) };
+        let probe_file = NamedTempFile::new().map_err(|err| { to_error(err.into()) })?;
+        let probe_path = quote(&self.remote_path(Path::new(".emergency-backup-probe"))).map_err(to_error)?;
+        let output = self.session(&[
+            format!("put {} {}", quote(probe_file.path()).map_err(to_error)?, probe_path),
+            format!("rm {}", probe_path),
+        ]).map_err(to_error)?;
+        if !output.success {
+            debug!(target: "general", "Destination {} not writable: {}", self.name(), output.stderr.trim());
+            return Err(DestinationNotWritableError(self.name()));
+        }
+        Ok(())
+    }
+
+    /// The server must accept the connection and the remote directory must exist
+    fn probe_read_only(&self) -> Result<(), Error> {
+        let to_error = |err: anyhow::Error| -> Error { self.probe_error(err) };
+        let remote_dir = quote(Path::new(&self.config.remote_dir)).map_err(to_error)?;
+        let output = self.session(&[format!("cd {}", remote_dir)]).map_err(to_error)?;
+        if !output.success {
+            debug!(target: "general", "Remote directory of {} not found: {}", self.name(), output.stderr.trim());
+            return Err(DestinationNotFoundError(self.name()));
+        }
+        Ok(())
+    }
+
+    /// Free space reported by the server, when it supports the statvfs extension of OpenSSH
+    fn available_space(&self) -> Option<u64> {
+        let remote_dir = quote(Path::new(&self.config.remote_dir)).ok()?;
+        match self.run(&[format!("df {}", remote_dir)]) {
+            Ok(output) => {
+                // Size, Used, Avail, (root), %Capacity in KiB, under a header line
+                let values = output.iter().rev().find(|line| { !line.trim().is_empty() })?;
+                values.split_whitespace().nth(2)?.parse::<u64>().ok().map(|kib| { kib * 1024 })
+            }
+            Err(err) => { debug!(target: "general", "Error reading free space of {}: {}", self.name(), err); None }
+        }
+    }
+
+    fn file_size(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+        Ok(self.list(path)?.and_then(|entries| { entries.first().map(|entry| { entry.size }) }))
+    }
+
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(self.list(path)?.is_some())
+    }
+
+    /// One 'ls' per directory of the tree
+    fn list_files(&self, path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>> {
+        let (files, _) = self.tree(path)?;
+        Ok(files.into_iter()
+            .filter_map(|(file, size)| { file.strip_prefix(path).ok().map(|relative_path| { (relative_path.to_path_buf(), size) }) })
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+        let mut commands: Vec<String> = vec![];
+        let mut current = PathBuf::new();
+        for component in path.components() {
+            current.push(component);
+            commands.push(format!("-mkdir {}", quote(&self.remote_path(&current))?));
+        }
+        if !commands.is_empty() {
+            self.run(&commands)?;
+        }
+        Ok(())
+    }
+
+    /// Symbolic links are followed: SFTP can not read a link back, so a restore could not recreate it.
+    /// Extended attributes and ACLs are not kept.
+    fn put_file(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+        let size = fs::metadata(source)?.len();
+        let written = self.upload(source, path, policy, throttle)?;
+        if written != size {
+            bail!("Size of {:?} on {} does not match the source: {} bytes instead of {}", path, self.config.host, written, size)
+        }
+        // sftp paces each transfer to the whole limit, the shared throttle keeps the copy workers together within it
+        if let Some(throttle) = throttle {
+            throttle.consume(written);
+        }
+        Ok(written)
+    }
+
+    fn get_file(&self, path: &Path, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<u64> {
+        let _ = fs::remove_file(target);
+        let flags = if policy.mtime { "-p " } else { "" };
+        self.run(&[format!("get {}{} {}", flags, quote(&self.remote_path(path))?, quote(target)?)])?;
+        #[cfg(target_family = "unix")]
+        if !policy.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(target, fs::Permissions::from_mode(DEFAULT_FILE_MODE))?;
+        }
+        Ok(fs::metadata(target)?.len())
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        let local = NamedTempFile::new()?;
+        let output = self.session(&[format!("get {} {}", quote(&self.remote_path(path))?, quote(local.path())?)])?;
+        if output.not_found() {
+            return Ok(None);
+        }
+        if !output.success {
+            bail!("sftp command failed on {}: {}", self.config.host, output.stderr.trim())
+        }
+        Ok(Some(fs::read(local.path())?))
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        let mut local = NamedTempFile::new()?;
+        local.write_all(content)?;
+        local.flush()?;
+        self.run(&[format!("put {} {}", quote(local.path())?, quote(&self.remote_path(path))?)])?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        self.run(&[format!("rename {} {}", quote(&self.remote_path(from))?, quote(&self.remote_path(to))?)])?;
+        Ok(())
+    }
+
+    fn remove_all(&self, path: &Path) -> anyhow::Result<()> {
+        let remote = quote(&self.remote_path(path))?;
+        let output = self.session(&[format!("rm {}", remote)])?;
+        if output.success || output.not_found() {
+            return Ok(());
+        }
+
+        // Not a file: the directory content is removed first, deepest directories first
+        let (files, dirs) = self.tree(path)?;
+        let mut commands: Vec<String> = vec![];
+        for (file, _) in files {
+            commands.push(format!("rm {}", quote(&self.remote_path(&file))?));
+        }
+        for dir in dirs {
+            commands.push(format!("rmdir {}", quote(&self.remote_path(&dir))?));
+        }
+        self.run(&commands)?;
+        Ok(())
+    }
+}
+
+/// Paths and sizes of the files and paths of the directories of a tree, directories after their content
+type Tree = (Vec<(PathBuf, u64)>, Vec<PathBuf>);
+
+/// Line of an 'ls -lan' listing
+struct ListEntry {
+    is_dir: bool,
+
+    size: u64,
+
+    name: String,
+}
+
+impl ListEntry {
+    /// Parses 'drwxr-xr-x    2 1000     1000         4096 Oct 19 10:00 name', the name may contain spaces
+    fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_start();
+        let mut fields: Vec<&str> = vec![];
+        for _ in 0..8 {
+            let end = rest.find(char::is_whitespace)?;
+            fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        if rest.is_empty() || fields[0].len() != 10 {
+            return None;
+        }
+        // Entries of a directory are listed with the directory path in front
+        Some(ListEntry {
+            is_dir: fields[0].starts_with('d'),
+            size: fields[4].parse().ok()?,
+            name: rest.rsplit('/').next()?.to_string(),
+        })
+    }
+}
+
+/// Quotes a path for an sftp batch command, glob characters inside the quotes are taken literally
+fn quote(path: &Path) -> anyhow::Result<String> {
+    let path = path.to_string_lossy();
+    if path.contains('\n') || path.contains('\r') {
+        bail!("Path {:?} can not be written through sftp", path)
+    }
+    Ok(format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+#[cfg(target_family = "unix")]
+fn file_mode(path: &Path) -> anyhow::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::metadata(path)?.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(target_family = "unix"))]
+fn file_mode(path: &Path) -> anyhow::Result<u32> {
+    Ok(if fs::metadata(path)?.permissions().readonly() { 0o444 } else { DEFAULT_FILE_MODE })
+}
@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use anyhow::bail;
+use log::error;
+use serde::{Deserialize, Serialize};
+use settings::settings::{BackupConfig, BackupSource};
+use crate::copy::{FailedFile, BYTE_BUDGET_REASON, TIME_BUDGET_REASON};
+use crate::destination::Destination;
+use crate::manifest::{Manifest, SkippedEntry};
+use crate::plan::PlannedFile;
+use crate::staging::metadata_path;
+
+/// Name of the report file, written in the metadata directory of the snapshot (see `staging::metadata_path`)
+pub const SNAPSHOT_REPORT_FILE_NAME: &str = "report.json";
+
+/// Increased on every change that is not only the addition of a field
+pub const SNAPSHOT_REPORT_VERSION: u32 = 1;
+
+/// File skipped or not copied because of an error
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportedFile {
+    /// Path relative to the snapshot root
+    pub path: PathBuf,
+    pub source: String,
+    pub size: u64,
+
+    /// 'time_budget' or 'byte_budget' for skipped files, the kind of the error otherwise, e.g. 'permission_denied'
+    pub kind: String,
+
+    /// Error message, only for files not copied because of an error
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+impl From<&SkippedEntry> for ReportedFile {
+    fn from(skipped: &SkippedEntry) -> Self {
+        let kind = match skipped.reason.as_str() {
+            TIME_BUDGET_REASON => { "time_budget" }
+            BYTE_BUDGET_REASON => { "byte_budget" }
+            _ => { "other" }
+        };
+        ReportedFile {
+            path: skipped.path.clone(),
+            source: skipped.source.clone(),
+            size: skipped.size,
+            kind: kind.to_string(),
+            message: None,
+        }
+    }
+}
+
+impl From<&FailedFile> for ReportedFile {
+    fn from(failed: &FailedFile) -> Self {
+        ReportedFile {
+            path: failed.path.clone(),
+            source: failed.source.clone(),
+            size: failed.size,
+            kind: failed.kind.clone(),
+            message: Some(failed.message.clone()),
+        }
+    }
+}
+
+/// Selection rules of the configuration the backup ran with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReportFilters {
+    pub sources: Vec<BackupSource>,
+    pub extension_only: bool,
+    pub extension_type: Vec<String>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub exclude_presets: Vec<String>,
+    pub max_file_size: Option<String>,
+    pub modified_within: Option<String>,
+    pub min_mtime: Option<String>,
+    pub max_mtime: Option<String>,
+    pub symlink_policy: String,
+    pub time_budget: Option<String>,
+    pub byte_budget: Option<String>,
+}
+
+impl From<&BackupConfig> for ReportFilters {
+    fn from(config: &BackupConfig) -> Self {
+        ReportFilters {
+            sources: config.backup_sources.clone(),
+            extension_only: config.extension_only,
+            extension_type: config.extension_type.clone(),
+            include: config.include.clone(),
+            exclude: config.exclude.clone(),
+            exclude_presets: config.exclude_presets.clone(),
+            max_file_size: config.max_file_size.clone(),
+            modified_within: config.modified_within.clone(),
+            min_mtime: config.min_mtime.clone(),
+            max_mtime: config.max_mtime.clone(),
+            symlink_policy: config.symlink_policy.clone(),
+            time_budget: config.time_budget.clone(),
+            byte_budget: config.byte_budget.clone(),
+        }
+    }
+}
+
+/// Machine-readable outcome of the backup on a destination, written into its snapshot and kept in the backup history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotReport {
+    pub version: u32,
+
+    /// Id of the backup, the same in the manifest of the snapshot
+    pub snapshot_id: String,
+
+    pub destination: String,
+
+    /// 'completed', 'partial' or 'failed'
+    pub status: String,
+
+    /// RFC 3339 start time of the backup
+    pub started_at: String,
+
+    /// Wall time and CPU time of the process spent writing the snapshot into the destination
+    pub wall_time_ms: u64,
+    pub cpu_time_ms: u64,
+
+    pub files_selected: usize,
+    pub bytes_selected: u64,
+    pub files_copied: usize,
+    pub bytes_copied: u64,
+
+    /// Files left out because of the budget
+    pub skipped: Vec<ReportedFile>,
+
+    /// Files not copied because of an error, the snapshot has not been written if any
+    pub errors: Vec<ReportedFile>,
+
+    pub filters: ReportFilters,
+}
+
+impl SnapshotReport {
+    /// Report of a copy of `plan` into `destination` that did not write anything yet
+    pub fn new(snapshot_id: &str, destination: &dyn Destination, started_at: SystemTime, plan: &[PlannedFile], config: &BackupConfig) -> Self {
+        SnapshotReport {
+            version: SNAPSHOT_REPORT_VERSION,
+            snapshot_id: snapshot_id.to_string(),
+            destination: destination.name(),
+            status: String::from("failed"),
+            started_at: chrono::DateTime::<chrono::Local>::from(started_at).to_rfc3339(),
+            wall_time_ms: 0,
+            cpu_time_ms: 0,
+            files_selected: plan.len(),
+            bytes_selected: plan.iter().map(|planned| { planned.file.size }).sum(),
+            files_copied: 0,
+            bytes_copied: 0,
+            skipped: vec![],
+            errors: vec![],
+            filters: ReportFilters::from(config),
+        }
+    }
+
+    /// Fills the copied and skipped files from the manifest of the snapshot
+    pub fn with_manifest(mut self, manifest: &Manifest) -> Self {
+        self.status = String::from(if manifest.partial { "partial" } else { "completed" });
+        self.files_copied = manifest.files.len();
+        self.bytes_copied = manifest.copied_bytes();
+        self.skipped = manifest.skipped.iter().map(ReportedFile::from).collect();
+        self
+    }
+
+    pub fn with_times(mut self, wall_time: Duration, cpu_time: Duration) -> Self {
+        self.wall_time_ms = wall_time.as_millis() as u64;
+        self.cpu_time_ms = cpu_time.as_millis() as u64;
+        self
+    }
+
+    /// Writes the report into `snapshot_path`, relative to the root of `destination`
+    pub fn write(&self, destination: &dyn Destination, snapshot_path: &Path) -> anyhow::Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        match destination.write(&metadata_path(snapshot_path).join(SNAPSHOT_REPORT_FILE_NAME), content.as_bytes()) {
+            Ok(_) => { Ok(()) }
+            Err(err) => { error!(target: "general", "Error writing the snapshot report into {:?} on {}: {}", snapshot_path, destination.name(), err); bail!(err) }
+        }
+    }
+
+    /// Reads the report of `snapshot_path`, relative to the root of `destination`
+    pub fn read(destination: &dyn Destination, snapshot_path: &Path) -> anyhow::Result<Self> {
+        match destination.read(&metadata_path(snapshot_path).join(SNAPSHOT_REPORT_FILE_NAME))? {
+            Some(content) => { Ok(serde_json::from_slice(&content)?) }
+            None => { bail!("No report in {:?} on {}", snapshot_path, destination.name()) }
+        }
+    }
+}
+
+/// Stable name of the kind of `err`: the kind of the I/O error in its chain in snake case (e.g. 'permission_denied',
+/// 'not_found'), the variant of an `errors::Error` (e.g. 'remote_transfer'), 'other' otherwise
+pub fn error_kind(err: &anyhow::Error) -> String {
+    for cause in err.chain() {
+        if let Some(io_error) = cause.downcast_ref::<std::io::Error>() {
+            return snake_case(&format!("{:?}", io_error.kind()));
+        }
+        if let Some(error) = cause.downcast_ref::<errors::Error>() {
+            let variant: String = format!("{:?}", error).chars().take_while(|c| { c.is_alphanumeric() }).collect();
+            return snake_case(variant.strip_suffix("Error").unwrap_or(&variant));
+        }
+    }
+    String::from("other")
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (index, c) in name.chars().enumerate() {
+        if c.is_uppercase() && index > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
@@ -0,0 +1,107 @@
+use std::path::{Path, PathBuf};
+use log::{info, warn};
+use settings::settings::SNAPSHOT_METADATA_DIR_NAME;
+use crate::destination::Destination;
+use crate::journal::Journal;
+use crate::manifest::Manifest;
+
+/// Name of the snapshot directory inside a destination
+pub const SNAPSHOT_DIR_NAME: &str = "emergency-backup";
+
+/// Directory a snapshot is written into, renamed to `SNAPSHOT_DIR_NAME` once complete
+pub const STAGING_DIR_NAME: &str = "emergency-backup.staging";
+
+/// Name the previous snapshot is moved to while the new one takes its place
+pub const PREVIOUS_DIR_NAME: &str = "emergency-backup.previous";
+
+/// Snapshot directory of a local destination
+pub fn snapshot_path(destination: &Path) -> PathBuf {
+    destination.join(SNAPSHOT_DIR_NAME)
+}
+
+/// Staging directory of a local destination
+pub fn staging_path(destination: &Path) -> PathBuf {
+    destination.join(STAGING_DIR_NAME)
+}
+
+/// Directory of `snapshot_path` holding the files written by the tool, apart from the files of the sources
+pub fn metadata_path(snapshot_path: &Path) -> PathBuf {
+    snapshot_path.join(SNAPSHOT_METADATA_DIR_NAME)
+}
+
+/// Replaces the snapshot of `destination` with the staging one. The staging manifest must carry the
+/// completion marker, the previous snapshot is removed only once the new one is in place, together with
+/// the partial uploads left on the destination.
+pub fn finalize(destination: &dyn Destination) -> anyhow::Result<()> {
+    let snapshot = Path::new(SNAPSHOT_DIR_NAME);
+    let staging = Path::new(STAGING_DIR_NAME);
+    let previous = Path::new(PREVIOUS_DIR_NAME);
+
+    Journal::remove(destination, staging)?;
+    if destination.exists(snapshot)? {
+        destination.rename(snapshot, previous)?;
+    }
+    destination.rename(staging, snapshot)?;
+    destination.remove_all(previous)?;
+    remove_partial_uploads(destination);
+    Ok(())
+}
+
+/// Removes the partial uploads nothing can resume anymore, a failure only leaves them for the next backup
+fn remove_partial_uploads(destination: &dyn Destination) {
+    if let Err(err) = destination.remove_partial_uploads() {
+        warn!(target: "general", "Destination {}: partial uploads not removed: {}", destination.name(), err);
+    }
+}
+
+/// Detects the leftovers of a backup interrupted on `destination` and brings it back to a consistent state:
+/// - a complete staging snapshot that was not renamed yet is finalized,
+/// - an incomplete staging snapshot is kept to be resumed when `resume` is set and its journal lists
+///   some copied files, removed otherwise together with the partial uploads left on the destination,
+/// - a previous snapshot left aside is restored if no snapshot took its place, removed otherwise.
+///
+/// Returns a description of every action taken.
+pub fn recover(destination: &dyn Destination, resume: bool) -> anyhow::Result<Vec<String>> {
+    let mut actions: Vec<String> = vec![];
+    let staging = Path::new(STAGING_DIR_NAME);
+    let snapshot = Path::new(SNAPSHOT_DIR_NAME);
+    let previous = Path::new(PREVIOUS_DIR_NAME);
+
+    if destination.exists(staging)? {
+        let complete = Manifest::read(destination, staging).map(|manifest| { manifest.complete }).unwrap_or(false);
+        if complete {
+            finalize(destination)?;
+            actions.push(String::from("completed snapshot of an interrupted backup finalized"));
+        } else {
+            let copied_files = Journal::read(destination, staging)?.len();
+            if resume && copied_files > 0 {
+                actions.push(format!("incomplete snapshot of an interrupted backup kept to be resumed, {} files already copied", copied_files));
+            } else {
+                destination.remove_all(staging)?;
+                remove_partial_uploads(destination);
+                if copied_files > 0 {
+                    actions.push(format!("incomplete snapshot of an interrupted backup removed, {} copied files discarded", copied_files));
+                } else {
+                    actions.push(String::from("incomplete snapshot of an interrupted backup removed"));
+                }
+            }
+        }
+    }
+
+    if destination.exists(previous)? {
+        if destination.exists(snapshot)? {
+            destination.remove_all(previous)?;
+        } else {
+            destination.rename(previous, snapshot)?;
+            actions.push(String::from("previous snapshot restored"));
+        }
+    }
+
+    for action in &actions {
+        warn!(target: "general", "Destination {}: {}", destination.name(), action);
+    }
+    if actions.is_empty() {
+        info!(target: "general", "Destination {}: no interrupted backup found", destination.name());
+    }
+    Ok(actions)
+}
@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use log::{debug, warn};
+
+/// Size of the chunks a throttled copy reads and writes
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Bandwidth limit shared by the copy workers of a destination
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: u64,
+
+    /// Start of the copy and bytes written since then
+    state: Mutex<(Instant, u64)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Throttle { bytes_per_sec: bytes_per_sec.max(1), state: Mutex::new((Instant::now(), 0)) }
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Accounts for `bytes` written and waits until the average rate is back within the limit
+    pub fn consume(&self, bytes: u64) {
+        let wait = match self.state.lock() {
+            Ok(mut state) => {
+                state.1 += bytes;
+                let allowed_at = state.0 + Duration::from_secs_f64(state.1 as f64 / self.bytes_per_sec as f64);
+                allowed_at.saturating_duration_since(Instant::now())
+            }
+            Err(_) => { Duration::ZERO }
+        };
+        if !wait.is_zero() {
+            sleep(wait);
+        }
+    }
+}
+
+/// Reader handing out the data of `inner` in chunks, waiting on the throttle after reading each one.
+/// Used as the body of an upload, it paces the transfer itself. Without throttle it only reads `inner`.
+pub struct ThrottledReader<'a, R> {
+    inner: R,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<'a, R: Read> ThrottledReader<'a, R> {
+    pub fn new(inner: R, throttle: Option<&'a Throttle>) -> Self {
+        ThrottledReader { inner, throttle }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let Some(throttle) = self.throttle else { return self.inner.read(buffer) };
+        let length = buffer.len().min(CHUNK_SIZE);
+        let read = self.inner.read(&mut buffer[..length])?;
+        throttle.consume(read as u64);
+        Ok(read)
+    }
+}
+
+/// Copies `source` to `target` in chunks, waiting on `throttle` after each one. Returns the bytes copied.
+pub fn copy_throttled(source: &Path, target: &Path, throttle: &Throttle) -> std::io::Result<u64> {
+    let mut reader = ThrottledReader::new(File::open(source)?, Some(throttle));
+    let mut writer = File::create(target)?;
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut copied = 0u64;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read])?;
+        copied += read as u64;
+    }
+    Ok(copied)
+}
+
+/// Lowers the CPU and I/O priority of the calling thread, the threads it spawns afterwards inherit it.
+/// On Linux it is the equivalent of `nice -n 19` and `ionice -c 3`, on other Unix systems the whole
+/// process gets the lowest CPU priority, on Windows the thread enters the background processing mode.
+pub fn lower_priority() {
+    #[cfg(target_os = "linux")]
+    {
+        // With PRIO_PROCESS and 0 Linux changes only the calling thread
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            warn!(target: "general", "Error lowering the backup CPU priority: {}", std::io::Error::last_os_error());
+        }
+        const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+        const IOPRIO_CLASS_IDLE: libc::c_long = 3;
+        const IOPRIO_CLASS_SHIFT: libc::c_long = 13;
+        if unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT) } != 0 {
+            warn!(target: "general", "Error lowering the backup I/O priority: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(all(target_family = "unix", not(target_os = "linux")))]
+    {
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 19) } != 0 {
+            warn!(target: "general", "Error lowering the backup CPU priority: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    #[cfg(target_family = "windows")]
+    {
+        use windows_sys::Win32::System::Threading::{GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN};
+        if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) } == 0 {
+            warn!(target: "general", "Error entering the background processing mode: {}", std::io::Error::last_os_error());
+        }
+    }
+
+    debug!(target: "general", "Backup running with low priority");
+}
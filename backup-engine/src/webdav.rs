@@ -0,0 +1,579 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, UNIX_EPOCH};
+use anyhow::bail;
+use base64::Engine;
+use filetime::FileTime;
+use log::{debug, trace, warn};
+use sha2::{Digest, Sha256};
+use errors::Error;
+use errors::Error::{DestinationNotFoundError, DestinationNotWritableError, DestinationUnreachableError, RemoteDestinationConfigError};
+use settings::settings::{BackupDestination, RemoteDestination, WebDavDestination};
+use crate::destination::{expand_home, Destination};
+use crate::metadata::MetadataPolicy;
+use crate::s3::uri_encode;
+use crate::throttle::{Throttle, ThrottledReader};
+
+/// Size of the chunks of an upload to a Nextcloud or ownCloud server, smaller files are written with a single PUT
+pub const CHUNK_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Attempts of a request failing with a network error or a server error
+const ATTEMPTS: u32 = 3;
+
+/// Modification time of an upload, in seconds, kept by Nextcloud and ownCloud
+const MTIME_HEADER: &str = "X-OC-Mtime";
+
+const PROPFIND_BODY: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+    <d:propfind xmlns:d=\"DAV:\"><d:prop><d:resourcetype/><d:getcontentlength/><d:quota-available-bytes/></d:prop></d:propfind>";
+
+/// Folder on a WebDAV server, with basic authentication.
+///
+/// On Nextcloud and ownCloud servers (URLs under `/remote.php/`) files larger than a chunk are uploaded in
+/// chunks to the uploads collection of the user, then assembled on the server. The chunks already on the server
+/// are not uploaded again, so an interrupted upload resumes where it stopped at the next attempt.
+pub struct WebDav {
+    config: WebDavDestination,
+
+    /// URL of the destination folder, without the trailing slash
+    root: String,
+
+    /// Collection of the chunked uploads, `None` on servers without chunked uploads
+    uploads: Option<String>,
+
+    /// Basic authorization header
+    authorization: Result<String, String>,
+
+    agent: ureq::Agent,
+}
+
+/// Body of a request
+#[derive(Clone, Copy)]
+enum Body<'a> {
+    Empty,
+    Bytes(&'a [u8]),
+    /// Streamed from the file, opened again at every attempt
+    File(&'a Path),
+}
+
+/// Error response of the server
+#[derive(Debug)]
+struct DavError {
+    status: u16,
+
+    message: String,
+}
+
+impl Display for DavError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "WebDAV error {}: {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for DavError {}
+
+fn status_of(err: &anyhow::Error) -> Option<u16> {
+    err.downcast_ref::<DavError>().map(|err| { err.status })
+}
+
+/// Resource listed by a PROPFIND request
+struct Resource {
+    /// Decoded path of the URL, see `href_path`
+    path: String,
+
+    /// Last segment of the URL
+    name: String,
+
+    collection: bool,
+
+    size: Option<u64>,
+
+    available_space: Option<u64>,
+}
+
+impl Debug for WebDav {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebDav").field("config", &self.config).finish()
+    }
+}
+
+impl WebDav {
+    pub fn new(config: WebDavDestination) -> Self {
+        let root = config.url.trim_end_matches('/').to_string();
+        let uploads = root.find("/remote.php/")
+            .map(|index| { format!("{}/remote.php/dav/uploads/{}", &root[..index], uri_encode(&config.user, false)) });
+        let authorization = load_password(&config)
+            .map(|password| { format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", config.user, password))) });
+        if let Err(err) = &authorization {
+            debug!(target: "general", "No password for {}: {}", config.url, err);
+        }
+        WebDav {
+            root,
+            uploads,
+            authorization,
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(15))
+                .timeout_read(Duration::from_secs(120))
+                .timeout_write(Duration::from_secs(120))
+                .build(),
+            config,
+        }
+    }
+
+    /// URL of `path`, relative to the destination folder
+    fn url(&self, path: &Path) -> String {
+        let mut url = self.root.clone();
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                url.push('/');
+                url.push_str(&uri_encode(&name.to_string_lossy(), false));
+            }
+        }
+        url
+    }
+
+    /// Sends a request, retrying on network and server errors.
+    /// Error responses are returned as `DavError`, network errors as `DestinationUnreachableError`.
+    fn send(&self, method: &str, url: &str, headers: &[(&str, String)], body: Body) -> anyhow::Result<ureq::Response> {
+        self.send_throttled(method, url, headers, body, None)
+    }
+
+    /// Like `send`, the body is streamed no faster than `throttle` allows
+    fn send_throttled(&self, method: &str, url: &str, headers: &[(&str, String)], body: Body, throttle: Option<&Throttle>) -> anyhow::Result<ureq::Response> {
+        let mut attempt = 1;
+        loop {
+            match self.send_once(method, url, headers, body, throttle) {
+                Ok(response) => { return Ok(response) }
+                Err(err) => {
+                    let retry = match status_of(&err) {
+                        Some(status) => { status >= 500 }
+                        None => { err.downcast_ref::<Error>().map(|err| { matches!(err, DestinationUnreachableError { .. }) }).unwrap_or(false) }
+                    };
+                    if !retry || attempt >= ATTEMPTS {
+                        return Err(err);
+                    }
+                    debug!(target: "general", "{} {} failed, attempt {} of {}: {}", method, url, attempt, ATTEMPTS, err);
+                    sleep(Duration::from_secs(attempt as u64));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn send_once(&self, method: &str, url: &str, headers: &[(&str, String)], body: Body, throttle: Option<&Throttle>) -> anyhow::Result<ureq::Response> {
+        let authorization = match &self.authorization {
+            Ok(authorization) => { authorization }
+            Err(err) => { bail!(RemoteDestinationConfigError(err.clone())) }
+        };
+        let mut request = self.agent.request(method, url).set("Authorization", authorization);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+        trace!(target: "general", "{} {}", method, url);
+
+        let result = match body {
+            Body::Empty => { request.call() }
+            Body::Bytes(bytes) if throttle.is_some() => {
+                request.set("Content-Length", &bytes.len().to_string()).send(ThrottledReader::new(bytes, throttle))
+            }
+            Body::Bytes(bytes) => { request.send_bytes(bytes) }
+            Body::File(path) => {
+                let file = File::open(path)?;
+                request.set("Content-Length", &file.metadata()?.len().to_string()).send(ThrottledReader::new(file, throttle))
+            }
+        };
+        match result {
+            Ok(response) => { Ok(response) }
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                Err(error_response(status, &body).into())
+            }
+            Err(ureq::Error::Transport(transport)) => {
+                bail!(DestinationUnreachableError { destination: self.name(), reason: transport.to_string() })
+            }
+        }
+    }
+
+    /// Error of a probe, refused credentials and connection failures make the destination unreachable
+    fn probe_error(&self, err: anyhow::Error) -> Error {
+        if matches!(status_of(&err), Some(401) | Some(403)) {
+            return DestinationUnreachableError { destination: self.name(), reason: String::from("authentication failed, check the user and the password") };
+        }
+        match err.downcast::<Error>() {
+            Ok(err) => { err }
+            Err(err) => { DestinationUnreachableError { destination: self.name(), reason: err.to_string() } }
+        }
+    }
+
+    /// Resources at `url` (depth 0) or in the collection at `url` (depth 1, the collection included),
+    /// `None` if there is nothing at `url`
+    fn propfind(&self, url: &str, depth: u8) -> anyhow::Result<Option<Vec<Resource>>> {
+        let headers = [("Depth", depth.to_string()), ("Content-Type", String::from("application/xml; charset=utf-8"))];
+        match self.send("PROPFIND", url, &headers, Body::Bytes(PROPFIND_BODY.as_bytes())) {
+            Ok(response) => { Ok(Some(parse_multistatus(&response.into_string()?)?)) }
+            Err(err) if status_of(&err) == Some(404) => { Ok(None) }
+            Err(err) => { Err(err) }
+        }
+    }
+
+    /// Members of the collection at `url`, empty if there is nothing at `url`
+    fn members(&self, url: &str) -> anyhow::Result<Vec<Resource>> {
+        Ok(members(self.propfind(url, 1)?.unwrap_or_default(), url))
+    }
+
+    /// Start of the name of the chunked uploads of this destination folder, so that the uploads of another
+    /// folder of the same user are never taken for stale ones
+    fn upload_prefix(&self) -> String {
+        format!("emergency-backup-{}-", &hex::encode(Sha256::digest(self.root.as_bytes()))[..8])
+    }
+
+    /// Uploads a file larger than a chunk to the uploads collection, then moves the assembled file to `url`.
+    /// The upload is named after the target and the size and modification time of the file, so that the
+    /// next attempt to upload the same file finds the chunks already written.
+    fn chunked_upload(&self, uploads: &str, source: &Path, url: &str, size: u64, mtime: Option<u64>, throttle: Option<&Throttle>) -> anyhow::Result<()> {
+        let modified = fs::metadata(source)?.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let id = hex::encode(Sha256::digest(format!("{}\n{}\n{}", url, size, modified.as_nanos())));
+        let upload = format!("{}/{}{}", uploads, self.upload_prefix(), &id[..24]);
+        let destination = [("Destination", url.to_string()), ("OC-Total-Length", size.to_string())];
+
+        let uploaded: HashMap<String, u64> = match self.propfind(&upload, 1)? {
+            Some(resources) => {
+                let uploaded: HashMap<String, u64> = resources.into_iter()
+                    .filter(|resource| { !resource.collection })
+                    .map(|resource| { (resource.name, resource.size.unwrap_or(0)) })
+                    .collect();
+                debug!(target: "general", "Resuming the upload of {:?}, {} chunks already on the server", source, uploaded.len());
+                uploaded
+            }
+            None => {
+                self.send("MKCOL", &upload, &destination, Body::Empty)?;
+                HashMap::new()
+            }
+        };
+
+        let mut file = File::open(source)?;
+        let mut buffer = vec![0u8; CHUNK_SIZE as usize];
+        let mut offset = 0u64;
+        let mut chunk = 1;
+        while offset < size {
+            let length = CHUNK_SIZE.min(size - offset);
+            // Chunk names sort in upload order
+            let name = format!("{:05}", chunk);
+            if uploaded.get(&name) != Some(&length) {
+                file.seek(SeekFrom::Start(offset))?;
+                file.read_exact(&mut buffer[..length as usize])?;
+                self.send_throttled("PUT", &format!("{}/{}", upload, name), &destination, Body::Bytes(&buffer[..length as usize]), throttle)?;
+            }
+            offset += length;
+            chunk += 1;
+        }
+
+        let mut headers = destination.to_vec();
+        headers.push(("Overwrite", String::from("T")));
+        if let Some(mtime) = mtime {
+            headers.push((MTIME_HEADER, mtime.to_string()));
+        }
+        self.send("MOVE", &format!("{}/.file", upload), &headers, Body::Empty)?;
+        Ok(())
+    }
+}
+
+impl Destination for WebDav {
+    fn name(&self) -> String {
+        BackupDestination::Remote(RemoteDestination::WebDav(self.config.clone())).name()
+    }
+
+    /// The folder must exist and a file must be writable in it
+    fn probe(&self) -> Result<(), Error> {
+        self.probe_read_only()?;
+
+        let probe_url = self.url(Path::new(".emergency-backup-probe"));
+        match self.send("PUT", &probe_url, &[], Body::Empty) {
+            Ok(_) => { let _ = self.send("DELETE", &probe_url, &[], Body::Empty); Ok(()) }
+            Err(err) if matches!(status_of(&err), Some(401) | None) => { Err(self.probe_error(err)) }
+            Err(err) => {
+                debug!(target: "general", "Destination {} not writable: {}", self.name(), err);
+                Err(DestinationNotWritableError(self.name()))
+            }
+        }
+    }
+
+    /// The folder must exist, checked with a PROPFIND request
+    fn probe_read_only(&self) -> Result<(), Error> {
+        match self.propfind(&self.root, 0) {
+            Ok(Some(resources)) if resources.first().map(|resource| { resource.collection }).unwrap_or(false) => { Ok(()) }
+            Ok(_) => { Err(DestinationNotFoundError(self.name())) }
+            Err(err) => { Err(self.probe_error(err)) }
+        }
+    }
+
+    /// Quota left to the user, when the server reports it
+    fn available_space(&self) -> Option<u64> {
+        match self.propfind(&self.root, 0) {
+            Ok(Some(resources)) => { resources.first().and_then(|resource| { resource.available_space }) }
+            Ok(None) => { None }
+            Err(err) => { debug!(target: "general", "Free space of {} not read: {}", self.name(), err); None }
+        }
+    }
+
+    fn file_size(&self, path: &Path) -> anyhow::Result<Option<u64>> {
+        Ok(self.propfind(&self.url(path), 0)?
+            .and_then(|resources| { resources.into_iter().next() })
+            .map(|resource| { resource.size.unwrap_or(0) }))
+    }
+
+    fn exists(&self, path: &Path) -> anyhow::Result<bool> {
+        Ok(self.propfind(&self.url(path), 0)?.is_some())
+    }
+
+    /// One PROPFIND per collection of the tree, servers often refuse 'Depth: infinity'
+    fn list_files(&self, path: &Path) -> anyhow::Result<HashMap<PathBuf, u64>> {
+        let mut files = HashMap::new();
+        let mut pending: Vec<PathBuf> = vec![PathBuf::new()];
+        while let Some(dir) = pending.pop() {
+            for resource in self.members(&self.url(&path.join(&dir)))? {
+                if resource.collection {
+                    pending.push(dir.join(&resource.name));
+                } else {
+                    files.insert(dir.join(&resource.name), resource.size.unwrap_or(0));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> anyhow::Result<()> {
+        let mut url = self.root.clone();
+        for component in path.components() {
+            if let Component::Normal(name) = component {
+                url.push('/');
+                url.push_str(&uri_encode(&name.to_string_lossy(), false));
+                match self.send("MKCOL", &url, &[], Body::Empty) {
+                    Ok(_) => {}
+                    // The collection already exists
+                    Err(err) if status_of(&err) == Some(405) => {}
+                    Err(err) => { return Err(err) }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The modification time is kept by Nextcloud and ownCloud servers and reapplied on restore, to the second.
+    /// Symbolic links are followed, permissions, extended attributes and ACLs are not kept.
+    fn put_file(&self, source: &Path, path: &Path, policy: &MetadataPolicy, throttle: Option<&Throttle>) -> anyhow::Result<u64> {
+        let metadata = fs::metadata(source)?;
+        let size = metadata.len();
+        let url = self.url(path);
+        let mtime = match policy.mtime {
+            true => { Some(metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()) }
+            false => { None }
+        };
+
+        match &self.uploads {
+            Some(uploads) if size > CHUNK_SIZE => { self.chunked_upload(uploads, source, &url, size, mtime, throttle)? }
+            uploads => {
+                if uploads.is_none() && size > CHUNK_SIZE {
+                    warn!(target: "general", "Uploading {:?} ({} bytes) to {} in a single request: chunked uploads are only \
+                                              supported on Nextcloud and ownCloud, an interrupted upload starts over", source, size, self.name());
+                }
+                let headers: Vec<(&str, String)> = mtime.map(|mtime| { (MTIME_HEADER, mtime.to_string()) }).into_iter().collect();
+                self.send_throttled("PUT", &url, &headers, Body::File(source), throttle)?;
+            }
+        }
+        Ok(size)
+    }
+
+    fn get_file(&self, path: &Path, target: &Path, policy: &MetadataPolicy) -> anyhow::Result<u64> {
+        let _ = fs::remove_file(target);
+        let response = self.send("GET", &self.url(path), &[], Body::Empty)?;
+        let mtime = response.header("Last-Modified")
+            .and_then(|modified| { chrono::DateTime::parse_from_rfc2822(modified).ok() })
+            .map(|modified| { FileTime::from_unix_time(modified.timestamp(), 0) });
+
+        let mut file = File::create(target)?;
+        let bytes = std::io::copy(&mut response.into_reader(), &mut file)?;
+        file.sync_all()?;
+        drop(file);
+
+        if let (true, Some(mtime)) = (policy.mtime, mtime) {
+            filetime::set_file_times(target, mtime, mtime)?;
+        }
+        Ok(bytes)
+    }
+
+    fn read(&self, path: &Path) -> anyhow::Result<Option<Vec<u8>>> {
+        match self.send("GET", &self.url(path), &[], Body::Empty) {
+            Ok(response) => {
+                let mut content = vec![];
+                response.into_reader().read_to_end(&mut content)?;
+                Ok(Some(content))
+            }
+            Err(err) if status_of(&err) == Some(404) => { Ok(None) }
+            Err(err) => { Err(err) }
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> anyhow::Result<()> {
+        self.send("PUT", &self.url(path), &[], Body::Bytes(content))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> anyhow::Result<()> {
+        let headers = [("Destination", self.url(to)), ("Overwrite", String::from("F"))];
+        self.send("MOVE", &self.url(from), &headers, Body::Empty)?;
+        Ok(())
+    }
+
+    fn remove_all(&self, path: &Path) -> anyhow::Result<()> {
+        match self.send("DELETE", &self.url(path), &[], Body::Empty) {
+            Ok(_) => { Ok(()) }
+            Err(err) if status_of(&err) == Some(404) => { Ok(()) }
+            Err(err) => { Err(err) }
+        }
+    }
+
+    /// Deletes the chunked uploads of the destination folder left by failed assemblies or interrupted copies
+    fn remove_partial_uploads(&self) -> anyhow::Result<()> {
+        let Some(uploads) = &self.uploads else { return Ok(()) };
+        let prefix = self.upload_prefix();
+        for resource in self.members(uploads)? {
+            if resource.collection && resource.name.starts_with(&prefix) {
+                debug!(target: "general", "Removing the partial upload {} from {}", resource.name, self.name());
+                self.send("DELETE", &format!("{}/{}", uploads, resource.name), &[], Body::Empty)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Error of a response, with the message of the Sabre/DAV error document of Nextcloud and ownCloud when there is one
+fn error_response(status: u16, body: &str) -> DavError {
+    let message = roxmltree::Document::parse(body).ok()
+        .and_then(|document| {
+            document.descendants()
+                .find(|node| { node.tag_name().name() == "message" })
+                .and_then(|node| { node.text() })
+                .map(|text| { text.trim().to_string() })
+        })
+        .unwrap_or(body.lines().next().unwrap_or_default().trim().to_string());
+    DavError { status, message }
+}
+
+/// Resources of a PROPFIND multistatus response, in the order of the response
+fn parse_multistatus(body: &str) -> anyhow::Result<Vec<Resource>> {
+    let document = roxmltree::Document::parse(body)?;
+    let resources = document.descendants()
+        .filter(|node| { node.tag_name().name() == "response" })
+        .map(|response| {
+            let text = |name: &str| -> Option<String> {
+                response.descendants()
+                    .find(|node| { node.tag_name().name() == name && node.text().is_some() })
+                    .and_then(|node| { node.text() })
+                    .map(|text| { text.trim().to_string() })
+            };
+            let path = href_path(&text("href").unwrap_or_default());
+            Resource {
+                name: path.rsplit('/').next().unwrap_or_default().to_string(),
+                path,
+                collection: response.descendants().any(|node| { node.tag_name().name() == "collection" }),
+                size: text("getcontentlength").and_then(|size| { size.parse().ok() }),
+                // Negative values stand for unknown or unlimited space
+                available_space: text("quota-available-bytes").and_then(|space| { space.parse().ok() }),
+            }
+        })
+        .collect();
+    Ok(resources)
+}
+
+/// Resources of a depth 1 PROPFIND of the collection at `url` without the collection itself. RFC 4918 does not
+/// say where the collection comes in the response, it is told apart by its href.
+fn members(resources: Vec<Resource>, url: &str) -> Vec<Resource> {
+    let collection = href_path(url);
+    resources.into_iter().filter(|resource| { resource.path != collection }).collect()
+}
+
+/// Decoded path of an href or a URL, without the scheme, the host and the trailing slash, so that the
+/// absolute URLs and the absolute paths servers answer with can be compared with the URLs requested
+fn href_path(href: &str) -> String {
+    let path = match href.split_once("://") {
+        Some((_, rest)) => { rest.find('/').map(|start| { &rest[start..] }).unwrap_or("") }
+        None => { href }
+    };
+    uri_decode(path).trim_end_matches('/').to_string()
+}
+
+/// Decodes the '%XX' escapes of a URL segment
+fn uri_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded: Vec<u8> = vec![];
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], value.get(index + 1..index + 3).and_then(|hex| { u8::from_str_radix(hex, 16).ok() })) {
+            (b'%', Some(byte)) => { decoded.push(byte); index += 3 }
+            (byte, _) => { decoded.push(byte); index += 1 }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Password from the configured file or, without one, from the environment
+fn load_password(config: &WebDavDestination) -> Result<String, String> {
+    match &config.password_file {
+        Some(password_file) => {
+            let path = expand_home(password_file);
+            match fs::read_to_string(&path) {
+                Ok(content) => { Ok(content.lines().next().unwrap_or_default().to_string()) }
+                Err(err) => { Err(format!("password file {:?} not readable: {}", path, err)) }
+            }
+        }
+        None => {
+            std::env::var("WEBDAV_PASSWORD").map_err(|_| { String::from("no password_file set and WEBDAV_PASSWORD missing from the environment") })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Multistatus response listing the collection after its members, with absolute URLs and other escapes
+    /// than the ones of the request
+    const MULTISTATUS: &str = r#"<?xml version="1.0"?>
+<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>https://cloud.example.com/remote.php/dav/files/alice/Backups/notes%20(1).txt</d:href>
+    <d:propstat><d:prop><d:resourcetype/><d:getcontentlength>12</d:getcontentlength></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>https://cloud.example.com/remote.php/dav/files/alice/Backups/photos/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/remote.php/dav/files/alice/Backups/</d:href>
+    <d:propstat><d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop></d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+    #[test]
+    fn collection_is_left_out_of_its_members_wherever_it_is_listed() {
+        let resources = parse_multistatus(MULTISTATUS).unwrap();
+
+        let members = members(resources, "https://cloud.example.com/remote.php/dav/files/alice/Backups");
+
+        assert_eq!(members.iter().map(|resource| { resource.name.as_str() }).collect::<Vec<_>>(), ["notes (1).txt", "photos"]);
+        assert_eq!((members[0].collection, members[0].size), (false, Some(12)));
+        assert!(members[1].collection);
+    }
+
+    #[test]
+    fn href_paths_are_compared_decoded() {
+        assert_eq!(href_path("https://cloud.example.com:8443/dav/My%20Files/"), "/dav/My Files");
+        assert_eq!(href_path("/dav/My Files"), "/dav/My Files");
+        assert_eq!(href_path("https://cloud.example.com"), "");
+    }
+}
@@ -0,0 +1,29 @@
+//! Fixtures shared by the integration tests, each test file uses only some of them.
+#![allow(dead_code)]
+
+use std::path::Path;
+use settings::settings::{BackupConfig, BackupDestination, BackupSource};
+
+/// Folder source saved as `name` inside the snapshot
+pub fn source(path: &Path, name: &str) -> BackupSource {
+    BackupSource {
+        path: path.to_string_lossy().to_string(),
+        mode: String::from("Folder"),
+        name: Some(name.to_string()),
+        ..BackupSource::default()
+    }
+}
+
+/// Local directory destination
+pub fn local(path: &Path) -> BackupDestination {
+    BackupDestination::Path(path.to_string_lossy().to_string())
+}
+
+/// Default configuration backing up `source` into `destination`
+pub fn config(source: BackupSource, destination: BackupDestination) -> BackupConfig {
+    BackupConfig {
+        backup_sources: vec![source],
+        backup_destinations: vec![destination],
+        ..BackupConfig::default()
+    }
+}
@@ -0,0 +1,57 @@
+//! Choice of the destinations written by a backup, with local directories standing for the destinations.
+
+use std::fs;
+use std::sync::mpsc;
+use backup_engine::backup;
+use backup_engine::job::BackupJob;
+use backup_engine::report::DestinationStatus;
+use backup_engine::staging::snapshot_path;
+
+mod common;
+
+#[test]
+fn first_available_skips_the_unavailable_destinations_and_stops_at_the_first_written_one() {
+    let source = tempfile::tempdir().unwrap();
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    let missing = first.path().join("unplugged-drive");
+    let mut config = common::config(common::source(source.path(), "source"), common::local(&missing));
+    config.backup_destinations.push(common::local(first.path()));
+    config.backup_destinations.push(common::local(second.path()));
+    config.destination_policy = String::from("first-available");
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(report.destinations.len(), 2);
+    assert!(matches!(report.destinations[0].status, DestinationStatus::Refused(_)), "{:?}", report.destinations[0].status);
+    assert!(matches!(report.destinations[1].status, DestinationStatus::Completed), "{:?}", report.destinations[1].status);
+    assert!(!missing.exists());
+    assert_eq!(fs::read_to_string(snapshot_path(first.path()).join("source").join("notes.txt")).unwrap(), "notes");
+    assert_eq!(fs::read_dir(second.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn all_writes_every_available_destination() {
+    let source = tempfile::tempdir().unwrap();
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    let missing = first.path().join("unplugged-drive");
+    let mut config = common::config(common::source(source.path(), "source"), common::local(first.path()));
+    config.backup_destinations.push(common::local(&missing));
+    config.backup_destinations.push(common::local(second.path()));
+    config.destination_policy = String::from("all");
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(report.destinations.len(), 3);
+    assert!(matches!(report.destinations[1].status, DestinationStatus::Refused(_)), "{:?}", report.destinations[1].status);
+    for destination in [first.path(), second.path()] {
+        assert_eq!(fs::read_to_string(snapshot_path(destination).join("source").join("notes.txt")).unwrap(), "notes");
+    }
+}
@@ -0,0 +1,26 @@
+//! Dry run against the local file system.
+
+use std::fs;
+use filetime::FileTime;
+use backup_engine::dry_run::dry_run;
+use backup_engine::job::BackupJob;
+
+mod common;
+
+#[test]
+fn dry_run_writes_nothing_into_the_destination() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    // A file created and removed in the destination would change the modification time of the directory
+    let modified = FileTime::from_unix_time(1_000_000_000, 0);
+    filetime::set_file_mtime(destination.path(), modified).unwrap();
+    let config = common::config(common::source(source.path(), "source"), common::local(destination.path()));
+
+    let dry_run = dry_run(&BackupJob::from_config(&config).unwrap()).unwrap();
+
+    assert!(dry_run.is_ready() && dry_run.checks[0].writable);
+    assert_eq!(dry_run.copied.len(), 1);
+    assert_eq!(fs::read_dir(destination.path()).unwrap().count(), 0);
+    assert_eq!(FileTime::from_last_modification_time(&fs::metadata(destination.path()).unwrap()), modified);
+}
@@ -0,0 +1,122 @@
+//! Git sources, run with the git command line against repositories created in temporary directories.
+//! The tests need git and fail when it is not installed.
+#![cfg(target_family = "unix")]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+use backup_engine::backup;
+use backup_engine::git::{BUNDLE_FILE_NAME, DIFF_FILE_NAME, STASH_DIR_NAME, STATUS_FILE_NAME, UNTRACKED_DIR_NAME};
+use backup_engine::job::BackupJob;
+use backup_engine::selection::select_sources;
+use backup_engine::staging::snapshot_path;
+use settings::settings::{BackupConfig, BackupSource};
+
+mod common;
+
+const SOURCE_NAME: &str = "projects";
+
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C").arg(dir)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com", "-c", "init.defaultBranch=main"])
+        .args(args)
+        .output()
+        .unwrap_or_else(|err| { panic!("git could not be run, install git to run the git tests: {}", err) });
+    assert!(output.status.success(), "git {:?}: {}", args, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+fn config(source: &Path, destination: &Path) -> BackupConfig {
+    let source = BackupSource { mode: String::from("Git"), exclude: Some(vec![String::from("archived/")]), ..common::source(source, SOURCE_NAME) };
+    common::config(source, common::local(destination))
+}
+
+/// Clone of a remote with one pushed commit, then one commit that is not pushed, a staged and an unstaged
+/// change, an untracked file, an ignored file and a stash entry
+fn repository_with_work(projects: &Path, remote: &Path) -> PathBuf {
+    git(remote, &["init", "--quiet", "--bare"]);
+    let app = projects.join("app");
+    fs::create_dir_all(&app).unwrap();
+    git(&app, &["init", "--quiet"]);
+    git(&app, &["remote", "add", "origin", &remote.to_string_lossy()]);
+    fs::write(app.join(".gitignore"), "target/\n").unwrap();
+    fs::write(app.join("main.rs"), "fn main() {}\n").unwrap();
+    git(&app, &["add", "."]);
+    git(&app, &["commit", "--quiet", "-m", "pushed"]);
+    git(&app, &["push", "--quiet", "origin", "HEAD:main"]);
+    git(&app, &["fetch", "--quiet", "origin"]);
+
+    fs::write(app.join("lib.rs"), "pub fn lib() {}\n").unwrap();
+    git(&app, &["add", "lib.rs"]);
+    git(&app, &["commit", "--quiet", "-m", "not pushed"]);
+
+    fs::write(app.join("stashed.rs"), "// stashed\n").unwrap();
+    git(&app, &["stash", "push", "--quiet", "--include-untracked", "-m", "experiment"]);
+
+    fs::write(app.join("main.rs"), "fn main() { println!(\"changed\"); }\n").unwrap();
+    fs::write(app.join("staged.rs"), "// staged\n").unwrap();
+    git(&app, &["add", "staged.rs"]);
+    fs::write(app.join("notes.txt"), "untracked notes\n").unwrap();
+    fs::create_dir_all(app.join("target")).unwrap();
+    fs::write(app.join("target/build.bin"), vec![0u8; 1000]).unwrap();
+    app
+}
+
+#[test]
+fn uncommitted_work_is_saved_instead_of_the_repository() {
+    let projects = tempfile::tempdir().unwrap();
+    let remote = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    repository_with_work(projects.path(), remote.path());
+    // Repositories in excluded directories are not searched
+    let archived = projects.path().join("archived/old");
+    fs::create_dir_all(&archived).unwrap();
+    git(&archived, &["init", "--quiet"]);
+    let config = config(projects.path(), destination.path());
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    let saved = snapshot_path(destination.path()).join(SOURCE_NAME).join("app");
+    let mut entries: Vec<String> = fs::read_dir(&saved).unwrap().map(|entry| { entry.unwrap().file_name().to_string_lossy().to_string() }).collect();
+    entries.sort();
+    assert_eq!(entries, vec![STASH_DIR_NAME, STATUS_FILE_NAME, BUNDLE_FILE_NAME, UNTRACKED_DIR_NAME, DIFF_FILE_NAME]);
+    assert!(!snapshot_path(destination.path()).join(SOURCE_NAME).join("archived").exists());
+
+    let diff = fs::read_to_string(saved.join(DIFF_FILE_NAME)).unwrap();
+    assert!(diff.contains("println!(\"changed\")") && diff.contains("// staged"), "{}", diff);
+    assert_eq!(fs::read_to_string(saved.join(UNTRACKED_DIR_NAME).join("notes.txt")).unwrap(), "untracked notes\n");
+    assert!(!saved.join(UNTRACKED_DIR_NAME).join("target").exists());
+    assert!(fs::read_to_string(saved.join(STASH_DIR_NAME).join("stash-0.diff")).unwrap().contains("// stashed"));
+    assert!(fs::read_to_string(saved.join(STATUS_FILE_NAME)).unwrap().contains("experiment"));
+
+    // The bundle holds only the unpushed commit, it is fetched into a clone of the remote
+    let restored = tempfile::tempdir().unwrap();
+    git(restored.path(), &["clone", "--quiet", &remote.path().to_string_lossy(), "app"]);
+    let clone = restored.path().join("app");
+    git(&clone, &["fetch", "--quiet", &saved.join(BUNDLE_FILE_NAME).to_string_lossy(), "main:saved"]);
+    assert_eq!(git(&clone, &["log", "--format=%s", "origin/main..saved"]), "not pushed\n");
+    git(&clone, &["checkout", "--quiet", "saved"]);
+    git(&clone, &["apply", "--index", &saved.join(DIFF_FILE_NAME).to_string_lossy()]);
+    assert_eq!(fs::read_to_string(clone.join("staged.rs")).unwrap(), "// staged\n");
+}
+
+#[test]
+fn repository_without_commits_saves_its_staged_files() {
+    let projects = tempfile::tempdir().unwrap();
+    git(projects.path(), &["init", "--quiet"]);
+    fs::write(projects.path().join("first.txt"), "first\n").unwrap();
+    git(projects.path(), &["add", "first.txt"]);
+    let config = config(projects.path(), Path::new("/unused"));
+
+    let selections = select_sources(&config).unwrap();
+
+    let mut files: Vec<PathBuf> = selections[0].files.iter().map(|file| { file.relative_path.clone() }).collect();
+    files.sort();
+    assert_eq!(files, vec![PathBuf::from(STATUS_FILE_NAME), PathBuf::from(DIFF_FILE_NAME)]);
+    let diff = selections[0].files.iter().find(|file| { file.relative_path == Path::new(DIFF_FILE_NAME) }).unwrap();
+    assert!(fs::read_to_string(&diff.path).unwrap().contains("+first"));
+}
@@ -0,0 +1,67 @@
+//! Backup history entries built from the result of local backups. The history file itself is not written,
+//! it lives in the config directory of the user.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use backup_engine::backup;
+use backup_engine::destination::LocalDestination;
+use backup_engine::history::{Confirmation, HistoryEntry, Outcome, Trigger};
+use backup_engine::job::BackupJob;
+use backup_engine::manifest::Manifest;
+use backup_engine::staging::SNAPSHOT_DIR_NAME;
+use settings::settings::{BackupConfig, BackupDestination};
+
+mod common;
+
+fn config(source: &str, destinations: Vec<String>) -> BackupConfig {
+    BackupConfig {
+        backup_sources: vec![common::source(Path::new(source), "source")],
+        backup_destinations: destinations.into_iter().map(BackupDestination::Path).collect(),
+        destination_policy: String::from("all"),
+        ..BackupConfig::default()
+    }
+}
+
+#[test]
+fn backup_is_recorded_with_its_snapshot_and_errors() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    let missing = destination.path().join("missing").to_string_lossy().to_string();
+    let config = config(&source.path().to_string_lossy(), vec![destination.path().to_string_lossy().to_string(), missing.clone()]);
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let result = backup::run(&job, &sender);
+    let entry = HistoryEntry::from_result(Trigger::Gesture, Confirmation::Confirmed, &result);
+
+    assert_eq!(entry.outcome, Outcome::Success);
+    assert_eq!((entry.files_selected, entry.bytes_selected, entry.bytes_copied()), (1, 5, 5));
+    assert_eq!(entry.destinations.len(), 2);
+    assert_eq!(entry.destinations[1].status, "refused");
+    assert!(entry.errors[0].starts_with(&missing), "{:?}", entry.errors);
+    // The snapshot id links the entry to the manifest of the snapshot
+    let manifest = Manifest::read(&LocalDestination::new(destination.path().to_path_buf()), std::path::Path::new(SNAPSHOT_DIR_NAME)).unwrap();
+    assert_eq!(manifest.snapshot_id, entry.snapshot_id);
+
+    let line = serde_json::to_string(&entry).unwrap();
+    assert!(line.contains("\"trigger\":\"gesture\"") && line.contains("\"confirmation\":\"confirmed\""), "{}", line);
+    assert_eq!(serde_json::from_str::<HistoryEntry>(&line).unwrap(), entry);
+}
+
+#[test]
+fn failed_and_unconfirmed_triggers_are_recorded() {
+    let not_run = HistoryEntry::not_run(Trigger::Gesture, Confirmation::TimedOut);
+    assert_eq!(not_run.outcome, Outcome::NotRun);
+    assert!(not_run.snapshot_id.is_none());
+    assert!(not_run.summary().contains("gesture (timed out)  not run"), "{}", not_run.summary());
+
+    let config = config("/nonexistent/source", vec![String::from("/nonexistent/destination")]);
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let failed = HistoryEntry::from_result(Trigger::Embctl, Confirmation::NotRequired, &backup::run(&job, &sender));
+    assert_eq!(failed.outcome, Outcome::Failed);
+    assert!(!failed.errors.is_empty());
+}
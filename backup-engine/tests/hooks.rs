@@ -0,0 +1,141 @@
+//! Pre-backup and post-backup hooks, run with the shell against the local file system.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use backup_engine::backup;
+use backup_engine::hooks::{HookOutcome, HOOK_LOG_NAME};
+use backup_engine::job::BackupJob;
+use backup_engine::progress::ProgressEvent;
+use backup_engine::staging::{metadata_path, snapshot_path};
+use errors::Error;
+use settings::settings::{BackupConfig, BackupHook, BackupSource};
+use tempfile::TempDir;
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+
+fn hook(command: &str, timeout_sec: Option<u64>, abort_on_failure: bool) -> BackupHook {
+    BackupHook { command: command.to_string(), timeout_sec, abort_on_failure }
+}
+
+fn config(source: &TempDir, destination: &TempDir, pre_backup_hooks: Vec<BackupHook>, post_backup_hooks: Vec<BackupHook>) -> BackupConfig {
+    BackupConfig {
+        pre_backup_hooks,
+        post_backup_hooks,
+        ..common::config(common::source(source.path(), SOURCE_NAME), common::local(destination.path()))
+    }
+}
+
+#[test]
+fn hooks_run_around_the_backup_with_their_output_in_the_snapshot() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    let dump = source.path().join("dump.sql");
+    let post_file = destination.path().join("post.txt");
+    let mut config = config(&source, &destination,
+                            vec![hook(&format!("echo dumping; echo 'create table t;' > '{}'", dump.display()), None, true)],
+                            vec![hook(&format!("echo \"$EMERGENCY_BACKUP_STATUS $EMERGENCY_BACKUP_SNAPSHOT\" > '{}'; echo ejected >&2", post_file.display()), None, false)]);
+    // A source file named like the log is saved as it is
+    let user_log = tempfile::tempdir().unwrap();
+    fs::write(user_log.path().join(HOOK_LOG_NAME), "user log\n").unwrap();
+    config.backup_sources.push(BackupSource {
+        path: user_log.path().join(HOOK_LOG_NAME).to_string_lossy().to_string(),
+        mode: String::from("File"),
+        ..BackupSource::default()
+    });
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(report.hooks.len(), 2);
+    assert!(report.hooks.iter().all(|outcome| { outcome.is_success() }));
+    // The file written by the pre-backup hook is part of the snapshot
+    let snapshot = snapshot_path(destination.path());
+    assert_eq!(fs::read_to_string(snapshot.join(SOURCE_NAME).join("dump.sql")).unwrap(), "create table t;\n");
+    assert_eq!(fs::read_to_string(&post_file).unwrap(), format!("success {}\n", snapshot.display()));
+
+    assert_eq!(fs::read_to_string(snapshot.join(HOOK_LOG_NAME)).unwrap(), "user log\n");
+    let log = fs::read_to_string(metadata_path(&snapshot).join(HOOK_LOG_NAME)).unwrap();
+    assert!(log.contains("pre-backup hook") && log.contains("dumping\n"), "{}", log);
+    assert!(log.contains("post-backup hook") && log.contains("ejected\n"), "{}", log);
+}
+
+#[test]
+fn pre_backup_hooks_get_the_first_available_destination() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    let missing = destination.path().join("unplugged-drive");
+    let pre_file = source.path().join("pre.txt");
+    let mut config = config(&source, &destination,
+                            vec![hook(&format!("echo \"$EMERGENCY_BACKUP_SNAPSHOT\" > '{}'", pre_file.display()), None, true)], vec![]);
+    config.backup_destinations.insert(0, common::local(&missing));
+    config.destination_policy = String::from("first-available");
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(fs::read_to_string(&pre_file).unwrap(), format!("{}\n", snapshot_path(destination.path()).display()));
+}
+
+#[test]
+fn failing_pre_backup_hook_aborts_the_backup() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    let post_file = destination.path().join("post.txt");
+    let config = config(&source, &destination,
+                        vec![hook("exit 3", None, true), hook("echo never run", None, false)],
+                        vec![hook(&format!("echo \"$EMERGENCY_BACKUP_STATUS\" > '{}'", post_file.display()), None, false)]);
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let err = backup::run(&job, &sender).unwrap_err();
+
+    assert!(matches!(err.downcast_ref::<Error>(), Some(Error::HookFailedError { .. })), "{:?}", err);
+    assert!(!snapshot_path(destination.path()).exists());
+    // The post-backup hooks still run, knowing the backup failed
+    assert_eq!(fs::read_to_string(&post_file).unwrap(), "failure\n");
+    drop(sender);
+    assert_eq!(receiver.iter().count(), 2);
+}
+
+#[test]
+fn hook_failure_is_only_reported_unless_configured_to_abort() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    let config = config(&source, &destination, vec![hook("false", None, false)], vec![]);
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert!(!report.hooks[0].is_success());
+    assert!(report.summary().contains("pre-backup hook 'false': failed"), "{}", report.summary());
+    assert!(snapshot_path(destination.path()).join(SOURCE_NAME).join("notes.txt").exists());
+}
+
+#[test]
+fn hook_is_killed_after_its_timeout() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    // The background sleep keeps the output pipes open, it must be killed with the shell
+    let config = config(&source, &destination, vec![hook("echo started; sleep 30 & sleep 30", Some(1), true)], vec![]);
+
+    let start = Instant::now();
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    assert!(backup::run(&job, &sender).is_err());
+
+    assert!(start.elapsed() < Duration::from_secs(10));
+    drop(sender);
+    let outcomes: Vec<HookOutcome> = receiver.iter()
+        .filter_map(|event| { if let ProgressEvent::HookFinished { outcome } = event { Some(outcome) } else { None } })
+        .collect();
+    assert_eq!(outcomes[0].error.as_deref(), Some("timed out after 1s"));
+    assert_eq!(outcomes[0].output, "started\n");
+}
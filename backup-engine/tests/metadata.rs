@@ -0,0 +1,224 @@
+//! Metadata policy of backups and restores, run against the local file system.
+//! Extended attribute and ACL tests are skipped when the file system of the temporary directory does not support them.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+use backup_engine::backup;
+use backup_engine::destination::LocalDestination;
+use backup_engine::job::BackupJob;
+use backup_engine::metadata::MetadataPolicy;
+use backup_engine::restore::restore;
+use backup_engine::staging::snapshot_path;
+use settings::settings::BackupConfig;
+use tempfile::TempDir;
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+
+struct Fixture {
+    source: TempDir,
+    destination: TempDir,
+    config: BackupConfig,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        let source = tempfile::tempdir().unwrap();
+        let destination = tempfile::tempdir().unwrap();
+        let config = common::config(common::source(source.path(), SOURCE_NAME), common::local(destination.path()));
+        Fixture { source, destination, config }
+    }
+
+    fn source(&self, relative_path: &str) -> PathBuf {
+        self.source.path().join(relative_path)
+    }
+
+    /// Runs the backup and returns the source tree inside the snapshot
+    fn backup(&self) -> PathBuf {
+        let job = BackupJob::from_config(&self.config).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        backup::run(&job, &sender).unwrap().into_result().unwrap();
+        snapshot_path(self.destination.path()).join(SOURCE_NAME)
+    }
+
+    /// Runs the backup, restores it into a new directory and returns the restored source tree
+    fn backup_and_restore(&self, restore_dir: &TempDir) -> PathBuf {
+        self.backup();
+        let policy = MetadataPolicy::from_config(&self.config).unwrap();
+        let destination = LocalDestination::new(self.destination.path().to_path_buf());
+        restore(&destination, restore_dir.path(), &policy).unwrap().into_result().unwrap();
+        restore_dir.path().join(SOURCE_NAME)
+    }
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::symlink_metadata(path).unwrap().permissions().mode() & 0o7777
+}
+
+fn mtime(path: &Path) -> SystemTime {
+    fs::symlink_metadata(path).unwrap().modified().unwrap()
+}
+
+#[test]
+fn permissions_and_mtime_are_preserved() {
+    let fixture = Fixture::new();
+    let file = fixture.source("script.sh");
+    fs::write(&file, "echo backup").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o750)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    filetime::set_file_mtime(&file, filetime::FileTime::from_system_time(modified)).unwrap();
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = fixture.backup_and_restore(&restore_dir);
+    let snapshot = snapshot_path(fixture.destination.path()).join(SOURCE_NAME);
+
+    for copy in [snapshot.join("script.sh"), restored.join("script.sh")] {
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "echo backup");
+        assert_eq!(mode(&copy), 0o750);
+        assert_eq!(mtime(&copy), modified);
+    }
+}
+
+#[test]
+fn permissions_and_mtime_are_not_preserved_when_disabled() {
+    let mut fixture = Fixture::new();
+    fixture.config.preserve_permissions = false;
+    fixture.config.preserve_mtime = false;
+    let file = fixture.source("script.sh");
+    fs::write(&file, "echo backup").unwrap();
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o700)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    filetime::set_file_mtime(&file, filetime::FileTime::from_system_time(modified)).unwrap();
+
+    let copy = fixture.backup().join("script.sh");
+
+    // A newly created file is never executable
+    assert_eq!(mode(&copy) & 0o111, 0);
+    assert_ne!(mtime(&copy), modified);
+}
+
+#[test]
+fn symlinks_are_followed() {
+    let fixture = Fixture::new();
+    fs::create_dir(fixture.source("dir")).unwrap();
+    fs::write(fixture.source("dir/file.txt"), "content").unwrap();
+    symlink("dir/file.txt", fixture.source("file_link")).unwrap();
+    symlink("dir", fixture.source("dir_link")).unwrap();
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = fixture.backup_and_restore(&restore_dir);
+
+    for tree in [fixture.backup(), restored] {
+        assert!(!fs::symlink_metadata(tree.join("file_link")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(tree.join("file_link")).unwrap(), "content");
+        assert_eq!(fs::read_to_string(tree.join("dir_link/file.txt")).unwrap(), "content");
+    }
+}
+
+#[test]
+fn symlinks_are_preserved() {
+    let mut fixture = Fixture::new();
+    fixture.config.symlink_policy = String::from("preserve");
+    fs::create_dir(fixture.source("dir")).unwrap();
+    fs::write(fixture.source("dir/file.txt"), "content").unwrap();
+    symlink("dir/file.txt", fixture.source("file_link")).unwrap();
+    symlink("dir", fixture.source("dir_link")).unwrap();
+    symlink("missing", fixture.source("broken_link")).unwrap();
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = fixture.backup_and_restore(&restore_dir);
+    let snapshot = snapshot_path(fixture.destination.path()).join(SOURCE_NAME);
+
+    for tree in [snapshot, restored] {
+        assert_eq!(fs::read_link(tree.join("file_link")).unwrap(), PathBuf::from("dir/file.txt"));
+        assert_eq!(fs::read_link(tree.join("dir_link")).unwrap(), PathBuf::from("dir"));
+        assert_eq!(fs::read_link(tree.join("broken_link")).unwrap(), PathBuf::from("missing"));
+        assert_eq!(fs::read_to_string(tree.join("file_link")).unwrap(), "content");
+    }
+}
+
+#[test]
+fn symlinks_are_skipped() {
+    let mut fixture = Fixture::new();
+    fixture.config.symlink_policy = String::from("skip");
+    fs::write(fixture.source("file.txt"), "content").unwrap();
+    symlink("file.txt", fixture.source("file_link")).unwrap();
+
+    let snapshot = fixture.backup();
+
+    assert!(snapshot.join("file.txt").exists());
+    assert!(fs::symlink_metadata(snapshot.join("file_link")).is_err());
+}
+
+#[test]
+fn special_files_are_left_out() {
+    let fixture = Fixture::new();
+    fs::write(fixture.source("file.txt"), "content").unwrap();
+    let fifo = std::ffi::CString::new(fixture.source("fifo").to_string_lossy().as_bytes()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    let snapshot = fixture.backup();
+
+    assert!(snapshot.join("file.txt").exists());
+    assert!(fs::symlink_metadata(snapshot.join("fifo")).is_err());
+}
+
+#[test]
+fn xattrs_are_copied_when_enabled() {
+    let mut fixture = Fixture::new();
+    let file = fixture.source("file.txt");
+    fs::write(&file, "content").unwrap();
+    if xattr::set(&file, "user.backup.test", b"value").is_err() {
+        eprintln!("Extended attributes not supported, test skipped");
+        return;
+    }
+
+    let copy = fixture.backup().join("file.txt");
+    assert_eq!(xattr::get(&copy, "user.backup.test").unwrap(), None);
+
+    fixture.config.preserve_xattrs = true;
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = fixture.backup_and_restore(&restore_dir).join("file.txt");
+    let copy = snapshot_path(fixture.destination.path()).join(SOURCE_NAME).join("file.txt");
+    assert_eq!(xattr::get(&copy, "user.backup.test").unwrap(), Some(b"value".to_vec()));
+    assert_eq!(xattr::get(&restored, "user.backup.test").unwrap(), Some(b"value".to_vec()));
+}
+
+/// Access ACL in the Linux xattr format: owner rw, user 1000 r, group r, mask r, others none
+fn acl_xattr() -> Vec<u8> {
+    const UNDEFINED_ID: u32 = u32::MAX;
+    let entries: [(u16, u16, u32); 5] = [(0x01, 6, UNDEFINED_ID), (0x02, 4, 1000), (0x04, 4, UNDEFINED_ID), (0x10, 4, UNDEFINED_ID), (0x20, 0, UNDEFINED_ID)];
+    let mut value = 2u32.to_le_bytes().to_vec();
+    for (tag, permissions, id) in entries {
+        value.extend_from_slice(&tag.to_le_bytes());
+        value.extend_from_slice(&permissions.to_le_bytes());
+        value.extend_from_slice(&id.to_le_bytes());
+    }
+    value
+}
+
+#[test]
+fn acls_are_copied_when_enabled() {
+    let mut fixture = Fixture::new();
+    let file = fixture.source("file.txt");
+    fs::write(&file, "content").unwrap();
+    if xattr::set(&file, "system.posix_acl_access", &acl_xattr()).is_err() {
+        eprintln!("POSIX ACLs not supported, test skipped");
+        return;
+    }
+
+    let copy = fixture.backup().join("file.txt");
+    assert_eq!(xattr::get(&copy, "system.posix_acl_access").unwrap(), None);
+
+    fixture.config.preserve_acls = true;
+    let restore_dir = tempfile::tempdir().unwrap();
+    let restored = fixture.backup_and_restore(&restore_dir).join("file.txt");
+    let copy = snapshot_path(fixture.destination.path()).join(SOURCE_NAME).join("file.txt");
+    assert_eq!(xattr::get(&copy, "system.posix_acl_access").unwrap(), Some(acl_xattr()));
+    assert_eq!(xattr::get(&restored, "system.posix_acl_access").unwrap(), Some(acl_xattr()));
+}
@@ -0,0 +1,65 @@
+//! Pre-flight checks of local destinations. The temporary directories of a test share a device, so a
+//! destination created next to the source stands for a destination on the disk being backed up.
+
+use std::path::Path;
+use backup_engine::destination::LocalDestination;
+use backup_engine::job::BackupJob;
+use backup_engine::preflight::{check_destination, DestinationCheck};
+use errors::Error;
+use tempfile::TempDir;
+
+mod common;
+
+/// Checks the destination `destination` of a job backing up `source`
+fn check(source: &TempDir, destination: &Path, same_device_policy: &str, required_space: u64) -> DestinationCheck {
+    let mut config = common::config(common::source(source.path(), "source"), common::local(destination));
+    config.same_device_policy = same_device_policy.to_string();
+    let job = BackupJob::from_config(&config).unwrap();
+    check_destination(&job, &LocalDestination::new(destination.to_path_buf()), required_space, false)
+}
+
+#[test]
+fn destination_without_enough_space_is_refused() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+
+    let check = check(&source, destination.path(), "warn", u64::MAX);
+
+    assert!(check.writable);
+    assert!(matches!(check.error, Some(Error::NotEnoughSpaceError { .. })), "{:?}", check.error);
+}
+
+#[test]
+fn destination_on_the_device_of_a_source_is_refused_by_the_refuse_policy() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+
+    let check = check(&source, destination.path(), "refuse", 0);
+
+    assert_eq!(check.same_device_sources, vec![source.path().to_string_lossy().to_string()]);
+    assert!(matches!(&check.error, Some(Error::SameDeviceError { source_path, .. }) if source_path == &check.same_device_sources[0]),
+            "{:?}", check.error);
+}
+
+#[test]
+fn destination_on_the_device_of_a_source_is_only_warned_about_by_the_warn_policy() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+
+    let check = check(&source, destination.path(), "warn", 0);
+
+    assert!(check.error.is_none(), "{:?}", check.error);
+    assert_eq!(check.warnings, vec![format!("same device as the source '{}'", source.path().display())]);
+}
+
+/// Permissions do not stop root from creating files, a directory of procfs refuses them for every user
+#[cfg(target_os = "linux")]
+#[test]
+fn unwritable_destination_is_refused() {
+    let source = tempfile::tempdir().unwrap();
+
+    let check = check(&source, Path::new("/proc/self"), "warn", 0);
+
+    assert!(!check.writable);
+    assert!(matches!(check.error, Some(Error::DestinationNotWritableError(_))), "{:?}", check.error);
+}
@@ -0,0 +1,115 @@
+//! Removable drive detection: parsing of /proc/self/mountinfo and choice of the drive matching a destination.
+//! The drives are described by hand, mount points being temporary directories.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use backup_engine::backup;
+use backup_engine::destination::LocalDestination;
+use backup_engine::dry_run::dry_run;
+use backup_engine::job::BackupJob;
+use backup_engine::removable::{choose_volume, parse_mountinfo, target_dir, Volume, MARKER_FILE_NAME};
+use backup_engine::staging::snapshot_path;
+use settings::settings::{BackupDestination, RemovableDestination, RemoteDestination};
+
+mod common;
+
+const MOUNTINFO: &str = "\
+23 28 0:22 / /proc rw,relatime - proc proc rw
+28 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+412 28 8:17 / /media/alice/MY\\040STICK rw,nosuid,nodev,relatime shared:230 - vfat /dev/sdb1 rw,fmask=0022,dmask=0022
+415 28 8:17 /backups /srv/backups rw,relatime shared:230 - vfat /dev/sdb1 rw
+broken line
+";
+
+fn volume(mount_point: &Path, label: Option<&str>, uuid: Option<&str>) -> Volume {
+    Volume {
+        device: String::from("/dev/sdb1"),
+        mount_point: mount_point.to_path_buf(),
+        label: label.map(|label| { label.to_string() }),
+        uuid: uuid.map(|uuid| { uuid.to_string() }),
+    }
+}
+
+#[test]
+fn mountinfo_is_parsed() {
+    let mounts = parse_mountinfo(MOUNTINFO);
+
+    assert_eq!(mounts.len(), 4);
+    let stick = &mounts[2];
+    assert_eq!((stick.major, stick.minor), (8, 17));
+    assert_eq!(stick.root, "/");
+    assert_eq!(stick.mount_point, PathBuf::from("/media/alice/MY STICK"));
+    assert_eq!(stick.fs_type, "vfat");
+    assert_eq!(stick.source, "/dev/sdb1");
+    // Bind mount of a directory of the same drive
+    assert_eq!(mounts[3].root, "/backups");
+}
+
+#[test]
+fn drive_must_match_every_criterion() {
+    let mount_point = tempfile::tempdir().unwrap();
+    let stick = volume(mount_point.path(), Some("EMERGENCY"), Some("1A2B-3C4D"));
+
+    assert!(stick.matches(&RemovableDestination::default()));
+    assert!(stick.matches(&RemovableDestination { label: Some(String::from("emergency")), ..RemovableDestination::default() }));
+    assert!(stick.matches(&RemovableDestination { uuid: Some(String::from("1a2b-3c4d")), ..RemovableDestination::default() }));
+    assert!(!stick.matches(&RemovableDestination { label: Some(String::from("OTHER")), ..RemovableDestination::default() }));
+    assert!(!volume(mount_point.path(), None, None).matches(&RemovableDestination { label: Some(String::from("EMERGENCY")), ..RemovableDestination::default() }));
+
+    let with_marker = RemovableDestination { label: Some(String::from("EMERGENCY")), marker: true, ..RemovableDestination::default() };
+    assert!(!stick.matches(&with_marker));
+    fs::write(mount_point.path().join(MARKER_FILE_NAME), "").unwrap();
+    assert!(stick.has_marker());
+    assert!(stick.matches(&with_marker));
+}
+
+#[test]
+fn snapshot_goes_in_the_configured_directory() {
+    let stick = volume(Path::new("/media/alice/STICK"), Some("STICK"), None);
+
+    assert_eq!(target_dir(&stick, &RemovableDestination::default()), PathBuf::from("/media/alice/STICK"));
+    assert_eq!(target_dir(&stick, &RemovableDestination { dir: Some(String::from("/backups/laptop")), ..RemovableDestination::default() }),
+               PathBuf::from("/media/alice/STICK/backups/laptop"));
+    assert_eq!(stick.description(), "/dev/sdb1 \"STICK\" on /media/alice/STICK");
+}
+
+#[test]
+fn destination_is_named_after_its_criteria() {
+    let destination = |removable: RemovableDestination| -> String { BackupDestination::Remote(RemoteDestination::Removable(removable)).name() };
+
+    assert_eq!(destination(RemovableDestination::default()), "removable:any");
+    assert_eq!(destination(RemovableDestination { label: Some(String::from("EMERGENCY")), marker: true, dir: Some(String::from("backups")), ..RemovableDestination::default() }),
+               "removable:label=EMERGENCY,marker/backups");
+}
+
+#[test]
+fn drive_is_chosen_without_writing_on_it() {
+    let first = tempfile::tempdir().unwrap();
+    let second = tempfile::tempdir().unwrap();
+    let destination = RemovableDestination { label: Some(String::from("STICK")), dir: Some(String::from("backups/laptop")), ..RemovableDestination::default() };
+
+    let chosen = choose_volume(vec![volume(first.path(), Some("OTHER"), None), volume(second.path(), Some("STICK"), None)], &destination);
+
+    assert_eq!(chosen.map(|volume| { volume.mount_point }), Some(second.path().to_path_buf()));
+    assert_eq!(fs::read_dir(second.path()).unwrap().count(), 0);
+    assert_eq!(choose_volume(vec![volume(first.path(), Some("OTHER"), None)], &destination), None);
+}
+
+#[test]
+fn directory_of_the_drive_is_created_by_the_backup_only() {
+    let source = tempfile::tempdir().unwrap();
+    let mount_point = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    let config = common::config(common::source(source.path(), "source"), common::local(mount_point.path()));
+    let mut job = BackupJob::from_config(&config).unwrap();
+    let root = mount_point.path().join("backups").join("laptop");
+    job.destinations = vec![Arc::new(LocalDestination::on_volume(root.clone(), String::from("removable:label=STICK/backups/laptop")))];
+
+    assert!(dry_run(&job).unwrap().is_ready());
+    assert_eq!(fs::read_dir(mount_point.path()).unwrap().count(), 0);
+
+    let (sender, _receiver) = mpsc::channel();
+    backup::run(&job, &sender).unwrap().into_result().unwrap();
+    assert_eq!(fs::read_to_string(snapshot_path(&root).join("source").join("notes.txt")).unwrap(), "notes");
+}
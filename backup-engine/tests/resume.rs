@@ -0,0 +1,93 @@
+//! Resume of an interrupted backup on a local destination. The interrupted run is seeded as a staging
+//! snapshot holding some copied files and the journal that lists them.
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use filetime::FileTime;
+use backup_engine::backup;
+use backup_engine::destination::LocalDestination;
+use backup_engine::job::BackupJob;
+use backup_engine::journal::{Journal, JournalEntry};
+use backup_engine::progress::ProgressEvent;
+use backup_engine::staging::{metadata_path, snapshot_path, staging_path, STAGING_DIR_NAME};
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+
+/// Modification time of the source files when the interrupted run copied them
+const COPIED_AT: i64 = 1_700_000_000;
+
+/// Writes the source file `name` with the modification time it had when the interrupted run copied it
+fn source_file(source: &Path, name: &str, content: &str) {
+    let path = source.join(name);
+    fs::write(&path, content).unwrap();
+    filetime::set_file_mtime(&path, FileTime::from_unix_time(COPIED_AT, 0)).unwrap();
+}
+
+/// Writes `content` as the staged copy of `name` and records it in the journal with the size of `recorded_size`
+fn staged_file(journal: &mut Journal, destination: &Path, name: &str, content: &str, recorded_size: u64) {
+    let snapshot_file = Path::new(SOURCE_NAME).join(name);
+    fs::write(staging_path(destination).join(&snapshot_file), content).unwrap();
+    journal.append(JournalEntry { path: snapshot_file, size: recorded_size, modified: COPIED_AT as u128 * 1000 }).unwrap();
+}
+
+#[test]
+fn resumed_backup_copies_only_the_files_missing_or_changed_since_the_interruption() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    source_file(source.path(), "kept.txt", "kept");
+    source_file(source.path(), "resized.txt", "resized since");
+    source_file(source.path(), "touched.txt", "touched");
+    source_file(source.path(), "missing.txt", "missing");
+    filetime::set_file_mtime(source.path().join("touched.txt"), FileTime::from_unix_time(COPIED_AT + 60, 0)).unwrap();
+
+    // The staged copies differ from the sources, so a file copied again is told apart from a kept one
+    let staging = staging_path(destination.path());
+    fs::create_dir_all(staging.join(SOURCE_NAME)).unwrap();
+    fs::create_dir_all(metadata_path(&staging)).unwrap();
+    let local = LocalDestination::new(destination.path().to_path_buf());
+    let mut journal = Journal::open(&local, Path::new(STAGING_DIR_NAME)).unwrap();
+    staged_file(&mut journal, destination.path(), "kept.txt", "KEPT", 4);
+    staged_file(&mut journal, destination.path(), "resized.txt", "RESIZED", 7);
+    staged_file(&mut journal, destination.path(), "touched.txt", "TOUCHED", 7);
+    journal.flush().unwrap();
+
+    let mut job = BackupJob::from_config(&common::config(common::source(source.path(), SOURCE_NAME), common::local(destination.path()))).unwrap();
+    job.resume = true;
+    let (sender, receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(report.destinations[0].files_copied, 4);
+    let snapshot = snapshot_path(destination.path()).join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(snapshot.join("kept.txt")).unwrap(), "KEPT");
+    assert_eq!(fs::read_to_string(snapshot.join("resized.txt")).unwrap(), "resized since");
+    assert_eq!(fs::read_to_string(snapshot.join("touched.txt")).unwrap(), "touched");
+    assert_eq!(fs::read_to_string(snapshot.join("missing.txt")).unwrap(), "missing");
+    assert!(!staging.exists());
+    let resumed = receiver.try_iter().any(|event| {
+        matches!(event, ProgressEvent::Warning { message, .. } if message.contains("1 of 4 files already copied"))
+    });
+    assert!(resumed);
+}
+
+#[test]
+fn interrupted_backup_is_copied_again_without_resume() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    source_file(source.path(), "kept.txt", "kept");
+    let staging = staging_path(destination.path());
+    fs::create_dir_all(staging.join(SOURCE_NAME)).unwrap();
+    fs::create_dir_all(metadata_path(&staging)).unwrap();
+    let local = LocalDestination::new(destination.path().to_path_buf());
+    let mut journal = Journal::open(&local, Path::new(STAGING_DIR_NAME)).unwrap();
+    staged_file(&mut journal, destination.path(), "kept.txt", "KEPT", 4);
+    journal.flush().unwrap();
+
+    let job = BackupJob::from_config(&common::config(common::source(source.path(), SOURCE_NAME), common::local(destination.path()))).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    backup::run(&job, &sender).unwrap().into_result().unwrap();
+
+    assert_eq!(fs::read_to_string(snapshot_path(destination.path()).join(SOURCE_NAME).join("kept.txt")).unwrap(), "kept");
+}
@@ -0,0 +1,167 @@
+//! S3 destination, run against a local MinIO server started for every test on a free port with its own
+//! data directory. The tests need the minio binary and are ignored by default, run them with
+//! `cargo test -p backup-engine --test s3 -- --ignored`. The request signing is tested without a server in `s3.rs`.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+use backup_engine::backup;
+use backup_engine::destination::Destination;
+use backup_engine::job::BackupJob;
+use backup_engine::metadata::MetadataPolicy;
+use backup_engine::restore::restore;
+use backup_engine::s3::{S3, PART_SIZE};
+use backup_engine::manifest::MANIFEST_FILE_NAME;
+use backup_engine::staging::{metadata_path, SNAPSHOT_DIR_NAME};
+use errors::Error;
+use settings::settings::{BackupConfig, BackupDestination, RemoteDestination, S3Destination};
+use tempfile::TempDir;
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+const ACCESS_KEY: &str = "emergency-backup";
+const SECRET_KEY: &str = "emergency-backup-secret";
+
+struct MinioServer {
+    dir: TempDir,
+    port: u16,
+    process: Child,
+}
+
+impl MinioServer {
+    /// Starts MinIO with a credentials file for its root user, panics if minio is missing
+    fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let process = match Command::new("minio")
+            .arg("server").arg(dir.path().join("data"))
+            .arg("--address").arg(format!("127.0.0.1:{}", port))
+            .arg("--console-address").arg("127.0.0.1:0")
+            .env("MINIO_ROOT_USER", ACCESS_KEY)
+            .env("MINIO_ROOT_PASSWORD", SECRET_KEY)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn() {
+            Ok(process) => { process }
+            Err(err) => { panic!("minio cannot be started, it must be in PATH to run the s3 tests: {}", err) }
+        };
+        fs::write(dir.path().join("credentials"), format!(
+            "[backup]\naws_access_key_id = {}\naws_secret_access_key = {}\n", ACCESS_KEY, SECRET_KEY)).unwrap();
+        let server = MinioServer { dir, port, process };
+
+        let start = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            if start.elapsed() > Duration::from_secs(20) {
+                panic!("minio did not accept connections on port {} within 20 seconds", port);
+            }
+            sleep(Duration::from_millis(100));
+        }
+        server
+    }
+
+    fn destination(&self, bucket: &str, prefix: Option<&str>) -> S3Destination {
+        S3Destination {
+            endpoint: format!("http://127.0.0.1:{}", self.port),
+            bucket: bucket.to_string(),
+            prefix: prefix.map(|prefix| { prefix.to_string() }),
+            region: None,
+            credentials_file: Some(self.dir.path().join("credentials").to_string_lossy().to_string()),
+            profile: Some(String::from("backup")),
+        }
+    }
+
+    /// Bucket created empty, retried while the server completes its start
+    fn bucket(&self, bucket: &str, prefix: Option<&str>) -> S3 {
+        let destination = S3::new(self.destination(bucket, prefix));
+        let start = Instant::now();
+        while let Err(err) = destination.create_bucket() {
+            if start.elapsed() > Duration::from_secs(20) {
+                panic!("bucket not created: {:?}", err);
+            }
+            sleep(Duration::from_millis(200));
+        }
+        destination
+    }
+}
+
+impl Drop for MinioServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn config(source: &Path, destination: S3Destination) -> BackupConfig {
+    common::config(common::source(source, SOURCE_NAME), BackupDestination::Remote(RemoteDestination::S3(destination)))
+}
+
+#[test]
+#[ignore = "needs a MinIO server, run with --ignored"]
+fn backup_and_restore_through_s3() {
+    let server = MinioServer::start();
+    let destination = server.bucket("backups", Some("laptop/"));
+    let source = tempfile::tempdir().unwrap();
+    fs::create_dir_all(source.path().join("docs/nested dir")).unwrap();
+    fs::write(source.path().join("docs/report \"final\" [v2] & co.txt"), "report").unwrap();
+    // Larger than a part, written with a multipart upload
+    let large: Vec<u8> = (0..PART_SIZE + 300_000).map(|index| { (index % 251) as u8 }).collect();
+    fs::write(source.path().join("docs/nested dir/large.bin"), &large).unwrap();
+    let script = source.path().join("run.sh");
+    fs::write(&script, "echo run").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    filetime::set_file_mtime(&script, filetime::FileTime::from_system_time(modified)).unwrap();
+    let config = config(source.path(), server.destination("backups", Some("laptop/")));
+
+    // The second run replaces the snapshot of the first one
+    for _ in 0..2 {
+        let job = BackupJob::from_config(&config).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        backup::run(&job, &sender).unwrap().into_result().unwrap();
+    }
+
+    let snapshot = Path::new(SNAPSHOT_DIR_NAME).join(SOURCE_NAME);
+    assert_eq!(destination.file_size(&snapshot.join("docs/nested dir/large.bin")).unwrap(), Some(large.len() as u64));
+    assert!(destination.exists(&metadata_path(Path::new(SNAPSHOT_DIR_NAME)).join(MANIFEST_FILE_NAME)).unwrap());
+    assert!(!destination.exists(Path::new("emergency-backup.staging")).unwrap());
+    assert!(!destination.exists(Path::new("emergency-backup.previous")).unwrap());
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let report = restore(&destination, restore_dir.path(), &MetadataPolicy::default()).unwrap().into_result().unwrap();
+    assert_eq!(report.files_restored, 3);
+    let restored = restore_dir.path().join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(restored.join("docs/report \"final\" [v2] & co.txt")).unwrap(), "report");
+    assert_eq!(fs::read(restored.join("docs/nested dir/large.bin")).unwrap(), large);
+    assert_eq!(fs::metadata(restored.join("run.sh")).unwrap().permissions().mode() & 0o777, 0o750);
+    assert_eq!(fs::metadata(restored.join("run.sh")).unwrap().modified().unwrap(), modified);
+}
+
+#[test]
+#[ignore = "needs a MinIO server, run with --ignored"]
+fn missing_bucket_is_reported() {
+    let server = MinioServer::start();
+    server.bucket("other", None);
+    let destination = S3::new(server.destination("missing", None));
+
+    assert!(matches!(destination.probe(), Err(Error::DestinationNotFoundError(_))));
+}
+
+#[test]
+#[ignore = "needs a MinIO server, run with --ignored"]
+fn wrong_credentials_are_refused() {
+    let server = MinioServer::start();
+    server.bucket("backups", None);
+    let mut config = server.destination("backups", None);
+    fs::write(server.dir.path().join("wrong"), "[default]\naws_access_key_id = nobody\naws_secret_access_key = nothing\n").unwrap();
+    config.credentials_file = Some(server.dir.path().join("wrong").to_string_lossy().to_string());
+    config.profile = None;
+
+    assert!(S3::new(config).probe().is_err());
+}
@@ -0,0 +1,211 @@
+//! SFTP destination, run against a local OpenSSH server started for every test on a free port, with its own
+//! host key, client key and known hosts file. The tests need sshd and ssh-keygen and are ignored by default,
+//! run them with `cargo test -p backup-engine --test sftp -- --ignored`.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+use backup_engine::backup;
+use backup_engine::destination::Destination;
+use backup_engine::job::BackupJob;
+use backup_engine::metadata::MetadataPolicy;
+use backup_engine::restore::restore;
+use backup_engine::sftp::Sftp;
+use backup_engine::staging::SNAPSHOT_DIR_NAME;
+use errors::Error;
+use settings::settings::{BackupConfig, BackupDestination, RemoteDestination, SftpDestination};
+use tempfile::TempDir;
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+
+struct SshServer {
+    dir: TempDir,
+    port: u16,
+    process: Child,
+}
+
+impl SshServer {
+    /// Starts sshd accepting the key of the current user, panics if sshd or ssh-keygen are missing
+    fn start() -> Self {
+        let Some(sshd) = find_sshd() else {
+            panic!("sshd not found in PATH, /usr/sbin or /usr/local/sbin: install OpenSSH server to run the sftp tests");
+        };
+        let dir = tempfile::tempdir().unwrap();
+        for key in ["host_key", "client_key"] {
+            let status = Command::new("ssh-keygen")
+                .args(["-q", "-t", "ed25519", "-N", "", "-f"]).arg(dir.path().join(key))
+                .stdout(Stdio::null())
+                .status();
+            if !status.map(|status| { status.success() }).unwrap_or(false) {
+                panic!("ssh-keygen failed or is not installed, it is needed to run the sftp tests");
+            }
+        }
+        fs::copy(dir.path().join("client_key.pub"), dir.path().join("authorized_keys")).unwrap();
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        fs::write(dir.path().join("sshd_config"), format!(
+            "Port {port}\n\
+             ListenAddress 127.0.0.1\n\
+             HostKey {dir}/host_key\n\
+             PidFile {dir}/sshd.pid\n\
+             AuthorizedKeysFile {dir}/authorized_keys\n\
+             StrictModes no\n\
+             UsePAM no\n\
+             PasswordAuthentication no\n\
+             KbdInteractiveAuthentication no\n\
+             PubkeyAuthentication yes\n\
+             Subsystem sftp internal-sftp\n",
+            port = port, dir = dir.path().display())).unwrap();
+        let host_key = fs::read_to_string(dir.path().join("host_key.pub")).unwrap();
+        fs::write(dir.path().join("known_hosts"), format!("[127.0.0.1]:{} {}", port, host_key)).unwrap();
+
+        let process = Command::new(sshd)
+            .arg("-D").arg("-e")
+            .arg("-f").arg(dir.path().join("sshd_config"))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let server = SshServer { dir, port, process };
+
+        let start = Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            if start.elapsed() > Duration::from_secs(10) {
+                panic!("sshd did not accept connections on port {} within 10 seconds", port);
+            }
+            sleep(Duration::from_millis(50));
+        }
+        server
+    }
+
+    /// Directory on the server, created empty
+    fn remote_dir(&self, name: &str) -> PathBuf {
+        let remote_dir = self.dir.path().join(name);
+        fs::create_dir_all(&remote_dir).unwrap();
+        remote_dir
+    }
+
+    fn destination(&self, remote_dir: &Path) -> SftpDestination {
+        SftpDestination {
+            host: String::from("127.0.0.1"),
+            port: Some(self.port),
+            user: current_user(),
+            key_path: self.dir.path().join("client_key").to_string_lossy().to_string(),
+            remote_dir: remote_dir.to_string_lossy().to_string(),
+            known_hosts: Some(self.dir.path().join("known_hosts").to_string_lossy().to_string()),
+        }
+    }
+}
+
+impl Drop for SshServer {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn find_sshd() -> Option<PathBuf> {
+    // sshd must be run with an absolute path
+    let path = std::env::var("PATH").unwrap_or_default();
+    path.split(':').map(PathBuf::from)
+        .chain([PathBuf::from("/usr/sbin"), PathBuf::from("/usr/local/sbin")])
+        .map(|dir| { dir.join("sshd") })
+        .find(|sshd| { sshd.is_absolute() && sshd.is_file() })
+}
+
+fn current_user() -> String {
+    let output = Command::new("id").arg("-un").output().unwrap();
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+fn config(source: &Path, destination: SftpDestination) -> BackupConfig {
+    common::config(common::source(source, SOURCE_NAME), BackupDestination::Remote(RemoteDestination::Sftp(destination)))
+}
+
+fn source_tree() -> TempDir {
+    let source = tempfile::tempdir().unwrap();
+    fs::create_dir_all(source.path().join("docs/nested dir")).unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    fs::write(source.path().join("docs/report \"final\" [v2].txt"), "report").unwrap();
+    fs::write(source.path().join("docs/nested dir/data.bin"), vec![7u8; 300_000]).unwrap();
+    source
+}
+
+#[test]
+#[ignore = "needs an OpenSSH server, run with --ignored"]
+fn backup_and_restore_through_sftp() {
+    let server = SshServer::start();
+    let source = source_tree();
+    let script = source.path().join("run.sh");
+    fs::write(&script, "echo run").unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    filetime::set_file_mtime(&script, filetime::FileTime::from_system_time(modified)).unwrap();
+    let remote_dir = server.remote_dir("backups");
+    let config = config(source.path(), server.destination(&remote_dir));
+
+    // The second run replaces the snapshot of the first one
+    for _ in 0..2 {
+        let job = BackupJob::from_config(&config).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        backup::run(&job, &sender).unwrap().into_result().unwrap();
+    }
+
+    let snapshot = remote_dir.join(SNAPSHOT_DIR_NAME).join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(snapshot.join("docs/report \"final\" [v2].txt")).unwrap(), "report");
+    assert_eq!(fs::read(snapshot.join("docs/nested dir/data.bin")).unwrap().len(), 300_000);
+    assert_eq!(fs::metadata(snapshot.join("run.sh")).unwrap().permissions().mode() & 0o777, 0o750);
+    assert_eq!(fs::metadata(snapshot.join("run.sh")).unwrap().modified().unwrap(), modified);
+    let entries: Vec<String> = fs::read_dir(&remote_dir).unwrap().map(|entry| { entry.unwrap().file_name().to_string_lossy().to_string() }).collect();
+    assert!(!entries.iter().any(|entry| { entry.ends_with(".staging") || entry.ends_with(".previous") }), "{:?}", entries);
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let destination = Sftp::new(server.destination(&remote_dir));
+    let report = restore(&destination, restore_dir.path(), &MetadataPolicy::default()).unwrap().into_result().unwrap();
+    assert_eq!(report.files_restored, 4);
+    let restored = restore_dir.path().join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(restored.join("notes.txt")).unwrap(), "notes");
+    assert_eq!(fs::read(restored.join("docs/nested dir/data.bin")).unwrap(), vec![7u8; 300_000]);
+    assert_eq!(fs::metadata(restored.join("run.sh")).unwrap().modified().unwrap(), modified);
+}
+
+#[test]
+#[ignore = "needs an OpenSSH server, run with --ignored"]
+fn unknown_host_key_is_refused() {
+    let server = SshServer::start();
+    let remote_dir = server.remote_dir("backups");
+    let mut destination = server.destination(&remote_dir);
+    let other_known_hosts = server.dir.path().join("other_known_hosts");
+    fs::write(&other_known_hosts, "").unwrap();
+    destination.known_hosts = Some(other_known_hosts.to_string_lossy().to_string());
+
+    match Sftp::new(destination.clone()).probe() {
+        Err(Error::HostKeyVerificationError(host)) => { assert_eq!(host, "127.0.0.1") }
+        result => { panic!("unexpected probe result: {:?}", result) }
+    }
+
+    let source = source_tree();
+    let job = BackupJob::from_config(&config(source.path(), destination)).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let report = backup::run(&job, &sender).unwrap();
+    assert!(!report.is_success());
+    assert_eq!(fs::read_dir(&remote_dir).unwrap().count(), 0);
+}
+
+#[test]
+#[ignore = "needs an OpenSSH server, run with --ignored"]
+fn missing_remote_directory_is_reported() {
+    let server = SshServer::start();
+    let destination = Sftp::new(server.destination(&server.dir.path().join("missing")));
+
+    assert!(matches!(destination.probe(), Err(Error::DestinationNotFoundError(_))));
+    assert!(!destination.exists(Path::new(SNAPSHOT_DIR_NAME)).unwrap());
+}
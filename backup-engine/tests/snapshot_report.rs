@@ -0,0 +1,78 @@
+//! Machine-readable report written into the snapshots, against the local file system.
+#![cfg(target_os = "linux")]
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc;
+use backup_engine::backup;
+use backup_engine::copy::{copy_snapshot, CopyOptions, FailedFiles};
+use backup_engine::destination::LocalDestination;
+use backup_engine::history::{Confirmation, HistoryEntry, Trigger};
+use backup_engine::job::BackupJob;
+use backup_engine::plan::plan;
+use backup_engine::selection::select_sources;
+use backup_engine::snapshot_report::{SnapshotReport, SNAPSHOT_REPORT_FILE_NAME, SNAPSHOT_REPORT_VERSION};
+use backup_engine::staging::SNAPSHOT_DIR_NAME;
+use settings::settings::{BackupConfig, BackupSource};
+use tempfile::TempDir;
+
+mod common;
+
+fn config(source: &TempDir, destination: &TempDir) -> BackupConfig {
+    let source = BackupSource { exclude: Some(vec![String::from("*.tmp")]), ..common::source(source.path(), "source") };
+    common::config(source, common::local(destination.path()))
+}
+
+#[test]
+fn report_is_written_into_the_snapshot_and_the_history() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("first.txt"), "0123456789").unwrap();
+    fs::write(source.path().join("second.txt"), "0123456789").unwrap();
+    fs::write(source.path().join("cache.tmp"), "temporary").unwrap();
+    let config = BackupConfig { byte_budget: Some(String::from("15b")), ..config(&source, &destination) };
+
+    let job = BackupJob::from_config(&config).unwrap();
+    let (sender, _receiver) = mpsc::channel();
+    let result = backup::run(&job, &sender);
+    let entry = HistoryEntry::from_result(Trigger::Embctl, Confirmation::NotRequired, &result);
+    result.unwrap().into_result().unwrap();
+
+    let report = SnapshotReport::read(&LocalDestination::new(destination.path().to_path_buf()), Path::new(SNAPSHOT_DIR_NAME)).unwrap();
+    assert_eq!(report.version, SNAPSHOT_REPORT_VERSION);
+    // The report does not take the place of a source file of the same name
+    assert!(!destination.path().join(SNAPSHOT_DIR_NAME).join(SNAPSHOT_REPORT_FILE_NAME).exists());
+    assert_eq!(report.status, "partial");
+    assert_eq!((report.files_selected, report.bytes_selected), (2, 20));
+    assert_eq!((report.files_copied, report.bytes_copied), (1, 10));
+    assert_eq!(report.skipped.len(), 1);
+    assert_eq!(report.skipped[0].kind, "byte_budget");
+    assert!(report.errors.is_empty());
+    assert_eq!(report.filters.sources[0].exclude, Some(vec![String::from("*.tmp")]));
+    assert_eq!(report.filters.byte_budget.as_deref(), Some("15b"));
+    assert_eq!(entry.snapshot_id.as_deref(), Some(report.snapshot_id.as_str()));
+    assert_eq!(entry.reports, vec![report]);
+    // The free-text log of the previous versions is gone
+    assert!(!destination.path().join("backup_log_info.log").exists());
+}
+
+#[test]
+fn failed_copies_are_reported_with_their_error_kind() {
+    let source = tempfile::tempdir().unwrap();
+    let destination = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("removed.txt"), "removed after the selection").unwrap();
+    let config = config(&source, &destination);
+    let job = BackupJob::from_config(&config).unwrap();
+    let plan = plan(&select_sources(&config).unwrap(), &config);
+    fs::remove_file(source.path().join("removed.txt")).unwrap();
+
+    let options = CopyOptions { budget: &job.budget, workers: 1, throttle: None, metadata: &job.metadata, snapshot_id: "test" };
+    let (sender, _receiver) = mpsc::channel();
+    let err = copy_snapshot(&plan, &LocalDestination::new(destination.path().to_path_buf()), Path::new("snapshot"), &options, &sender).unwrap_err();
+
+    let FailedFiles(failed) = err.downcast_ref::<FailedFiles>().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].path, Path::new("source/removed.txt"));
+    assert_eq!(failed[0].kind, "not_found");
+    assert!(err.downcast_ref::<errors::Error>().is_some());
+}
@@ -0,0 +1,98 @@
+//! Finalization of the staging snapshot and recovery of interrupted runs on a local destination. The states
+//! a crash can leave behind are built by hand.
+
+use std::fs;
+use std::path::Path;
+use backup_engine::destination::LocalDestination;
+use backup_engine::journal::{Journal, JournalEntry};
+use backup_engine::manifest::Manifest;
+use backup_engine::staging::{finalize, metadata_path, recover, PREVIOUS_DIR_NAME, SNAPSHOT_DIR_NAME, STAGING_DIR_NAME};
+use tempfile::TempDir;
+
+/// Snapshot directory `dir_name` holding `notes.txt` with `content`, with a manifest carrying the completion
+/// marker or not
+fn snapshot(destination: &TempDir, dir_name: &str, content: &str, complete: bool) {
+    let dir = destination.path().join(dir_name);
+    fs::create_dir_all(metadata_path(&dir)).unwrap();
+    fs::write(dir.join("notes.txt"), content).unwrap();
+    let manifest = Manifest { complete, ..Manifest::new() };
+    manifest.write(&LocalDestination::new(destination.path().to_path_buf()), Path::new(dir_name)).unwrap();
+}
+
+fn notes(destination: &TempDir, dir_name: &str) -> String {
+    fs::read_to_string(destination.path().join(dir_name).join("notes.txt")).unwrap()
+}
+
+#[test]
+fn finalize_replaces_the_snapshot_and_removes_the_previous_one() {
+    let destination = tempfile::tempdir().unwrap();
+    snapshot(&destination, SNAPSHOT_DIR_NAME, "old", true);
+    snapshot(&destination, STAGING_DIR_NAME, "new", true);
+
+    finalize(&LocalDestination::new(destination.path().to_path_buf())).unwrap();
+
+    assert_eq!(notes(&destination, SNAPSHOT_DIR_NAME), "new");
+    assert!(!destination.path().join(STAGING_DIR_NAME).exists());
+    assert!(!destination.path().join(PREVIOUS_DIR_NAME).exists());
+}
+
+#[test]
+fn complete_staging_snapshot_is_finalized() {
+    let destination = tempfile::tempdir().unwrap();
+    snapshot(&destination, SNAPSHOT_DIR_NAME, "old", true);
+    snapshot(&destination, STAGING_DIR_NAME, "new", true);
+
+    let actions = recover(&LocalDestination::new(destination.path().to_path_buf()), false).unwrap();
+
+    assert_eq!(actions, vec!["completed snapshot of an interrupted backup finalized"]);
+    assert_eq!(notes(&destination, SNAPSHOT_DIR_NAME), "new");
+    assert!(!destination.path().join(STAGING_DIR_NAME).exists());
+    assert!(!destination.path().join(PREVIOUS_DIR_NAME).exists());
+}
+
+#[test]
+fn incomplete_staging_snapshot_is_removed_unless_it_is_resumed() {
+    let destination = tempfile::tempdir().unwrap();
+    let local = LocalDestination::new(destination.path().to_path_buf());
+    snapshot(&destination, SNAPSHOT_DIR_NAME, "old", true);
+    snapshot(&destination, STAGING_DIR_NAME, "new", false);
+    let mut journal = Journal::open(&local, Path::new(STAGING_DIR_NAME)).unwrap();
+    journal.append(JournalEntry { path: Path::new("notes.txt").to_path_buf(), size: 3, modified: 0 }).unwrap();
+    journal.flush().unwrap();
+
+    let actions = recover(&local, true).unwrap();
+    assert_eq!(actions, vec!["incomplete snapshot of an interrupted backup kept to be resumed, 1 files already copied"]);
+    assert_eq!(notes(&destination, STAGING_DIR_NAME), "new");
+
+    let actions = recover(&local, false).unwrap();
+    assert_eq!(actions, vec!["incomplete snapshot of an interrupted backup removed, 1 copied files discarded"]);
+    assert!(!destination.path().join(STAGING_DIR_NAME).exists());
+    assert_eq!(notes(&destination, SNAPSHOT_DIR_NAME), "old");
+}
+
+#[test]
+fn previous_snapshot_is_restored_when_the_new_one_did_not_take_its_place() {
+    let destination = tempfile::tempdir().unwrap();
+    // The process died between the two renames of `finalize`, after an incomplete staging was removed
+    snapshot(&destination, PREVIOUS_DIR_NAME, "old", true);
+
+    let actions = recover(&LocalDestination::new(destination.path().to_path_buf()), false).unwrap();
+
+    assert_eq!(actions, vec!["previous snapshot restored"]);
+    assert_eq!(notes(&destination, SNAPSHOT_DIR_NAME), "old");
+    assert!(!destination.path().join(PREVIOUS_DIR_NAME).exists());
+}
+
+#[test]
+fn previous_snapshot_is_removed_once_the_new_one_is_in_place() {
+    let destination = tempfile::tempdir().unwrap();
+    // The process died after the two renames of `finalize`, before the previous snapshot was removed
+    snapshot(&destination, SNAPSHOT_DIR_NAME, "new", true);
+    snapshot(&destination, PREVIOUS_DIR_NAME, "old", true);
+
+    let actions = recover(&LocalDestination::new(destination.path().to_path_buf()), false).unwrap();
+
+    assert!(actions.is_empty());
+    assert_eq!(notes(&destination, SNAPSHOT_DIR_NAME), "new");
+    assert!(!destination.path().join(PREVIOUS_DIR_NAME).exists());
+}
@@ -0,0 +1,312 @@
+//! WebDAV destination, run against a local WebDAV server started in the test process for every test, serving a
+//! temporary directory under the Nextcloud URL layout (`/remote.php/dav/files/<user>`, `/remote.php/dav/uploads/<user>`).
+//! The server checks the basic authentication and assembles chunked uploads like Nextcloud.
+#![cfg(target_os = "linux")]
+
+use std::convert::Infallible;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::sync::{mpsc, Arc};
+use base64::Engine;
+use backup_engine::backup;
+use backup_engine::destination::Destination;
+use backup_engine::job::BackupJob;
+use backup_engine::journal::{Journal, JournalEntry};
+use backup_engine::metadata::MetadataPolicy;
+use backup_engine::restore::restore;
+use backup_engine::staging::{metadata_path, recover, SNAPSHOT_DIR_NAME, STAGING_DIR_NAME};
+use backup_engine::throttle::Throttle;
+use backup_engine::webdav::{WebDav, CHUNK_SIZE};
+use dav_server::body::Body;
+use dav_server::fakels::FakeLs;
+use dav_server::localfs::LocalFs;
+use dav_server::DavHandler;
+use errors::Error;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use settings::settings::{BackupConfig, BackupDestination, RemoteDestination, WebDavDestination};
+use tempfile::TempDir;
+
+mod common;
+
+const SOURCE_NAME: &str = "source";
+const USER: &str = "alice";
+const PASSWORD: &str = "app-password";
+const PREFIX: &str = "/remote.php/dav";
+
+struct DavServer {
+    dir: TempDir,
+    port: u16,
+    state: Arc<ServerState>,
+    _runtime: tokio::runtime::Runtime,
+}
+
+struct ServerState {
+    root: PathBuf,
+
+    handler: DavHandler,
+
+    /// Chunks received by the uploads collection
+    chunks_received: AtomicUsize,
+
+    /// Refuses the next assembly of a chunked upload, as when the connection drops before it
+    fail_next_assembly: AtomicBool,
+
+    /// Time the slowest PUT took to be received, in milliseconds
+    slowest_put_ms: AtomicU64,
+}
+
+impl DavServer {
+    fn start() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("files").join(USER).join("backups")).unwrap();
+        fs::create_dir_all(dir.path().join("uploads").join(USER)).unwrap();
+        let state = Arc::new(ServerState {
+            root: dir.path().to_path_buf(),
+            handler: DavHandler::builder()
+                .filesystem(LocalFs::new(dir.path(), false, false, false))
+                .locksystem(FakeLs::new())
+                .strip_prefix(PREFIX)
+                .build_handler(),
+            chunks_received: AtomicUsize::new(0),
+            fail_next_assembly: AtomicBool::new(false),
+            slowest_put_ms: AtomicU64::new(0),
+        });
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        listener.set_nonblocking(true).unwrap();
+        let server_state = state.clone();
+        runtime.spawn(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(move |request| { handle(request, state.clone()) });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+                });
+            }
+        });
+        DavServer { dir, port, state, _runtime: runtime }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}/files/{}/{}", self.port, PREFIX, USER, path)
+    }
+
+    /// Local directory of `path`, relative to the files of the user
+    fn local(&self, path: &str) -> PathBuf {
+        self.dir.path().join("files").join(USER).join(path)
+    }
+
+    fn destination(&self, path: &str) -> WebDavDestination {
+        let password_file = self.dir.path().join("password");
+        fs::write(&password_file, format!("{}\n", PASSWORD)).unwrap();
+        WebDavDestination {
+            url: self.url(path),
+            user: USER.to_string(),
+            password_file: Some(password_file.to_string_lossy().to_string()),
+        }
+    }
+}
+
+async fn handle(request: Request<Incoming>, state: Arc<ServerState>) -> Result<Response<Body>, Infallible> {
+    let expected = format!("Basic {}", base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", USER, PASSWORD)));
+    if request.headers().get("Authorization").map(|value| { value.as_bytes() != expected.as_bytes() }).unwrap_or(true) {
+        return Ok(Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header("WWW-Authenticate", "Basic realm=\"test\"")
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    let path = request.uri().path().to_string();
+    if request.method() == "PUT" && path.contains("/uploads/") {
+        state.chunks_received.fetch_add(1, Ordering::SeqCst);
+    }
+    if request.method() == "MOVE" && path.ends_with("/.file") {
+        let status = assemble(&state, &request);
+        return Ok(Response::builder().status(status).body(Body::empty()).unwrap());
+    }
+    let is_put = request.method() == "PUT";
+    let start = Instant::now();
+    let response = state.handler.handle(request).await;
+    if is_put {
+        state.slowest_put_ms.fetch_max(start.elapsed().as_millis() as u64, Ordering::SeqCst);
+    }
+    Ok(response)
+}
+
+/// Concatenates the chunks of an upload into its destination, then removes the upload
+fn assemble(state: &ServerState, request: &Request<Incoming>) -> StatusCode {
+    if state.fail_next_assembly.swap(false, Ordering::SeqCst) {
+        return StatusCode::CONFLICT;
+    }
+    let local = |path: &str| -> PathBuf { state.root.join(path.split_once(PREFIX).unwrap().1.trim_start_matches('/')) };
+    let upload = local(request.uri().path().trim_end_matches("/.file"));
+    let destination = local(request.headers().get("Destination").unwrap().to_str().unwrap());
+
+    let mut chunks: Vec<PathBuf> = fs::read_dir(&upload).unwrap().map(|entry| { entry.unwrap().path() }).collect();
+    chunks.sort();
+    let content: Vec<u8> = chunks.iter().flat_map(|chunk| { fs::read(chunk).unwrap() }).collect();
+    let total_length: usize = request.headers().get("OC-Total-Length").unwrap().to_str().unwrap().parse().unwrap();
+    if content.len() != total_length {
+        return StatusCode::BAD_REQUEST;
+    }
+    fs::write(destination, content).unwrap();
+    fs::remove_dir_all(upload).unwrap();
+    StatusCode::CREATED
+}
+
+fn config(source: &Path, destination: WebDavDestination) -> BackupConfig {
+    common::config(common::source(source, SOURCE_NAME), BackupDestination::Remote(RemoteDestination::WebDav(destination)))
+}
+
+#[test]
+fn backup_and_restore_through_webdav() {
+    let server = DavServer::start();
+    let source = tempfile::tempdir().unwrap();
+    fs::create_dir_all(source.path().join("docs/nested dir")).unwrap();
+    fs::write(source.path().join("notes.txt"), "notes").unwrap();
+    fs::write(source.path().join("docs/report \"final\" [v2] & 100%.txt"), "report").unwrap();
+    fs::write(source.path().join("docs/nested dir/data.bin"), vec![7u8; 300_000]).unwrap();
+    let config = config(source.path(), server.destination("backups"));
+
+    // The second run replaces the snapshot of the first one
+    for _ in 0..2 {
+        let job = BackupJob::from_config(&config).unwrap();
+        let (sender, _receiver) = mpsc::channel();
+        backup::run(&job, &sender).unwrap().into_result().unwrap();
+    }
+
+    let snapshot = server.local("backups").join(SNAPSHOT_DIR_NAME).join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(snapshot.join("docs/report \"final\" [v2] & 100%.txt")).unwrap(), "report");
+    assert_eq!(fs::read(snapshot.join("docs/nested dir/data.bin")).unwrap().len(), 300_000);
+    let entries: Vec<String> = fs::read_dir(server.local("backups")).unwrap().map(|entry| { entry.unwrap().file_name().to_string_lossy().to_string() }).collect();
+    assert!(!entries.iter().any(|entry| { entry.ends_with(".staging") || entry.ends_with(".previous") }), "{:?}", entries);
+
+    let restore_dir = tempfile::tempdir().unwrap();
+    let destination = WebDav::new(server.destination("backups"));
+    let files = destination.list_files(&Path::new(SNAPSHOT_DIR_NAME).join(SOURCE_NAME)).unwrap();
+    assert_eq!(files.get(Path::new("docs/report \"final\" [v2] & 100%.txt")), Some(&6));
+    assert_eq!(files.get(Path::new("docs/nested dir/data.bin")), Some(&300_000));
+    let report = restore(&destination, restore_dir.path(), &MetadataPolicy::default()).unwrap().into_result().unwrap();
+    assert_eq!(report.files_restored, 3);
+    let restored = restore_dir.path().join(SOURCE_NAME);
+    assert_eq!(fs::read_to_string(restored.join("notes.txt")).unwrap(), "notes");
+    assert_eq!(fs::read(restored.join("docs/nested dir/data.bin")).unwrap(), vec![7u8; 300_000]);
+}
+
+#[test]
+fn interrupted_chunked_upload_is_resumed() {
+    let server = DavServer::start();
+    let source = tempfile::tempdir().unwrap();
+    let large: Vec<u8> = (0..CHUNK_SIZE * 2 + 300_000).map(|index| { (index % 251) as u8 }).collect();
+    fs::write(source.path().join("large.bin"), &large).unwrap();
+    let destination = WebDav::new(server.destination("backups"));
+
+    server.state.fail_next_assembly.store(true, Ordering::SeqCst);
+    assert!(destination.put_file(&source.path().join("large.bin"), Path::new("large.bin"), &MetadataPolicy::default(), None).is_err());
+    assert_eq!(server.state.chunks_received.load(Ordering::SeqCst), 3);
+    assert!(!server.local("backups/large.bin").exists());
+
+    // The chunks already on the server are not sent again
+    destination.put_file(&source.path().join("large.bin"), Path::new("large.bin"), &MetadataPolicy::default(), None).unwrap();
+    assert_eq!(server.state.chunks_received.load(Ordering::SeqCst), 3);
+    assert_eq!(fs::read(server.local("backups/large.bin")).unwrap(), large);
+    assert_eq!(fs::read_dir(server.dir.path().join("uploads").join(USER)).unwrap().count(), 0);
+}
+
+#[test]
+fn partial_uploads_are_removed_with_the_discarded_snapshot() {
+    let server = DavServer::start();
+    let source = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("large.bin"), vec![5u8; CHUNK_SIZE as usize + 1]).unwrap();
+    let destination = WebDav::new(server.destination("backups"));
+    let staging = Path::new(STAGING_DIR_NAME);
+    destination.create_dir_all(staging).unwrap();
+    let uploads = server.dir.path().join("uploads").join(USER);
+    fs::create_dir_all(uploads.join("emergency-backup-0123abcd-other-folder")).unwrap();
+
+    server.state.fail_next_assembly.store(true, Ordering::SeqCst);
+    assert!(destination.put_file(&source.path().join("large.bin"), &staging.join("large.bin"), &MetadataPolicy::default(), None).is_err());
+    assert_eq!(fs::read_dir(&uploads).unwrap().count(), 2);
+
+    recover(&destination, false).unwrap();
+
+    // The upload of another destination folder is left alone
+    let left: Vec<String> = fs::read_dir(&uploads).unwrap().map(|entry| { entry.unwrap().file_name().to_string_lossy().to_string() }).collect();
+    assert_eq!(left, ["emergency-backup-0123abcd-other-folder"]);
+    assert!(!server.local("backups").join(STAGING_DIR_NAME).exists());
+}
+
+#[test]
+fn journal_is_written_in_segments() {
+    let server = DavServer::start();
+    let destination = WebDav::new(server.destination("backups"));
+    let staging = Path::new(STAGING_DIR_NAME);
+    destination.create_dir_all(&metadata_path(staging)).unwrap();
+    let entry = |index: u64| -> JournalEntry { JournalEntry { path: PathBuf::from(format!("file-{}", index)), size: index, modified: 0 } };
+
+    let mut journal = Journal::open(&destination, staging).unwrap();
+    for index in 0..3 {
+        journal.append(entry(index)).unwrap();
+        journal.flush().unwrap();
+    }
+
+    // Every flush writes a new object instead of rewriting the journal
+    let staged = metadata_path(&server.local("backups").join(STAGING_DIR_NAME));
+    for index in 0..3 {
+        assert_eq!(fs::read_to_string(staged.join(format!("journal-{}.jsonl", index))).unwrap().lines().count(), 1);
+    }
+    let mut journal = Journal::open(&destination, staging).unwrap();
+    assert_eq!(journal.len(), 3);
+    journal.append(entry(3)).unwrap();
+    journal.flush().unwrap();
+    assert!(staged.join("journal-3.jsonl").exists());
+    assert_eq!(Journal::read(&destination, staging).unwrap().len(), 4);
+
+    Journal::remove(&destination, staging).unwrap();
+    assert_eq!(fs::read_dir(&staged).unwrap().count(), 0);
+}
+
+#[test]
+fn bandwidth_limit_paces_the_upload() {
+    let server = DavServer::start();
+    let source = tempfile::tempdir().unwrap();
+    fs::write(source.path().join("data.bin"), vec![3u8; 300_000]).unwrap();
+    let destination = WebDav::new(server.destination("backups"));
+
+    let throttle = Throttle::new(200_000);
+    destination.put_file(&source.path().join("data.bin"), Path::new("data.bin"), &MetadataPolicy::default(), Some(&throttle)).unwrap();
+
+    // 1.5 seconds at 200 KB/s: the request itself is slowed down, not the worker once it is over
+    assert!(Duration::from_millis(server.state.slowest_put_ms.load(Ordering::SeqCst)) >= Duration::from_secs(1));
+    assert_eq!(fs::read(server.local("backups/data.bin")).unwrap(), vec![3u8; 300_000]);
+}
+
+#[test]
+fn wrong_password_is_refused() {
+    let server = DavServer::start();
+    let mut config = server.destination("backups");
+    fs::write(server.dir.path().join("wrong"), "nothing").unwrap();
+    config.password_file = Some(server.dir.path().join("wrong").to_string_lossy().to_string());
+
+    assert!(matches!(WebDav::new(config).probe(), Err(Error::DestinationUnreachableError { .. })));
+}
+
+#[test]
+fn missing_folder_is_reported() {
+    let server = DavServer::start();
+    let destination = WebDav::new(server.destination("missing"));
+
+    assert!(matches!(destination.probe(), Err(Error::DestinationNotFoundError(_))));
+    assert!(!destination.exists(Path::new(SNAPSHOT_DIR_NAME)).unwrap());
+}
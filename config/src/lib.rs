@@ -25,7 +25,8 @@ pub struct AppConfig {
     pub pop_up_window_size: (f32, f32),
     pub main_window_gui_size: (f32, f32),
     pub installation_window_gui_size: (f32, f32),
-    pub cpu_consumption_log_interval_msec: u128
+    pub cpu_consumption_log_interval_msec: u128,
+    pub progress_publish_interval_msec: u128
 }
 
 pub fn app_config() -> AppConfig {
@@ -42,13 +43,14 @@ pub fn app_config() -> AppConfig {
         pop_up_window_size: (400f32, 100f32),
         main_window_gui_size: (1024f32, 512f32),
         installation_window_gui_size: (512f32, 256f32),
-        cpu_consumption_log_interval_msec: 120000
+        cpu_consumption_log_interval_msec: 120000,
+        progress_publish_interval_msec: 500
     }
 }
 
 
 /// Returns the configuration for the logger.
-
+///
 /// The logger configuration writes logs to the `.emergency-backup/logs` directory within the user's home directory.
 /// When the log file reaches 1MB in size, it's compressed (gzip) and archived in a new directory called `.emergency-backup/logs/old`.
 /// Up to two archived log files are stored using a rolling mechanism.
@@ -62,7 +64,7 @@ pub fn log_config(debug: bool) -> anyhow::Result<Config> {
     };
 
     let trigger_size = byte_unit::Byte::parse_str("1mb", false).unwrap().as_u64();
-    let trigger_general = Box::new(SizeTrigger::new(trigger_size.clone()));
+    let trigger_general = Box::new(SizeTrigger::new(trigger_size));
     let trigger_cpu_consumption = Box::new(SizeTrigger::new(trigger_size));
 
     let roller_pattern_general = config_dir.clone().join("logs/old/emergency_backup_{}.log.gz");
@@ -118,6 +120,8 @@ pub fn log_config(debug: bool) -> anyhow::Result<Config> {
                 .appender("cpu_consumption_ap")
                 .build("cpu_consumption", LevelFilter::Trace),
         )
+        // The HTTP client of the object storage destinations logs every request and its headers
+        .logger(Logger::builder().build("ureq", LevelFilter::Info))
         .build(Root::builder().appender("stdout").build(LevelFilter::Debug)) {
         Ok(build) => { Ok(build) },
         Err(err) => { eprintln!("Log Configuration creation failed."); bail!(err) }
@@ -1,11 +1,27 @@
 use std::fmt::Display;
 use std::path::PathBuf;
 use anyhow::bail;
+use backup_engine::{format_duration, format_size};
+use backup_engine::backup;
+use backup_engine::destination::open;
+use backup_engine::dry_run::dry_run;
+use backup_engine::history::{read_history, record, Confirmation, HistoryEntry, Outcome, Trigger};
+use backup_engine::job::BackupJob;
+use backup_engine::metadata::MetadataPolicy;
+use backup_engine::progress::{read_published, ProgressEvent};
+use backup_engine::report::BackupReport;
+use backup_engine::removable::{removable_volumes, MARKER_FILE_NAME};
+use backup_engine::restore::{find_snapshot, restore};
+use backup_engine::s3::S3;
+use backup_engine::selection::select_sources;
+use config::app_config;
+use service_manager_util::notify;
+use chrono::{DateTime, Local};
 use clap::{Args, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info};
-use errors::Error::{DaemonStartupError, DaemonStopError, ExtensionOnlyValueError, LoadSettingsError};
-use serde::Serialize;
-use settings::settings::{load_settings, set_destination, set_extension_only, set_extension_types, set_millis_update_time, set_mode, set_source, set_tolerance, set_tracking_window_sec};
+use errors::Error::{BackupSourceError, DaemonStartupError, FileTransferError, DaemonStopError, ExtensionOnlyValueError, ConfigValidationError, NoDestinationAvailableError};
+use settings::settings::{add_destination, add_remote_destination, add_source, check_settings, load_settings, remove_destination, remove_source, set_destination, set_destination_policy, set_exclude, set_exclude_presets, set_extension_only, set_extension_types, add_hook, clear_hooks, set_include, set_max_file_size, set_modified_within, set_mtime_range, set_millis_update_time, set_bandwidth_limit, set_budget, set_copy_workers, set_low_priority, set_preserve, set_priority, set_priority_paths, set_same_device_policy, set_source, set_symlink_policy, set_tolerance, set_tracking_window_sec, BackupDestination, BackupHook, BackupSource, RemoteDestination, RemovableDestination, S3Destination, SftpDestination, WebDavDestination};
 
 /// This tool allow to perform emergency backups using a mouse command
 #[derive(Parser)]
@@ -34,6 +50,21 @@ enum Commands {
     /// Print current configuration
     ShowConfig,
 
+    /// Check the configuration file
+    Config(ConfigArg),
+
+    /// List the files that would be copied under the current rules
+    Preview,
+
+    /// Run the configured backup now, without the mouse command and without the daemon
+    Backup(BackupArg),
+
+    /// Restore the last snapshot into a directory, reapplying the metadata policy
+    Restore(RestoreArg),
+
+    /// List the past backup triggers, most recent first: gesture or manual, confirmation, snapshot, destinations, size and errors
+    History(HistoryArg),
+
     /// Mouse position sampling frequency. Higher frequency results in higher CPU usage. (default: 200ms)
     SetMillisUpdateTime(MillisUpdateTimeArg),
 
@@ -43,20 +74,98 @@ enum Commands {
     /// Tolerance for touching in the corners of the display. (default: 5px)
     SetTolerance(ToleranceArgs),
 
-    /// Backup source path
+    /// Replace every configured backup source with the given path
     SetSource(SourceArg),
 
-    /// Backup destination path, a new emergency-backup/ folder will be created containing the content of the backup_source.
+    /// Add a file or directory to the backup sources
+    AddSource(AddSourceArg),
+
+    /// Remove a backup source, given its name in the snapshot or its path
+    RemoveSource(RemoveSourceArg),
+
+    /// Replace every configured backup destination with the given path
     SetDestination(DestinationArg),
 
+    /// Add a backup destination at the end of the destination list
+    AddDestination(DestinationArg),
+
+    /// Add a directory on an SSH server at the end of the destination list, written through sftp
+    AddSftpDestination(SftpDestinationArg),
+
+    /// Add a bucket of an S3-compatible object storage (AWS S3, MinIO, ...) at the end of the destination list
+    AddS3Destination(S3DestinationArg),
+
+    /// Add a folder on a WebDAV server (Nextcloud, ownCloud, ...) at the end of the destination list
+    AddWebdavDestination(WebDavDestinationArg),
+
+    /// Add a removable drive, picked among the plugged in ones when the backup starts, at the end of the destination list
+    AddRemovableDestination(RemovableDestinationArg),
+
+    /// List the removable drives currently mounted, with their label, UUID and marker file
+    RemovableDrives,
+
+    /// Remove a backup destination
+    RemoveDestination(RemoveDestinationArg),
+
+    /// 'first-available' or 'all' destinations (default: first-available)
+    SetDestinationPolicy(DestinationPolicyArg),
+
+    /// 'warn' or 'refuse' when a destination is on the same physical device as a source (default: warn)
+    SetSameDevicePolicy(SameDevicePolicyArg),
+
     /// If true, only files with an extension contained in extension_type vector will be copied. (default: false)
     SetExtensionOnly(ExtensionOnlyArg),
 
     /// List of backup target file extensions, separated by comma or space
     SetExtensionType(ExtensionTypeArg),
 
-    /// 'file' or 'folder' mode (default: Folder)
-    SetMode(ModeArg),
+    /// Glob rules a file must match to be copied, e.g. '*.rs' 'docs/**' (no rule: every file is copied)
+    SetInclude(RulesArg),
+
+    /// Gitignore-style rules of files and directories to skip, e.g. '*.tmp' 'build/'
+    SetExclude(RulesArg),
+
+    /// Built-in exclude rule sets to enable: rust-target, node-modules, git-objects
+    SetExcludePresets(ExcludePresetsArg),
+
+    /// Skip files bigger than the given size, e.g. '100mb' (no argument: no limit)
+    SetMaxFileSize(MaxFileSizeArg),
+
+    /// Copy only files modified within the given time window, e.g. '12h', '7days' (no argument: no limit)
+    SetModifiedWithin(ModifiedWithinArg),
+
+    /// Copy only files modified between the given dates, 'YYYY-MM-DD' or RFC 3339
+    SetMtimeRange(MtimeRangeArg),
+
+    /// Order files are copied in: paths, recent, smallest (the first criterion has the highest weight, no argument: walk order)
+    SetPriority(PriorityArg),
+
+    /// Paths copied first when the 'paths' priority criterion is enabled, absolute or relative to the source root
+    SetPriorityPaths(PriorityPathsArg),
+
+    /// Limit the time spent or the data copied by a backup, the files left out are listed in the snapshot manifest
+    SetBudget(BudgetArg),
+
+    /// Number of files copied at the same time (no argument: chosen by destination, sequential on spinning disks and USB 2 drives)
+    SetCopyWorkers(CopyWorkersArg),
+
+    /// Limit the data written per second by a backup, e.g. '20mb' (no argument: no limit)
+    SetBandwidthLimit(BandwidthLimitArg),
+
+    /// If true, backups run with low CPU and I/O priority to keep the machine responsive. (default: false)
+    SetLowPriority(LowPriorityArg),
+
+    /// 'follow' copies what symbolic links point to, 'preserve' recreates the links, 'skip' leaves them out (default: follow)
+    SetSymlinkPolicy(SymlinkPolicyArg),
+
+    /// Choose the metadata kept by backups and restores: permissions, times, extended attributes, ACLs
+    SetPreserve(PreserveArg),
+
+    /// Add a command run before ('pre') or after ('post') every backup, with the snapshot path in EMERGENCY_BACKUP_SNAPSHOT
+    AddHook(AddHookArg),
+
+    /// Remove every pre-backup ('pre') or post-backup ('post') hook
+    ClearHooks(ClearHooksArg),
 }
 
 
@@ -80,46 +189,414 @@ struct SourceArg {
     source: PathBuf,
 }
 
+#[derive(Debug, Args)]
+struct AddSourceArg {
+    /// File or directory to back up, the mode ('file' or 'folder') is detected from the path
+    source: PathBuf,
+
+    /// Name of the entry inside the snapshot (default: the file name of the path)
+    #[arg(short, long)]
+    name: Option<String>,
+
+    /// Extensions to filter this source by, overriding the global extension filter
+    #[arg(short, long, num_args = 1..)]
+    extension_type: Option<Vec<String>>,
+
+    /// Include rules applied to this source only, in addition to the global ones
+    #[arg(short, long, num_args = 1..)]
+    include: Option<Vec<String>>,
+
+    /// Exclude rules applied to this source only, in addition to the global ones
+    #[arg(short = 'x', long, num_args = 1..)]
+    exclude: Option<Vec<String>>,
+
+    /// Save the uncommitted work (unpushed commits, diff, untracked files, stash) of the git repositories
+    /// found in the directory instead of copying its files
+    #[arg(long)]
+    git: bool,
+}
+
+#[derive(Debug, Args)]
+struct RemoveSourceArg {
+    /// Name of the entry inside the snapshot or path of the source
+    source: String,
+}
+
 #[derive(Debug, Args)]
 struct DestinationArg {
-    /// Backup destination path, a new emergency-backup/ folder will be created containing the content of the backup_source.
+    /// Backup destination path, a new emergency-backup/ folder will be created containing the content of the backup sources.
     destination: PathBuf,
 }
 
 #[derive(Debug, Args)]
-struct ExtensionOnlyArg {
-    /// If true, only files with an extension contained in extension_type vector will be copied.
-    extension_only: String,
+struct SftpDestinationArg {
+    /// SSH server name or address
+    #[arg(long)]
+    host: String,
+
+    /// SSH port
+    #[arg(long, default_value_t = 22)]
+    port: u16,
+
+    /// User to log in as
+    #[arg(long)]
+    user: String,
+
+    /// Private key used to log in, without passphrase
+    #[arg(long)]
+    key: PathBuf,
+
+    /// Directory on the server the emergency-backup/ folder is created in, absolute or relative to the user home
+    #[arg(long)]
+    remote_dir: String,
+
+    /// Known hosts file the server key is verified against (default: ~/.ssh/known_hosts)
+    #[arg(long)]
+    known_hosts: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct S3DestinationArg {
+    /// Base URL of the service, e.g. https://s3.eu-west-1.amazonaws.com or http://minio.local:9000
+    #[arg(long)]
+    endpoint: String,
+
+    #[arg(long)]
+    bucket: String,
+
+    /// Key prefix the emergency-backup/ objects are written under (default: the bucket root)
+    #[arg(long)]
+    prefix: Option<String>,
+
+    /// Region the requests are signed for (default: us-east-1)
+    #[arg(long)]
+    region: Option<String>,
+
+    /// AWS shared credentials file holding the access keys (default: the AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY environment variables)
+    #[arg(long)]
+    credentials_file: Option<PathBuf>,
+
+    /// Profile of the credentials file (default: default)
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Create the bucket if it does not exist
+    #[arg(long, default_value_t = false)]
+    create_bucket: bool,
+}
+
+#[derive(Debug, Args)]
+struct WebDavDestinationArg {
+    /// URL of the folder, e.g. https://cloud.example.com/remote.php/dav/files/alice/backups
+    #[arg(long)]
+    url: String,
+
+    #[arg(long)]
+    user: String,
+
+    /// File holding the password, an app password for Nextcloud and ownCloud (default: the WEBDAV_PASSWORD environment variable)
+    #[arg(long)]
+    password_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+struct RemovableDestinationArg {
+    /// File system label the drive must have
+    #[arg(long)]
+    label: Option<String>,
+
+    /// File system UUID the drive must have
+    #[arg(long)]
+    uuid: Option<String>,
+
+    /// The drive must hold a .emergency-backup-target file in its root
+    #[arg(long, default_value_t = false)]
+    marker: bool,
+
+    /// Directory of the drive the emergency-backup/ folder is created in (default: the root of the drive)
+    #[arg(long)]
+    dir: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct RemoveDestinationArg {
+    /// Path of the destination to remove, or URL of a remote one (sftp://user@host:port/remote_dir, s3://endpoint/bucket/prefix, davs://user@host/path),
+    /// or the criteria of a removable drive (removable:label=EMERGENCY,marker)
+    destination: String,
+}
+
+#[derive(Debug, Clone, Args)]
+struct DestinationPolicyArg {
+    /// 'first-available' writes the snapshot in the first reachable and writable destination, 'all' in every destination
+    policy: DestinationPolicyEnum
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, Default)]
+enum DestinationPolicyEnum {
+    #[default]
+    FirstAvailable,
+    All
+}
+
+impl Display for DestinationPolicyEnum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            DestinationPolicyEnum::FirstAvailable => { String::from("first-available") }
+            DestinationPolicyEnum::All => { String::from("all") }
+        };
+        write!(f, "{}", str)
+    }
 }
+
 #[derive(Debug, Clone, Args)]
-struct ModeArg {
-    /// 'Folder' or 'File', if the backup has as objective a file or an entire directory
-    mode: ModeEnum
+struct SameDevicePolicyArg {
+    /// 'warn' copies anyway reporting a warning, 'refuse' does not use the destination
+    policy: SameDevicePolicyEnum
 }
 
-#[derive(clap::ValueEnum, Clone, Debug, Default, Serialize)]
-enum ModeEnum {
+#[derive(clap::ValueEnum, Clone, Debug, Default)]
+enum SameDevicePolicyEnum {
     #[default]
-    Folder,
-    File
+    Warn,
+    Refuse
 }
 
-impl Display for ModeEnum {
+impl Display for SameDevicePolicyEnum {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let str = match self {
-            ModeEnum::Folder => { String::from("folder") }
-            ModeEnum::File => { String::from("file") }
+            SameDevicePolicyEnum::Warn => { String::from("warn") }
+            SameDevicePolicyEnum::Refuse => { String::from("refuse") }
         };
         write!(f, "{}", str)
     }
 }
 
+#[derive(Debug, Args)]
+struct ExtensionOnlyArg {
+    /// If true, only files with an extension contained in extension_type vector will be copied.
+    extension_only: String,
+}
 #[derive(Debug, Args)]
 struct ExtensionTypeArg {
     /// A space separated list of file extensions to filter the backup source files
     extension_type: Vec<String>,
 }
 
+#[derive(Debug, Args)]
+struct RulesArg {
+    /// A space separated list of rules, quote them to avoid shell expansion
+    rules: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct ExcludePresetsArg {
+    /// A space separated list of preset names
+    presets: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct MaxFileSizeArg {
+    /// Maximum file size, e.g. '500kb', '100mb', '1gb'
+    size: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct ModifiedWithinArg {
+    /// Time window, e.g. '30min', '12h', '7days'
+    window: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct MtimeRangeArg {
+    /// Oldest modification date of the copied files
+    #[arg(long)]
+    from: Option<String>,
+
+    /// Newest modification date of the copied files
+    #[arg(long)]
+    to: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct BackupArg {
+    #[command(subcommand)]
+    action: Option<BackupAction>,
+
+    /// Print the files that would be copied, the total size and the estimated duration and run the
+    /// destination checks, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Continue the snapshot left incomplete by an interrupted backup, skipping the files already copied.
+    /// Without it the incomplete snapshot is discarded and the backup starts over
+    #[arg(long)]
+    resume: bool,
+}
+
+#[derive(Debug, Args)]
+struct ConfigArg {
+    #[command(subcommand)]
+    action: ConfigAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigAction {
+    /// List every problem of the configuration file with the field, the line and column and a suggested fix
+    Check,
+}
+
+#[derive(Debug, Subcommand)]
+enum BackupAction {
+    /// Run the backup now (same as 'embctl backup')
+    Now,
+}
+
+#[derive(Debug, Args)]
+struct PriorityArg {
+    /// A space separated list of criteria
+    criteria: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct PriorityPathsArg {
+    /// A space separated list of paths, in order of importance
+    paths: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+struct BudgetArg {
+    /// Maximum copy time, e.g. '30s', '2min' (omitted: no limit)
+    #[arg(long)]
+    time: Option<String>,
+
+    /// Maximum amount of data copied, e.g. '500mb', '2gb' (omitted: no limit)
+    #[arg(long)]
+    bytes: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct CopyWorkersArg {
+    workers: Option<u32>,
+}
+
+#[derive(Debug, Args)]
+struct RestoreArg {
+    /// Directory the snapshot tree is restored into
+    to: PathBuf,
+
+    /// Destination holding the snapshot (default: the first destination with a complete snapshot)
+    #[arg(long)]
+    from: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct HistoryArg {
+    /// Number of entries listed, 0 for all of them
+    #[arg(short = 'n', long, default_value_t = 20)]
+    limit: usize,
+
+    /// Only list the backups started this way
+    #[arg(long)]
+    trigger: Option<TriggerEnum>,
+
+    /// Only list the backups that failed
+    #[arg(long)]
+    failed: bool,
+
+    /// Print the entries as JSON lines, as stored in the history file
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum TriggerEnum {
+    Gesture,
+    Gui,
+    Embctl
+}
+
+#[derive(Debug, Clone, Args)]
+struct SymlinkPolicyArg {
+    policy: SymlinkPolicyEnum
+}
+
+#[derive(clap::ValueEnum, Clone, Debug, Default)]
+enum SymlinkPolicyEnum {
+    #[default]
+    Follow,
+    Preserve,
+    Skip
+}
+
+impl Display for SymlinkPolicyEnum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let str = match self {
+            SymlinkPolicyEnum::Follow => { String::from("follow") }
+            SymlinkPolicyEnum::Preserve => { String::from("preserve") }
+            SymlinkPolicyEnum::Skip => { String::from("skip") }
+        };
+        write!(f, "{}", str)
+    }
+}
+
+#[derive(Debug, Args)]
+struct PreserveArg {
+    /// Keep the permissions of the original files (default: true)
+    #[arg(long)]
+    permissions: Option<bool>,
+
+    /// Keep the access and modification time of the original files (default: true)
+    #[arg(long)]
+    mtime: Option<bool>,
+
+    /// Copy the extended attributes, Unix only (default: false)
+    #[arg(long)]
+    xattrs: Option<bool>,
+
+    /// Copy the POSIX ACLs, Linux only (default: false)
+    #[arg(long)]
+    acls: Option<bool>,
+}
+
+#[derive(Debug, Args)]
+struct AddHookArg {
+    phase: HookPhaseEnum,
+
+    /// Command line, run by 'sh -c' ('cmd /C' on Windows)
+    command: String,
+
+    /// Seconds after which the command is killed (default: 60)
+    #[arg(long)]
+    timeout_sec: Option<u64>,
+
+    /// A failure of a pre-backup hook aborts the backup, of a post-backup hook skips the following hooks
+    #[arg(long)]
+    abort_on_failure: bool,
+}
+
+#[derive(Debug, Args)]
+struct ClearHooksArg {
+    phase: HookPhaseEnum,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum HookPhaseEnum {
+    Pre,
+    Post
+}
+
+#[derive(Debug, Args)]
+struct BandwidthLimitArg {
+    /// Maximum data written per second, e.g. '500kb', '20mb'
+    limit: Option<String>,
+}
+
+#[derive(Debug, Args)]
+struct LowPriorityArg {
+    #[arg(action = clap::ArgAction::Set)]
+    low_priority: bool,
+}
+
 #[derive(Debug, Args)]
 struct TrackingWindowSecArgs {
     /// An integer that represent the time in seconds
@@ -135,20 +612,30 @@ fn main() -> anyhow::Result<()> {
         Ok(config) => { config },
         Err(err) => { eprintln!("Logging configuration creation FAILED: {:?}", err); bail!(err) }
     };
-    let config = log4rs::init_config(log_config);
-    if config.is_err() {
+    if log4rs::init_config(log_config).is_err() {
         eprintln!("Logging configuration creation FAILED");
-    } else {
-        config.unwrap();
+    }
+
+    // A configuration file that cannot be loaded can still be checked
+    if let Some(Commands::Config(ConfigArg { action: ConfigAction::Check })) = &args.command {
+        return check_config();
     }
 
     let settings = match load_settings() {
         Ok(config) => { config }
-        Err(_) => { bail!(LoadSettingsError) }
+        Err(err) => {
+            // The problems of the file, with their fix, are more useful than the first parse error alone
+            if let Ok(report) = check_settings() {
+                for problem in &report.problems {
+                    error!("{}", problem);
+                }
+            }
+            return Err(err)
+        }
     };
 
-    if args.command.is_some() {
-        match args.command.unwrap() {
+    if let Some(command) = args.command {
+        match command {
 
             Commands::Start => {
                 match service_manager_util::start(args.debug) {
@@ -168,6 +655,143 @@ fn main() -> anyhow::Result<()> {
                 info!(target: "general", "{:?}", settings)
             }
 
+            Commands::Config(_) => {}
+
+            Commands::Preview => {
+                let selections = match select_sources(&settings) {
+                    Ok(selections) => { selections }
+                    Err(err) => { error!("Error: {:?}", err); bail!(err) }
+                };
+
+                for selection in &selections {
+                    info!("{} ({}): {} files, {}", selection.name, selection.source.path, selection.files.len(), format_size(selection.total_size()));
+                    for file in &selection.files {
+                        let modified: DateTime<Local> = DateTime::from(file.modified);
+                        info!("    {:>12}  {}  {}", format_size(file.size), modified.format("%Y-%m-%d %H:%M"), file.relative_path.display());
+                    }
+                }
+                let files_count: usize = selections.iter().map(|selection| { selection.files.len() }).sum();
+                let total_size: u64 = selections.iter().map(|selection| { selection.total_size() }).sum();
+                info!("Total: {} files, {}", files_count, format_size(total_size));
+            }
+
+            Commands::Backup(arg) => {
+                let mut job = match BackupJob::from_config(&settings) {
+                    Ok(job) => { job }
+                    Err(err) => { error!("Error: {}", err); bail!(err) }
+                };
+                job.resume = arg.resume;
+
+                if arg.dry_run {
+                    let dry_run = match dry_run(&job) {
+                        Ok(dry_run) => { dry_run }
+                        Err(err) => { error!("Error: {:?}", err); bail!(err) }
+                    };
+
+                    for planned in &dry_run.copied {
+                        info!("    {:>12}  {}", format_size(planned.file.size), planned.snapshot_path.display());
+                    }
+                    for (planned, reason) in &dry_run.skipped {
+                        info!("    {:>12}  {} (skipped: {})", format_size(planned.file.size), planned.snapshot_path.display(), reason);
+                    }
+                    info!("Total: {} files, {}, estimated duration {}", dry_run.copied.len(), format_size(dry_run.total_size()), format_duration(dry_run.estimated_duration()));
+                    if !dry_run.skipped.is_empty() {
+                        info!("The snapshot would be partial, {} files skipped", dry_run.skipped.len());
+                    }
+                    for check in &dry_run.checks {
+                        info!("Destination {}", check.summary());
+                    }
+                    if dry_run.is_ready() {
+                        info!("Pre-flight checks passed");
+                    } else {
+                        error!("Pre-flight checks failed, the backup would not complete");
+                    }
+                } else {
+                    match arg.action.unwrap_or(BackupAction::Now) {
+                        BackupAction::Now => {
+                            let (events, handle) = backup::start(job);
+                            let progress_bar = ProgressBar::new(0);
+                            progress_bar.set_style(ProgressStyle::with_template("{bar:40} {percent:>3}% {msg}").unwrap_or(ProgressStyle::default_bar()));
+                            // Without a terminal the bar is hidden and its lines would be lost
+                            let print = |line: String| {
+                                if progress_bar.is_hidden() { info!("{}", line) } else { progress_bar.println(line) }
+                            };
+                            for event in events {
+                                match event {
+                                    ProgressEvent::Planned { files, bytes } => { print(format!("{} files selected, {}", files, format_size(bytes))) }
+                                    ProgressEvent::DestinationStarted { destination } => { print(format!("Backing up into {}", destination)) }
+                                    ProgressEvent::DestinationRefused { destination, reason } => { print(format!("Destination {} not used: {}", destination, reason)) }
+                                    ProgressEvent::Warning { destination, message } => { print(format!("Warning, destination {}: {}", destination, message)) }
+                                    ProgressEvent::Progress(progress) => {
+                                        progress_bar.set_length(progress.bytes_total);
+                                        progress_bar.set_position(progress.bytes_done);
+                                        progress_bar.set_message(progress.summary());
+                                    }
+                                    ProgressEvent::FileCopied { path, .. } => { debug!("Copied {}", path.display()) }
+                                    ProgressEvent::FileSkipped { path, reason, .. } => { print(format!("Skipped {} ({})", path.display(), reason)) }
+                                    ProgressEvent::DestinationFinished { report } => { print(report.summary()) }
+                                    ProgressEvent::HookFinished { outcome } => { print(outcome.summary()) }
+                                }
+                            }
+                            progress_bar.finish_and_clear();
+
+                            let result = match handle.join() {
+                                Ok(result) => { result }
+                                Err(_) => { error!("The backup thread panicked"); Err(anyhow::Error::new(FileTransferError)) }
+                            };
+                            if let Err(err) = record(&HistoryEntry::from_result(Trigger::Embctl, Confirmation::NotRequired, &result)) {
+                                error!("Backup not recorded into the history: {:?}", err);
+                            }
+                            let result = result.and_then(BackupReport::into_result);
+                            let app_config = app_config();
+                            match result {
+                                Ok(report) => {
+                                    info!("Backup done in {}", format_duration(report.duration));
+                                    notify(app_config.backup_done_summary, app_config.backup_done_body + "\n" + &report.summary());
+                                }
+                                Err(err) => {
+                                    error!("Backup failed: {}", err);
+                                    notify(app_config.backup_error_summary, app_config.backup_error_body + "\n" + &err.to_string());
+                                    bail!(err)
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            Commands::Restore(arg) => {
+                let policy = match MetadataPolicy::from_config(&settings) {
+                    Ok(policy) => { policy }
+                    Err(err) => { error!("Error: {}", err); bail!(err) }
+                };
+                let destination = match arg.from {
+                    // A configured destination, remote ones are given by name, or any local directory
+                    Some(from) => {
+                        match settings.backup_destinations.iter().find(|destination| { destination.name() == from }) {
+                            Some(destination) => { Some(open(destination)) }
+                            None => { Some(open(&BackupDestination::Path(from))) }
+                        }
+                    }
+                    None => { find_snapshot(&settings.backup_destinations.iter().map(open).collect::<Vec<_>>()) }
+                };
+                let destination = match destination {
+                    Some(destination) => { destination }
+                    None => { error!("No complete snapshot found in the backup destinations"); bail!(NoDestinationAvailableError) }
+                };
+
+                info!("Restoring the snapshot of {} into {}", destination.name(), arg.to.display());
+                let report = match restore(destination.as_ref(), &arg.to, &policy) {
+                    Ok(report) => { report }
+                    Err(err) => { error!("Error: {}", err); bail!(err) }
+                };
+                for (path, reason) in &report.failed {
+                    error!("    {} not restored: {}", path.display(), reason);
+                }
+                info!("Restore done: {}", report.summary());
+                report.into_result()?;
+            }
+
             Commands::Status => {
                 debug!(target: "general", "Status check...");
                 match load_settings() {
@@ -180,22 +804,63 @@ fn main() -> anyhow::Result<()> {
                     }
                     Err(err) => { bail!(err) }
                 }
+
+                match read_published() {
+                    Ok(Some(published)) => {
+                        let updated_at: DateTime<Local> = DateTime::from(published.updated_at);
+                        info!(target: "general", "Last backup: {} (updated {})", published.state, updated_at.format("%Y-%m-%d %H:%M:%S"));
+                        if let Some(progress) = &published.progress {
+                            info!(target: "general", "    {}", progress.summary());
+                        }
+                        if let Some(summary) = &published.summary {
+                            info!(target: "general", "    {}", summary.replace('\n', "\n    "));
+                        }
+                    }
+                    Ok(None) => { debug!(target: "general", "No backup progress published") }
+                    Err(err) => { error!(target: "general", "Error reading the backup progress: {:?}", err) }
+                }
             }
 
-            Commands::SetMillisUpdateTime(arg) => {
-                match set_millis_update_time(arg.time) {
-                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
-                    Err(err) => { error!("Error: {:?}", err) }
+            Commands::History(arg) => {
+                let entries = match read_history() {
+                    Ok(entries) => { entries }
+                    Err(err) => { error!("Error reading the backup history: {:?}", err); bail!(err) }
+                };
+                let trigger = arg.trigger.map(|trigger| {
+                    match trigger {
+                        TriggerEnum::Gesture => { Trigger::Gesture }
+                        TriggerEnum::Gui => { Trigger::Gui }
+                        TriggerEnum::Embctl => { Trigger::Embctl }
+                    }
+                });
+                let selected: Vec<&HistoryEntry> = entries.iter().rev()
+                    .filter(|entry| { trigger.map(|trigger| { entry.trigger == trigger }).unwrap_or(true) })
+                    .filter(|entry| { !arg.failed || entry.outcome == Outcome::Failed })
+                    .take(if arg.limit == 0 { usize::MAX } else { arg.limit })
+                    .collect();
+                if selected.is_empty() {
+                    info!("No backup in the history");
+                }
+                for entry in selected {
+                    if arg.json {
+                        println!("{}", serde_json::to_string(entry)?);
+                        continue;
+                    }
+                    info!("{}", entry.summary());
+                    for err in &entry.errors {
+                        info!("    error: {}", err);
+                    }
                 }
             }
 
-            Commands::SetMode(arg) => {
-                match set_mode(arg.mode.to_string()) {
+            Commands::SetMillisUpdateTime(arg) => {
+                match set_millis_update_time(arg.time) {
                     Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
                     Err(err) => { error!("Error: {:?}", err) }
                 }
             }
 
+
             Commands::SetExtensionOnly(arg) => {
                 let bool_val = match arg.extension_only.as_str() {
                     "true" => { true }
@@ -215,6 +880,119 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            Commands::SetInclude(arg) => {
+                match set_include(arg.rules) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetExclude(arg) => {
+                match set_exclude(arg.rules) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetExcludePresets(arg) => {
+                match set_exclude_presets(arg.presets) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetMaxFileSize(arg) => {
+                match set_max_file_size(arg.size) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetModifiedWithin(arg) => {
+                match set_modified_within(arg.window) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetMtimeRange(arg) => {
+                match set_mtime_range(arg.from, arg.to) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetPriority(arg) => {
+                match set_priority(arg.criteria) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetPriorityPaths(arg) => {
+                match set_priority_paths(arg.paths) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetBudget(arg) => {
+                match set_budget(arg.time, arg.bytes) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetCopyWorkers(arg) => {
+                match set_copy_workers(arg.workers) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetBandwidthLimit(arg) => {
+                match set_bandwidth_limit(arg.limit) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetLowPriority(arg) => {
+                match set_low_priority(arg.low_priority) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetSymlinkPolicy(arg) => {
+                match set_symlink_policy(arg.policy.to_string()) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetPreserve(arg) => {
+                match set_preserve(arg.permissions, arg.mtime, arg.xattrs, arg.acls) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::AddHook(arg) => {
+                let hook = BackupHook { command: arg.command, timeout_sec: arg.timeout_sec, abort_on_failure: arg.abort_on_failure };
+                match add_hook(matches!(arg.phase, HookPhaseEnum::Pre), hook) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::ClearHooks(arg) => {
+                match clear_hooks(matches!(arg.phase, HookPhaseEnum::Pre)) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
             Commands::SetDestination(arg) => {
                 match set_destination(arg.destination) {
                     Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
@@ -222,6 +1000,124 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            Commands::AddDestination(arg) => {
+                match add_destination(arg.destination) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::AddSftpDestination(arg) => {
+                let key_path = match arg.key.canonicalize() {
+                    Ok(key_path) => { key_path }
+                    Err(err) => { error!("Error: key {} not readable: {}", arg.key.display(), err); bail!(err) }
+                };
+                let known_hosts = match arg.known_hosts.map(|known_hosts| { known_hosts.canonicalize() }).transpose() {
+                    Ok(known_hosts) => { known_hosts }
+                    Err(err) => { error!("Error: known hosts file not readable: {}", err); bail!(err) }
+                };
+                let destination = RemoteDestination::Sftp(SftpDestination {
+                    host: arg.host,
+                    port: Some(arg.port),
+                    user: arg.user,
+                    key_path: key_path.to_string_lossy().to_string(),
+                    remote_dir: arg.remote_dir,
+                    known_hosts: known_hosts.map(|known_hosts| { known_hosts.to_string_lossy().to_string() }),
+                });
+                match add_remote_destination(destination) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::AddS3Destination(arg) => {
+                let credentials_file = match arg.credentials_file.map(|credentials_file| { credentials_file.canonicalize() }).transpose() {
+                    Ok(credentials_file) => { credentials_file }
+                    Err(err) => { error!("Error: credentials file not readable: {}", err); bail!(err) }
+                };
+                let destination = S3Destination {
+                    endpoint: arg.endpoint,
+                    bucket: arg.bucket,
+                    prefix: arg.prefix,
+                    region: arg.region,
+                    credentials_file: credentials_file.map(|credentials_file| { credentials_file.to_string_lossy().to_string() }),
+                    profile: arg.profile,
+                };
+                if arg.create_bucket {
+                    match S3::new(destination.clone()).create_bucket() {
+                        Ok(_) => { info!("Bucket {} ready", destination.bucket) }
+                        Err(err) => { error!("Error: {:?}", err); bail!(err) }
+                    }
+                }
+                match add_remote_destination(RemoteDestination::S3(destination)) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::AddWebdavDestination(arg) => {
+                let password_file = match arg.password_file.map(|password_file| { password_file.canonicalize() }).transpose() {
+                    Ok(password_file) => { password_file }
+                    Err(err) => { error!("Error: password file not readable: {}", err); bail!(err) }
+                };
+                let destination = RemoteDestination::WebDav(WebDavDestination {
+                    url: arg.url,
+                    user: arg.user,
+                    password_file: password_file.map(|password_file| { password_file.to_string_lossy().to_string() }),
+                });
+                match add_remote_destination(destination) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::AddRemovableDestination(arg) => {
+                let destination = RemoteDestination::Removable(RemovableDestination {
+                    label: arg.label,
+                    uuid: arg.uuid,
+                    marker: arg.marker,
+                    dir: arg.dir,
+                });
+                match add_remote_destination(destination) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::RemovableDrives => {
+                let volumes = removable_volumes();
+                if volumes.is_empty() {
+                    info!(target: "general", "No removable drive mounted");
+                }
+                for volume in volumes {
+                    info!(target: "general", "{}: label {}, uuid {}{}", volume.device,
+                        volume.label.as_deref().unwrap_or("-"), volume.uuid.as_deref().unwrap_or("-"),
+                        if volume.has_marker() { format!(", {} found", MARKER_FILE_NAME) } else { String::new() });
+                    info!(target: "general", "    mounted on {}", volume.mount_point.display());
+                }
+            }
+
+            Commands::RemoveDestination(arg) => {
+                match remove_destination(arg.destination) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetDestinationPolicy(arg) => {
+                match set_destination_policy(arg.policy.to_string()) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::SetSameDevicePolicy(arg) => {
+                match set_same_device_policy(arg.policy.to_string()) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
             Commands::SetSource(arg) => {
                 match set_source(arg.source) {
                     Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
@@ -229,6 +1125,34 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            Commands::AddSource(arg) => {
+                let mut source = match BackupSource::from_path(arg.source) {
+                    Ok(source) => { source }
+                    Err(err) => { error!("Error: {:?}", err); bail!(BackupSourceError) }
+                };
+                if arg.git {
+                    source.mode = String::from("Git");
+                }
+                source.name = arg.name;
+                source.include = arg.include;
+                source.exclude = arg.exclude;
+                if arg.extension_type.is_some() {
+                    source.extension_only = Some(true);
+                    source.extension_type = arg.extension_type;
+                }
+                match add_source(source) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
+            Commands::RemoveSource(arg) => {
+                match remove_source(arg.source) {
+                    Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
+                    Err(err) => { error!("Error: {:?}", err) }
+                }
+            }
+
             Commands::SetTolerance(arg) => {
                 match set_tolerance(arg.tolerance) {
                     Ok(_) => { info!("Configuration has been successfully updated, restart the mouse_tracker to make it effective") }
@@ -247,4 +1171,20 @@ fn main() -> anyhow::Result<()> {
     };
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Prints every problem of the configuration file, an error is returned if there is any
+fn check_config() -> anyhow::Result<()> {
+    let report = match check_settings() {
+        Ok(report) => { report }
+        Err(err) => { error!("Error checking the configuration: {:?}", err); bail!(err) }
+    };
+    if report.is_valid() {
+        info!("The configuration is valid");
+        return Ok(());
+    }
+    for problem in &report.problems {
+        error!("{}", problem);
+    }
+    bail!(ConfigValidationError(format!("{} problems found", report.problems.len())))
+}
@@ -1,39 +1,116 @@
+#[allow(clippy::module_inception)]
 pub mod gui_action {
 
     use std::path::{PathBuf};
+    use std::thread;
+    use std::time::Instant;
     use anyhow::bail;
     use log::{debug, error};
     use native_dialog::FileDialog;
-    use slint::{ComponentHandle, SharedString};
-    use crate::{AppWindow, HomePageAdapter, SettingsPageAdapter};
-    use errors::Error::{ApplySettingsError, LoadSettingsError, ModeSelectionError, NoPathChosenError};
-    use settings::settings::{apply_settings, BackupConfig, load_settings};
+    use slint::{ComponentHandle, Model, ModelRc, SharedString, VecModel, Weak};
+    use backup_engine::dry_run::dry_run;
+    use backup_engine::history::{read_history, record, Confirmation, HistoryEntry, Outcome, Trigger};
+    use backup_engine::backup;
+    use backup_engine::job::BackupJob;
+    use backup_engine::progress::ProgressEvent;
+    use backup_engine::report::BackupReport;
+    use config::app_config;
+    use backup_engine::{format_duration, format_size};
+    use crate::{AppWindow, BackupSourceEntry, HistoryPageAdapter, HistoryRow, HomePageAdapter, PreviewWindow, ProgressWindow, SettingsPageAdapter};
+    use errors::Error::{ApplySettingsError, ConfigValidationError, FileTransferError, LoadSettingsError, ModeSelectionError, NoPathChosenError};
+    use settings::settings::{apply_settings, BackupConfig, BackupDestination, BackupSource, load_settings};
+    use settings::validation::{ConfigProblem, ValidationReport};
 
     pub fn set_ui_settings_fields(app_window: &AppWindow, config: &BackupConfig) {
-        app_window.global::<SettingsPageAdapter>().set_backup_source(SharedString::from(config.backup_source.clone()));
-        app_window.global::<SettingsPageAdapter>().set_backup_destination(SharedString::from(config.backup_destination.clone()));
-        app_window.global::<SettingsPageAdapter>().set_tolerance(config.tolerance.clone() as i32);
-        app_window.global::<SettingsPageAdapter>().set_extension_only(config.extension_only.clone());
+        let sources: Vec<BackupSourceEntry> = config.backup_sources.iter()
+            .map(|source| { BackupSourceEntry { path: SharedString::from(source.path.clone()), mode: SharedString::from(source.mode.clone()) } })
+            .collect();
+        app_window.global::<SettingsPageAdapter>().set_backup_sources(ModelRc::new(VecModel::from(sources)));
+        let destinations: Vec<SharedString> = config.backup_destinations.iter().map(|destination| { SharedString::from(destination.name()) }).collect();
+        app_window.global::<SettingsPageAdapter>().set_backup_destinations(ModelRc::new(VecModel::from(destinations)));
+        app_window.global::<SettingsPageAdapter>().set_destination_policy(SharedString::from(config.destination_policy.clone()));
+        app_window.global::<SettingsPageAdapter>().set_tolerance(config.tolerance as i32);
+        app_window.global::<SettingsPageAdapter>().set_extension_only(config.extension_only);
         app_window.global::<SettingsPageAdapter>().set_extension_type(SharedString::from(config.extension_type.clone().join("|")));
-        app_window.global::<SettingsPageAdapter>().set_millis_update_frequency(config.millis_update_frequency.clone() as i32);
-        app_window.global::<SettingsPageAdapter>().set_tracking_window_sec(config.tracking_window_sec.clone() as i32);
-        app_window.global::<SettingsPageAdapter>().set_mode(SharedString::from(config.mode.clone()));
-        app_window.global::<HomePageAdapter>().set_active(config.active.clone());
+        app_window.global::<SettingsPageAdapter>().set_include(SharedString::from(config.include.join("|")));
+        app_window.global::<SettingsPageAdapter>().set_exclude(SharedString::from(config.exclude.join("|")));
+        app_window.global::<SettingsPageAdapter>().set_preset_rust_target(config.exclude_presets.contains(&String::from("rust-target")));
+        app_window.global::<SettingsPageAdapter>().set_preset_node_modules(config.exclude_presets.contains(&String::from("node-modules")));
+        app_window.global::<SettingsPageAdapter>().set_preset_git_objects(config.exclude_presets.contains(&String::from("git-objects")));
+        app_window.global::<SettingsPageAdapter>().set_millis_update_frequency(config.millis_update_frequency as i32);
+        app_window.global::<SettingsPageAdapter>().set_tracking_window_sec(config.tracking_window_sec as i32);
+        app_window.global::<SettingsPageAdapter>().set_mode(SharedString::from("Folder"));
+        app_window.global::<HomePageAdapter>().set_active(config.active);
+    }
+
+    /// Shows the problems of the report below the settings they are about, the ones without a field
+    /// of their own at the bottom of the page. An empty report clears them.
+    pub fn set_ui_problems(app_window: &AppWindow, report: &ValidationReport) {
+        let groups: [&[&str]; 6] = [
+            &["backup_sources"],
+            &["backup_destinations", "destination_policy"],
+            &["extension_only", "extension_type"],
+            &["include", "exclude", "exclude_presets"],
+            &["millis_update_frequency"],
+            &["tracking_window_sec"],
+        ];
+        let texts: Vec<SharedString> = groups.iter()
+            .map(|fields| { problems_text(report.problems.iter().filter(|problem| { fields.iter().any(|field| { problem.is_about(field) }) })) })
+            .collect();
+        let other = problems_text(report.problems.iter().filter(|problem| { !groups.iter().flat_map(|fields| { fields.iter() }).any(|field| { problem.is_about(field) }) }));
+
+        let adapter = app_window.global::<SettingsPageAdapter>();
+        adapter.set_sources_problems(texts[0].clone());
+        adapter.set_destinations_problems(texts[1].clone());
+        adapter.set_extension_problems(texts[2].clone());
+        adapter.set_rules_problems(texts[3].clone());
+        adapter.set_millis_update_frequency_problems(texts[4].clone());
+        adapter.set_tracking_window_sec_problems(texts[5].clone());
+        adapter.set_other_problems(other);
+    }
+
+    fn problems_text<'a>(problems: impl Iterator<Item = &'a ConfigProblem>) -> SharedString {
+        SharedString::from(problems.map(|problem| { problem.to_string() }).collect::<Vec<String>>().join("\n"))
+    }
+
+    /// Fills the history page, most recent backup first
+    pub fn set_ui_history(app_window: &AppWindow) {
+        let entries = match read_history() {
+            Ok(entries) => { entries }
+            Err(err) => { error!("Error reading the backup history: {:?}", err); vec![] }
+        };
+        let rows: Vec<HistoryRow> = entries.iter().rev()
+            .map(|entry| {
+                let details: Vec<String> = entry.snapshot_summary().into_iter().chain(entry.errors.iter().cloned()).collect();
+                HistoryRow {
+                    date: SharedString::from(entry.date()),
+                    trigger: SharedString::from(format!("{} ({})", entry.trigger, entry.confirmation)),
+                    outcome: SharedString::from(entry.outcome.to_string()),
+                    details: SharedString::from(details.join("\n")),
+                    failed: entry.outcome == Outcome::Failed,
+                }
+            })
+            .collect();
+        app_window.global::<HistoryPageAdapter>().set_entries(ModelRc::new(VecModel::from(rows)));
     }
 
     pub fn define_ui_callbacks(ui: &AppWindow) {
 
-        let config_log = config::log_config(false);
-        if config_log.is_ok() {
-            log4rs::init_config(config_log.unwrap()).unwrap();
+        if let Ok(config_log) = config::log_config(false) {
+            log4rs::init_config(config_log).unwrap();
         }
 
         ui.global::<SettingsPageAdapter>().on_choose_backup_source({
             let ui_handle = ui.as_weak();
             move || {
                 let ui = ui_handle.unwrap();
-                match open_file_dialog_and_get_path( ui.global::<SettingsPageAdapter>().get_mode(), true ) {
-                    Ok(string_path) => { ui.global::<SettingsPageAdapter>().set_backup_source(string_path); }
+                let mode = ui.global::<SettingsPageAdapter>().get_mode();
+                match open_file_dialog_and_get_path( mode.clone(), true ) {
+                    Ok(string_path) => {
+                        let mut sources: Vec<BackupSourceEntry> = ui.global::<SettingsPageAdapter>().get_backup_sources().iter().collect();
+                        sources.push(BackupSourceEntry { path: string_path, mode });
+                        ui.global::<SettingsPageAdapter>().set_backup_sources(ModelRc::new(VecModel::from(sources)));
+                    }
                     Err(err) => {
                         window_spawn_util::pop_out_error_window( String::from("An error occurred selecting file/folder for source selection"), Some(err.to_string()) )
                     }
@@ -42,12 +119,28 @@ pub mod gui_action {
             }
         });
 
+        ui.global::<SettingsPageAdapter>().on_remove_backup_source({
+            let ui_handle = ui.as_weak();
+            move |index| {
+                let ui = ui_handle.unwrap();
+                let mut sources: Vec<BackupSourceEntry> = ui.global::<SettingsPageAdapter>().get_backup_sources().iter().collect();
+                if index >= 0 && (index as usize) < sources.len() {
+                    sources.remove(index as usize);
+                }
+                ui.global::<SettingsPageAdapter>().set_backup_sources(ModelRc::new(VecModel::from(sources)));
+            }
+        });
+
         ui.global::<SettingsPageAdapter>().on_choose_backup_destination({
             let ui_handle = ui.as_weak();
             move || {
                 let ui = ui_handle.unwrap();
                 match open_file_dialog_and_get_path( ui.global::<SettingsPageAdapter>().get_mode(), false ) {
-                    Ok(string_path) => { ui.global::<SettingsPageAdapter>().set_backup_destination(string_path); }
+                    Ok(string_path) => {
+                        let mut destinations: Vec<SharedString> = ui.global::<SettingsPageAdapter>().get_backup_destinations().iter().collect();
+                        destinations.push(string_path);
+                        ui.global::<SettingsPageAdapter>().set_backup_destinations(ModelRc::new(VecModel::from(destinations)));
+                    }
                     Err(err) => {
                         window_spawn_util::pop_out_error_window( String::from("An error occurred selecting file/folder for destination selection"), Some(err.to_string()) )
                     }
@@ -55,6 +148,18 @@ pub mod gui_action {
             }
         });
 
+        ui.global::<SettingsPageAdapter>().on_remove_backup_destination({
+            let ui_handle = ui.as_weak();
+            move |index| {
+                let ui = ui_handle.unwrap();
+                let mut destinations: Vec<SharedString> = ui.global::<SettingsPageAdapter>().get_backup_destinations().iter().collect();
+                if index >= 0 && (index as usize) < destinations.len() {
+                    destinations.remove(index as usize);
+                }
+                ui.global::<SettingsPageAdapter>().set_backup_destinations(ModelRc::new(VecModel::from(destinations)));
+            }
+        });
+
         ui.global::<SettingsPageAdapter>().on_apply_settings({
             let ui_handle = ui.as_weak();
             move || {
@@ -69,6 +174,15 @@ pub mod gui_action {
             }
         });
 
+        ui.global::<SettingsPageAdapter>().on_preview({
+            let ui_handle = ui.as_weak();
+            move || {
+                if let Err(err) = show_preview(&ui_handle.unwrap()) {
+                    window_spawn_util::pop_out_error_window( String::from("The backup preview could not be computed"), Some(err.to_string()) )
+                }
+            }
+        });
+
         ui.global::<SettingsPageAdapter>().on_edited_millis_update_frequency( {
             let ui_handle = ui.as_weak();
             move |val| {
@@ -97,6 +211,22 @@ pub mod gui_action {
             }
         });
 
+        ui.global::<HomePageAdapter>().on_backup_now({
+            let ui_handle = ui.as_weak();
+            move || {
+                if let Err(err) = start_backup(ui_handle.clone()) {
+                    record_history(HistoryEntry::from_result(Trigger::Gui, Confirmation::NotRequired, &Err(anyhow::anyhow!(err.to_string()))));
+                    set_ui_history(&ui_handle.unwrap());
+                    window_spawn_util::pop_out_error_window( String::from("The backup could not be started"), Some(err.to_string()) )
+                }
+            }
+        });
+
+        ui.global::<HistoryPageAdapter>().on_refresh({
+            let ui_handle = ui.as_weak();
+            move || { set_ui_history(&ui_handle.unwrap()) }
+        });
+
         ui.global::<HomePageAdapter>().on_toggle_tracker({
             let ui_handle = ui.as_weak();
 
@@ -121,10 +251,8 @@ pub mod gui_action {
     pub fn open_file_dialog_and_get_path(mode: SharedString, source_selection: bool) -> anyhow::Result<SharedString> {
         debug!("Open file dialog START");
         debug!("source_selection: {:?}", source_selection);
-        let result_path : Option<PathBuf>;
-
-        if source_selection {
-            result_path = match mode.to_string().to_lowercase().as_str() {
+        let result_path : Option<PathBuf> = if source_selection {
+            match mode.to_string().to_lowercase().as_str() {
                 "file" => {
                     debug!("File mode");
                     FileDialog::new().show_open_single_file()?
@@ -134,10 +262,10 @@ pub mod gui_action {
                     FileDialog::new().show_open_single_dir()?
                 },
                 _ => bail!(ModeSelectionError)
-            };
+            }
         } else {
-            result_path = FileDialog::new().show_open_single_dir()?;
-        }
+            FileDialog::new().show_open_single_dir()?
+        };
 
         match result_path {
             Some(path) => {
@@ -155,10 +283,12 @@ pub mod gui_action {
             Err(err) => { error!("Error loading settings: {:?}", err); bail!(LoadSettingsError)}
         };
 
-        let mut config = match BackupConfig::field_checks(get_config_from_ui(app_window)) {
-            Ok(config) => { config }
-            Err(err) => { bail!(err) }
-        };
+        let mut config = get_config_from_ui(app_window, &settings);
+        let report = config.validate();
+        set_ui_problems(app_window, &report);
+        if !report.is_valid() {
+            bail!(ConfigValidationError(report.to_string()))
+        }
 
         config.installation_dir = settings.installation_dir;
 
@@ -168,18 +298,174 @@ pub mod gui_action {
         }
     }
 
-    fn get_config_from_ui(app_window: &AppWindow) -> BackupConfig {
+    /// Runs a dry run of the backup with the configuration shown in the UI, even if not applied yet,
+    /// and opens a window with its result
+    fn show_preview(app_window: &AppWindow) -> anyhow::Result<()> {
+        let settings = match load_settings() {
+            Ok(settings) => { settings }
+            Err(err) => { error!("Error loading settings: {:?}", err); bail!(LoadSettingsError)}
+        };
+        let config = get_config_from_ui(app_window, &settings);
+        let report = config.validate();
+        set_ui_problems(app_window, &report);
+        if !report.is_valid() {
+            bail!(ConfigValidationError(report.to_string()))
+        }
+        let dry_run = dry_run(&BackupJob::from_config(&config)?)?;
+
+        let mut summary = format!("{} files, {}, estimated duration {}", dry_run.copied.len(), format_size(dry_run.total_size()), format_duration(dry_run.estimated_duration()));
+        if !dry_run.skipped.is_empty() {
+            summary.push_str(format!("\nPartial snapshot, {} files skipped", dry_run.skipped.len()).as_str());
+        }
+        let files: Vec<SharedString> = dry_run.copied.iter()
+            .map(|planned| { format!("{:>12}  {}", format_size(planned.file.size), planned.snapshot_path.display()) })
+            .chain(dry_run.skipped.iter().map(|(planned, reason)| { format!("{:>12}  {} (skipped: {})", format_size(planned.file.size), planned.snapshot_path.display(), reason) }))
+            .map(SharedString::from)
+            .collect();
+        let checks: Vec<SharedString> = dry_run.checks.iter().map(|check| { SharedString::from(check.summary()) }).collect();
+
+        let preview_window = PreviewWindow::new()?;
+        preview_window.set_summary(SharedString::from(summary));
+        preview_window.set_files(ModelRc::new(VecModel::from(files)));
+        preview_window.set_checks(ModelRc::new(VecModel::from(checks)));
+        preview_window.set_ready(dry_run.is_ready());
+        preview_window.on_close({
+            let preview_handle = preview_window.as_weak();
+            move || {
+                if let Some(preview_window) = preview_handle.upgrade() {
+                    let _ = preview_window.hide();
+                }
+            }
+        });
+        preview_window.show()?;
+        Ok(())
+    }
+
+    /// Runs the saved configuration backup on a background thread, showing its progress in a new window.
+    /// The backup is recorded into the history, whose page is refreshed once it ends.
+    fn start_backup(ui_handle: Weak<AppWindow>) -> anyhow::Result<()> {
+        let config = match load_settings() {
+            Ok(config) => { config }
+            Err(err) => { error!("Error loading settings: {:?}", err); bail!(LoadSettingsError)}
+        };
+        let mut job = BackupJob::from_config(&config)?;
+        job.resume = true;
+
+        let progress_window = ProgressWindow::new()?;
+        progress_window.on_close({
+            let progress_handle = progress_window.as_weak();
+            move || {
+                if let Some(progress_window) = progress_handle.upgrade() {
+                    let _ = progress_window.hide();
+                }
+            }
+        });
+        progress_window.show()?;
+
+        let progress_handle = progress_window.as_weak();
+        let refresh_interval = app_config().progress_publish_interval_msec;
+        let (events, handle) = backup::start(job);
+        thread::spawn(move || {
+            let mut last_refresh: Option<Instant> = None;
+            for event in events {
+                match event {
+                    ProgressEvent::DestinationStarted { destination } => {
+                        let _ = progress_handle.upgrade_in_event_loop(move |progress_window| {
+                            progress_window.set_destination(SharedString::from(destination));
+                        });
+                    }
+                    ProgressEvent::Progress(progress) => {
+                        let done = progress.files_done == progress.files_total;
+                        if done || last_refresh.map(|last_refresh| { last_refresh.elapsed().as_millis() >= refresh_interval }).unwrap_or(true) {
+                            last_refresh = Some(Instant::now());
+                            let _ = progress_handle.upgrade_in_event_loop(move |progress_window| {
+                                progress_window.set_progress(progress.fraction());
+                                progress_window.set_details(SharedString::from(progress.summary()));
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            let result = match handle.join() {
+                Ok(result) => { result }
+                Err(_) => { Err(anyhow::Error::new(FileTransferError)) }
+            };
+            record_history(HistoryEntry::from_result(Trigger::Gui, Confirmation::NotRequired, &result));
+            let _ = ui_handle.upgrade_in_event_loop(|ui| { set_ui_history(&ui) });
+            let result = result.and_then(BackupReport::into_result);
+            let (success, summary) = match &result {
+                Ok(report) => { (true, report.summary()) }
+                Err(err) => { error!("Backup failed: {:?}", err); (false, err.to_string()) }
+            };
+            let _ = progress_handle.upgrade_in_event_loop(move |progress_window| {
+                progress_window.set_finished(true);
+                progress_window.set_success(success);
+                progress_window.set_summary(SharedString::from(summary));
+            });
+            result
+        });
+        Ok(())
+    }
+
+    /// Builds the configuration from the UI fields, `settings` is the saved configuration used to
+    /// keep the options that can not be edited from the UI (e.g. per-source name and filters).
+    fn get_config_from_ui(app_window: &AppWindow, settings: &BackupConfig) -> BackupConfig {
+        let backup_sources = app_window.global::<SettingsPageAdapter>().get_backup_sources().iter()
+            .map(|entry| {
+                match settings.backup_sources.iter().find(|source| { source.path == entry.path.as_str() && source.mode == entry.mode.as_str() }) {
+                    Some(source) => { source.clone() }
+                    None => {
+                        BackupSource {
+                            path: entry.path.to_string(),
+                            mode: entry.mode.to_string(),
+                            ..BackupSource::default()
+                        }
+                    }
+                }
+            })
+            .collect();
+        // Remote destinations are listed by name, their settings are kept from the configuration
+        let backup_destinations = app_window.global::<SettingsPageAdapter>().get_backup_destinations().iter()
+            .map(|entry| {
+                match settings.backup_destinations.iter().find(|destination| { destination.name() == entry.as_str() }) {
+                    Some(destination) => { destination.clone() }
+                    None => { BackupDestination::Path(entry.to_string()) }
+                }
+            })
+            .collect();
+
         BackupConfig {
-            backup_source: app_window.global::<SettingsPageAdapter>().get_backup_source().parse().unwrap(),
-            backup_destination: app_window.global::<SettingsPageAdapter>().get_backup_destination().parse().unwrap(),
+            backup_sources,
+            backup_destinations,
+            destination_policy: app_window.global::<SettingsPageAdapter>().get_destination_policy().to_string(),
             millis_update_frequency: app_window.global::<SettingsPageAdapter>().get_millis_update_frequency() as usize,
             tracking_window_sec: app_window.global::<SettingsPageAdapter>().get_tracking_window_sec() as usize,
             tolerance: app_window.global::<SettingsPageAdapter>().get_tolerance() as u32,
             extension_only: app_window.global::<SettingsPageAdapter>().get_extension_only(),
-            extension_type: app_window.global::<SettingsPageAdapter>().get_extension_type().to_string().split("|").map(str::to_string).collect(),
-            mode: app_window.global::<SettingsPageAdapter>().get_mode().parse().unwrap(),
+            extension_type: app_window.global::<SettingsPageAdapter>().get_extension_type().split("|").map(str::to_string).collect(),
+            include: split_rules(app_window.global::<SettingsPageAdapter>().get_include()),
+            exclude: split_rules(app_window.global::<SettingsPageAdapter>().get_exclude()),
+            exclude_presets: [
+                ("rust-target", app_window.global::<SettingsPageAdapter>().get_preset_rust_target()),
+                ("node-modules", app_window.global::<SettingsPageAdapter>().get_preset_node_modules()),
+                ("git-objects", app_window.global::<SettingsPageAdapter>().get_preset_git_objects()),
+            ].iter().filter(|(_, enabled)| { *enabled }).map(|(preset, _)| { preset.to_string() }).collect(),
             active: app_window.global::<HomePageAdapter>().get_active(),
-            installation_dir: None
+            installation_dir: None,
+            ..settings.clone()
         }
     }
-}
\ No newline at end of file
+
+    fn record_history(entry: HistoryEntry) {
+        if let Err(err) = record(&entry) {
+            error!("Backup not recorded into the history: {:?}", err);
+        }
+    }
+
+    /// Splits a '|' separated list of rules, ignoring empty entries
+    fn split_rules(rules: SharedString) -> Vec<String> {
+        rules.split('|').map(str::trim).filter(|rule| { !rule.is_empty() }).map(str::to_string).collect()
+    }
+}
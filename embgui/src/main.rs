@@ -5,9 +5,9 @@ use log::{debug, error};
 use slint::{LogicalPosition, WindowPosition};
 use config::app_config;
 use errors::Error::LoadSettingsError;
-use settings::settings::load_settings;
-use window_spawn_util::get_screen_size;
-use crate::gui_action::gui_action::{define_ui_callbacks, set_ui_settings_fields};
+use settings::settings::{check_settings, load_settings};
+use window_spawn_util::{get_screen_size, pop_out_error_window};
+use crate::gui_action::gui_action::{define_ui_callbacks, set_ui_history, set_ui_problems, set_ui_settings_fields};
 slint::include_modules!();
 mod gui_action;
 
@@ -17,10 +17,18 @@ fn main() -> anyhow::Result<()> {
 
     let config = match load_settings() {
         Ok(config) => { config }
-        Err(_) => { bail!(LoadSettingsError) }
+        Err(err) => {
+            error!("Error loading settings: {:?}", err);
+            // Shows what is wrong in the configuration file before quitting
+            let details = check_settings().map(|report| { report.to_string() }).ok().filter(|details| { !details.is_empty() });
+            pop_out_error_window(String::from("The configuration file cannot be loaded"), details);
+            bail!(LoadSettingsError)
+        }
     };
 
     set_ui_settings_fields(&ui, &config);
+    set_ui_problems(&ui, &config.validate());
+    set_ui_history(&ui);
     define_ui_callbacks(&ui);
 
     let (max_x, max_y) = match get_screen_size() {
@@ -23,6 +23,18 @@ pub enum Error {
     #[error("Failed to retrieve application configuration path")]
     ParentPathError,
 
+    #[error("Invalid configuration file: {0}")]
+    ConfigParseError(String),
+
+    #[error("The configuration file has version {found}, this version of the tool supports up to {supported}")]
+    ConfigVersionError { found: u64, supported: u64 },
+
+    #[error("Configuration upgrade failed: {0}")]
+    ConfigMigrationError(String),
+
+    #[error("Invalid configuration:\n{0}")]
+    ConfigValidationError(String),
+
     /********************************************
     *                 Slint Errors              *
     *********************************************/
@@ -50,7 +62,7 @@ pub enum Error {
     #[error("Backup mode set to 'file', but a folder was provided")]
     FolderProvidedFileRequiredError,
 
-    #[error("Backup mode set to 'folder', but a file was provided")]
+    #[error("Backup mode set to 'folder' or 'git', but a file was provided")]
     FileProvidedFolderRequiredError,
 
     #[error("Tolerance value must be non-negative")]
@@ -62,21 +74,90 @@ pub enum Error {
     #[error("File extension list must be provided in the format: 'txt|pdf|png ...'")]
     ExtensionTypeFormatError,
 
+    #[error("Include rules must be valid glob patterns, e.g. '*.rs' or 'docs/**'")]
+    GlobRuleFormatError,
+
+    #[error("Unknown exclude preset. Must be 'rust-target', 'node-modules' or 'git-objects'")]
+    ExcludePresetNotRecognizedError,
+
+    #[error("File size must be provided in the format: '500kb', '100mb', '1gb' ...")]
+    FileSizeFormatError,
+
+    #[error("Time window must be provided in the format: '30min', '12h', '7days' ...")]
+    DurationFormatError,
+
+    #[error("Date must be provided in the format 'YYYY-MM-DD' or RFC 3339 ('2024-07-01T18:00:00+02:00')")]
+    DateFormatError,
+
+    #[error("Invalid priority criterion. Must be 'paths', 'recent' or 'smallest'")]
+    PriorityNotRecognizedError,
+
     #[error("Failed to create mouse tracker")]
     MouseTrackerCreationError,
 
     #[error("'extension_only' attribute must be either 'true' or 'false'")]
     ExtensionOnlyValueError,
 
-    #[error("Invalid backup mode. Must be 'file' or 'folder'")]
+    #[error("Invalid backup mode. Must be 'file', 'folder' or 'git'")]
     BackupModeNotRecognizedError,
 
     #[error("Failed to retrieve backup source")]
     BackupSourceError,
 
+    #[error("Git command failed: {0}")]
+    GitCommandError(String),
+
+    #[error("Failed to retrieve backup destination")]
+    BackupDestinationError,
+
+    #[error("Invalid destination policy. Must be 'first-available' or 'all'")]
+    DestinationPolicyNotRecognizedError,
+
+    #[error("None of the backup destinations is reachable and writable")]
+    NoDestinationAvailableError,
+
     #[error("File transfer failed")]
     FileTransferError,
 
+    #[error("Invalid same device policy. Must be 'warn' or 'refuse'")]
+    SameDevicePolicyNotRecognizedError,
+
+    #[error("Copy workers cannot be zero")]
+    ZeroCopyWorkersError,
+
+    #[error("Invalid symlink policy. Must be 'follow', 'preserve' or 'skip'")]
+    SymlinkPolicyNotRecognizedError,
+
+    #[error("Invalid remote destination: {0}")]
+    RemoteDestinationConfigError(String),
+
+    #[error("Invalid hook: {0}")]
+    HookConfigError(String),
+
+    /********************************************
+    *             Pre-flight Errors             *
+    *********************************************/
+    #[error("Backup destination '{0}' does not exist or is not mounted")]
+    DestinationNotFoundError(String),
+
+    #[error("Backup destination '{0}' is not writable")]
+    DestinationNotWritableError(String),
+
+    #[error("Not enough free space on '{destination}': {required} required, {available} available")]
+    NotEnoughSpaceError { destination: String, required: String, available: String },
+
+    #[error("Backup destination '{destination}' is on the same device as the source '{source_path}'")]
+    SameDeviceError { destination: String, source_path: String },
+
+    #[error("Backup destination '{destination}' is not reachable: {reason}")]
+    DestinationUnreachableError { destination: String, reason: String },
+
+    #[error("Host key of '{0}' is unknown or does not match the known hosts file")]
+    HostKeyVerificationError(String),
+
+    #[error("Pre-backup hook '{hook}' failed: {reason}")]
+    HookFailedError { hook: String, reason: String },
+
     /********************************************
     *            Service Daemon Errors          *
     *********************************************/
@@ -4,13 +4,13 @@ use slint::{ComponentHandle, SharedString};
 use std::{fs, process};
 use std::fs::{File};
 use std::io::Write;
-#[cfg(all(target_family="unix"))] use std::os::unix::fs::PermissionsExt;
-#[cfg(all(target_family="unix"))] use nix::unistd::Uid;
-use std::path::{PathBuf};
+#[cfg(target_family="unix")] use std::os::unix::fs::PermissionsExt;
+#[cfg(target_family="unix")] use nix::unistd::Uid;
+use std::path::{Path, PathBuf};
 use anyhow::bail;
-#[cfg(all(target_os="macos"))] use include_directory::{include_directory};
+#[cfg(target_os="macos")] use include_directory::{include_directory};
 use settings::settings::{set_installation_dir};
-#[cfg(all(target_family="unix"))] use settings::settings::{get_config_dir};
+#[cfg(target_family="unix")] use settings::settings::{get_config_dir};
 use window_spawn_util::pop_out_error_window;
 use crate::{AppWindow, InstallerUiAdapter};
 
@@ -164,7 +164,7 @@ fn get_daemon_binary() -> &'static [u8] {
     include_bytes!("../../target/x86_64-apple-darwin/release/mouse_tracker")
 }
 
-fn create_executable( executable_name: &str, executable: &[u8], installation_dir: &PathBuf ) -> anyhow::Result<()> {
+fn create_executable( executable_name: &str, executable: &[u8], installation_dir: &Path ) -> anyhow::Result<()> {
     let exe_path = installation_dir.join(executable_name);
     let mut file = File::create(&exe_path)?;
     file.write_all(executable)?;
@@ -184,9 +184,9 @@ fn create_executable( executable_name: &str, executable: &[u8], installation_dir
 
 #[cfg(target_family = "unix")]
 fn set_permission(path: &PathBuf) -> anyhow::Result<()> {
-    let mut permission = fs::metadata(&path)?.permissions();
+    let mut permission = fs::metadata(path)?.permissions();
     permission.set_mode(0o777);
-    match fs::set_permissions(&path, permission) {
+    match fs::set_permissions(path, permission) {
         Ok(_) => { println!("{:?} successfully installed", &path); Ok(())}
         Err(err) => { eprintln!("{:?} installation FAILED.\n{:?}", &path, err); bail!(err) }
     }
@@ -260,7 +260,7 @@ fn install(installation_dir: PathBuf, is_cli_to_install: bool, is_gui_to_install
        {
             let gui = get_gui_binary();
             let exe_name = if cfg!(target_family = "windows") { "embgui.exe" } else { "embgui "};
-            let _ = create_executable(exe_name, gui, &installation_dir)
+            create_executable(exe_name, gui, &installation_dir)
                 .unwrap_or_else(|err| pop_out_error_window(String::from("Error creating Emergency Backup GUI executable. Did you run the Emergency Backup Installer with administrator privileges?"), Some(err.to_string())));
         }
     }
@@ -291,8 +291,8 @@ pub fn open_file_dialog_and_get_path() -> anyhow::Result<SharedString> {
 pub fn define_callbacks_and_props(ui: &AppWindow) -> anyhow::Result<()>{
     let homedir_path = homedir::my_home()?;
 
-    if homedir_path.is_some() {
-        ui.global::<InstallerUiAdapter>().set_installation_dir(SharedString::from(homedir_path.unwrap().join("EmergencyBackup").to_str().unwrap()));
+    if let Some(homedir_path) = homedir_path {
+        ui.global::<InstallerUiAdapter>().set_installation_dir(SharedString::from(homedir_path.join("EmergencyBackup").to_str().unwrap()));
     }
 
     ui.global::<InstallerUiAdapter>().on_close({
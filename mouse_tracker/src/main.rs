@@ -21,7 +21,7 @@ fn main() -> anyhow::Result<()>{
     }
 
     //Loading log configuration from 'config' lib crate
-    let config_log = config::log_config(if args.len() == 2 { true } else { false });
+    let config_log = config::log_config(args.len() == 2);
     log4rs::init_config(config_log?).unwrap();
     info!(target: "general", "Mouse tracker service START");
 
@@ -1,29 +1,32 @@
 use std::cmp::PartialEq;
-use std::path::{PathBuf};
-use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::{sleep};
-use std::time::{Duration, SystemTime};
-use std::{fs, process,};
-use std::fs::File;
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
+use std::{process};
 use anyhow::bail;
-use cpu_time::ProcessTime;
-use dircpy::{CopyBuilder};
 use log::{debug, error, info, trace};
 
 use perf_monitor::cpu::ProcessStat;
 use ringbuffer::{AllocRingBuffer, RingBuffer};
 use config::app_config;
-use errors::Error::{BackupModeNotRecognizedError, BackupPathNotConfigured, BackupSourceError, CpuConsumptionLoggingError, FileTransferError, MillisUpdateFrequencyError, ZeroTrackingWindowSecError};
+use errors::Error::{BackupPathNotConfigured, CpuConsumptionLoggingError, FileTransferError, MillisUpdateFrequencyError, ZeroTrackingWindowSecError};
 use service_manager_util::notify;
-use settings::settings::BackupConfig;
+use settings::settings::{BackupConfig, BackupDestination, RemoteDestination};
 use window_spawn_util::pop_out_error_window;
 use crate::data_type::screen_size::{ScreenSize};
 use crate::data_type::mouse_position::{MousePosition};
+use backup_engine::{backup, destination, staging};
+use backup_engine::history::{record, Confirmation, HistoryEntry, Trigger};
+use backup_engine::job::BackupJob;
+use backup_engine::progress::{publish, Progress, ProgressEvent, PublishedProgress};
+use backup_engine::report::BackupReport;
 use mouse_position::mouse_position::{Mouse};
 
 #[cfg(target_family = "unix")] use std::{thread};
 
+/// A first mouse command is waiting for its confirmation, cleared once confirmed, timed out or cancelled
+static CONFIRMATION_PENDING: AtomicBool = AtomicBool::new(false);
+
 #[derive(Clone)]
 pub struct MouseTracker {
     pub config: BackupConfig,
@@ -64,12 +67,10 @@ impl MouseTracker {
             Err(screen_size_error) => { bail!(screen_size_error) }
         };
 
-        let source_path = PathBuf::from_str(&*config.backup_source);
-        let destination_path = PathBuf::from_str(&*config.backup_destination);
-
-        if source_path.is_err() || destination_path.is_err() ||
-            source_path.clone().unwrap().as_os_str().is_empty() || destination_path.clone().unwrap().as_os_str().is_empty() {
-            error!(target: "general", "Source path: {:?}, Dest path; {:?}", source_path, destination_path);
+        if config.backup_sources.is_empty() || config.backup_destinations.is_empty() ||
+            config.backup_sources.iter().any(|source| { source.path.is_empty() }) ||
+            config.backup_destinations.iter().any(|destination| { destination.name().is_empty() }) {
+            error!(target: "general", "Source paths: {:?}, Dest paths; {:?}", config.backup_sources, config.backup_destinations);
             bail!(BackupPathNotConfigured)
         }
 
@@ -79,7 +80,8 @@ impl MouseTracker {
         })
     }
 
-    pub fn start(self) -> () {
+    pub fn start(self) {
+        Self::recover_interrupted_backups(&self.config);
         match MouseTracker::tracking_loop(self.config.clone(), self.screen_size.clone(), false, None) {
             Ok(_) => { debug!(target: "general", "Tracking loop successfully started") }
             Err(err) => {
@@ -89,6 +91,25 @@ impl MouseTracker {
         }
     }
 
+    /// Finalizes or cleans up the snapshots left behind by a backup interrupted before the daemon stopped,
+    /// incomplete ones are kept for the next trigger to resume them. Only local directories and removable
+    /// drives are recovered here, so that a slow or unreachable server does not delay the start: remote
+    /// destinations are recovered by the next backup before it writes to them.
+    fn recover_interrupted_backups(config: &BackupConfig) {
+        let local_destinations = config.backup_destinations.iter()
+            .filter(|destination| { matches!(destination, BackupDestination::Path(_) | BackupDestination::Remote(RemoteDestination::Removable(_))) });
+        for destination in local_destinations.map(destination::open) {
+            match staging::recover(destination.as_ref(), true) {
+                Ok(actions) => {
+                    for action in actions {
+                        info!(target: "general", "Interrupted backup on {}: {}", destination.name(), action);
+                    }
+                }
+                Err(err) => { error!(target: "general", "Error recovering the interrupted backup on {}: {:?}", destination.name(), err) }
+            }
+        }
+    }
+
     fn tracking_loop(config: BackupConfig, screen_size: ScreenSize, is_second_command: bool, first_command_time: Option<SystemTime>) -> anyhow::Result<()> {
         debug!(target: "general", "tracking_loop START");
         let buffer_size = 1000 / config.millis_update_frequency * config.tracking_window_sec;
@@ -112,17 +133,16 @@ impl MouseTracker {
                     let app_config = app_config();
 
                     if is_second_command {
+                        CONFIRMATION_PENDING.store(false, Ordering::SeqCst);
                         notify(app_config.second_command_notification_summary, app_config.second_command_notification_body);
-                        info!(target: "general", "Backup starting..");
-
-                        match Self::backup(config) {
-                            Ok(_) => {
+                        match Self::backup(&config) {
+                            Ok(report) => {
                                 info!(target: "general", "Backup done.");
-                                notify(app_config.backup_done_summary, app_config.backup_done_body);
+                                notify(app_config.backup_done_summary, app_config.backup_done_body + "\n" + &report.summary());
                             }
                             Err(err) => {
                                 error!(target: "general", "An error occurred during the Backup: {}", err);
-                                notify(app_config.backup_error_summary, app_config.backup_error_body);
+                                notify(app_config.backup_error_summary, app_config.backup_error_body + "\n" + &err.to_string());
                             }
                         }
 
@@ -130,6 +150,7 @@ impl MouseTracker {
                     } else {
                         info!(target: "general", "Mouse command detected: First time scenario, listening for the second one..");
                         notify(app_config.first_command_notification_summary, app_config.first_command_notification_body);
+                        CONFIRMATION_PENDING.store(true, Ordering::SeqCst);
                         let config_bind = config.clone();
                         let screen_size_bind = screen_size.clone();
                         #[cfg(target_family = "unix")]
@@ -144,8 +165,13 @@ impl MouseTracker {
                                 }
                             });
                             window_spawn_util::pop_out_deny_window(
-                                String::from("Command detected, press cancel to stop the second command listening. \
-                                The listening for the second command will be stopped anyway after".to_owned() + config.tracking_window_sec.to_string().as_str()));
+                                "Command detected, press cancel to stop the second command listening. \
+                                The listening for the second command will be stopped anyway after".to_owned() + config.tracking_window_sec.to_string().as_str(),
+                                || {
+                                    if CONFIRMATION_PENDING.swap(false, Ordering::SeqCst) {
+                                        Self::record_history(HistoryEntry::not_run(Trigger::Gesture, Confirmation::Cancelled));
+                                    }
+                                });
                         }
 
 
@@ -175,6 +201,9 @@ impl MouseTracker {
                     Ok(time_since) => {
                         if time_since.as_secs() > config.tracking_window_sec as u64 {
                             info!(target: "general", "Second command listening finish");
+                            if CONFIRMATION_PENDING.swap(false, Ordering::SeqCst) {
+                                Self::record_history(HistoryEntry::not_run(Trigger::Gesture, Confirmation::TimedOut));
+                            }
                             break Ok(());
                         }
                     }
@@ -201,20 +230,20 @@ impl MouseTracker {
         }
     }
 
-    fn is_touching_upper_left_corner(coordinates: &MousePosition, mouse_position_buffer: &Vec<MousePosition>) -> bool {
+    fn is_touching_upper_left_corner(coordinates: &MousePosition, mouse_position_buffer: &[MousePosition]) -> bool {
         coordinates.eq(&MousePosition::new((0, 0))) || mouse_position_buffer.contains(&MousePosition::new((0, 0)))
     }
 
-    fn is_touching_lower_left_corner(mouse_position_buffer: &Vec<MousePosition>, screen_size: &ScreenSize, tolerance: u32) -> bool {
+    fn is_touching_lower_left_corner(mouse_position_buffer: &[MousePosition], screen_size: &ScreenSize, tolerance: u32) -> bool {
         for y in screen_size.max_height - tolerance..screen_size.max_height {
             if mouse_position_buffer.contains(&MousePosition::new((0, y as i32))) {
                 return true;
             }
         }
-        return false;
+        false
     }
 
-    fn is_touching_lower_right_corner(mouse_position_buffer: &Vec<MousePosition>, screen_size: &ScreenSize, tolerance: u32) -> bool {
+    fn is_touching_lower_right_corner(mouse_position_buffer: &[MousePosition], screen_size: &ScreenSize, tolerance: u32) -> bool {
         for x in screen_size.max_width - tolerance..screen_size.max_width {
             for y in screen_size.max_height - tolerance..screen_size.max_height {
                 if mouse_position_buffer.contains(&MousePosition::new((x as i32, y as i32))) {
@@ -222,16 +251,16 @@ impl MouseTracker {
                 }
             }
         }
-        return false;
+        false
     }
 
-    fn is_touching_upper_right_corner(mouse_position_buffer: &Vec<MousePosition>, screen_size: &ScreenSize, tolerance: u32) -> bool {
+    fn is_touching_upper_right_corner(mouse_position_buffer: &[MousePosition], screen_size: &ScreenSize, tolerance: u32) -> bool {
         for x in screen_size.max_width - tolerance..screen_size.max_width {
             if mouse_position_buffer.contains(&MousePosition::new((x as i32, 0))) {
                 return true;
             }
         }
-        return false;
+        false
     }
 
     fn log_cpu_consumption(process_stat: &mut ProcessStat) {
@@ -244,95 +273,57 @@ impl MouseTracker {
         }
     }
 
-    fn backup(settings: BackupConfig) -> anyhow::Result<()> {
-        let start = ProcessTime::now();
-
-        let result = match settings.mode.to_lowercase().as_str() {
-            "file" => { Self::file_backup(&settings) }
-            "folder" => { Self::folder_backup(settings.clone()) }
-            _ => { bail!(BackupModeNotRecognizedError) }
+    /// Runs the configured backup, publishing its progress into the config directory (read by `embctl status`)
+    /// and recording it into the backup history
+    fn backup(config: &BackupConfig) -> anyhow::Result<BackupReport> {
+        let mut job = match BackupJob::from_config(config) {
+            Ok(job) => { job }
+            Err(err) => {
+                let result = Err(err);
+                Self::record_history(HistoryEntry::from_result(Trigger::Gesture, Confirmation::Confirmed, &result));
+                return result;
+            }
         };
-
-        return if result.is_ok() {
-            let cpu_time = start.elapsed();
-            match Self::log_backup_cpu_time(cpu_time, PathBuf::from(&settings.backup_destination), PathBuf::from(&settings.backup_source)) {
-                Ok(_) => { info!("Backup logs successfully written.")}
-                Err(err) => { pop_out_error_window(String::from("Error writing backup logs"), Some(err.to_string()))}
+        // A trigger after a power loss continues the interrupted snapshot
+        job.resume = true;
+        let publish_interval = app_config().progress_publish_interval_msec;
+        let (events, handle) = backup::start(job);
+        let mut last_progress: Option<Progress> = None;
+        let mut last_publish: Option<Instant> = None;
+
+        for event in events {
+            if let ProgressEvent::Progress(progress) = event {
+                if last_publish.map(|last_publish| { last_publish.elapsed().as_millis() >= publish_interval }).unwrap_or(true) {
+                    Self::publish_progress("running", Some(progress.clone()), None);
+                    last_publish = Some(Instant::now());
+                }
+                last_progress = Some(progress);
             }
-            result
-        } else {
-            Err(result.err().unwrap())
         }
-    }
-
-    fn log_backup_cpu_time(duration: Duration, target_path: PathBuf, source_path: PathBuf) -> anyhow::Result<()>{
-        let target_file = target_path.join("backup_log_info.log");
-        let mut file = match File::create(target_file) {
-            Ok(file) => { debug!("Backup log file opened"); file }
-            Err(err) => { error!("Error writing backup logs on target directory: {:?}", err); bail!(err) }
-        };
-        debug!(target: "general", "Backup path: {:?}", source_path);
 
-        let backup_size = match fs_extra::dir::get_size(source_path) {
-            Ok(size) => { (size as f64)/1024f64/1024f64 }
-            Err(err) => { error!("Error calculating backup size, returning 0. \nError: {}", err); 0f64 }
+        let result = match handle.join() {
+            Ok(result) => { result }
+            Err(_) => { error!(target: "general", "The backup thread panicked"); Err(anyhow::Error::new(FileTransferError)) }
         };
-        let mut backup_message = String::from("Backup CPU time: ");
-
-        backup_message.push_str(&*duration.as_millis().to_string());
-        backup_message.push_str(" ms");
-        backup_message.push_str("\n");
-        backup_message.push_str("Backup size: ");
-        backup_message.push_str(format!("{:.2}", backup_size).as_str());
-        backup_message.push_str(" Mb");
-
-        match file.write(backup_message.as_bytes()) {
-            Ok(_) => { debug!("Backup log saved."); Ok(()) }
-            Err(err) => { error!("An error occurred writing backup log: {:?}", err); bail!(err) }
+        Self::record_history(HistoryEntry::from_result(Trigger::Gesture, Confirmation::Confirmed, &result));
+        let result = result.and_then(BackupReport::into_result);
+        match &result {
+            Ok(report) => { Self::publish_progress("done", last_progress, Some(report.summary())) }
+            Err(err) => { Self::publish_progress("failed", last_progress, Some(err.to_string())) }
         }
+        result
     }
 
-    /// Function responsible to back up the entire folder (applying filters if needed, if extension_only is true
-    fn folder_backup(settings: BackupConfig) -> anyhow::Result<()> {
-        let destination_path = PathBuf::from(&settings.backup_destination).join("emergency-backup");
-        let mut builder = CopyBuilder::new(&settings.backup_source, &destination_path);
-        if settings.extension_only {
-            debug!(target: "general", "Target extensions: {:?}", settings.extension_type);
-            for ext in settings.extension_type {
-                let mut dotted_ext = ext.clone();
-                dotted_ext.insert(0, '.');
-                builder = builder.with_include_filter(&dotted_ext);
-            }
-        }
-
-        match builder
-            .run() {
-            Ok(_) => { Ok(()) }
-            Err(err) => { error!(target: "general", "Ext-only directory copy error: {}", err.to_string()); bail!(FileTransferError) }
+    fn record_history(entry: HistoryEntry) {
+        if let Err(err) = record(&entry) {
+            error!(target: "general", "Backup not recorded into the history: {:?}", err);
         }
-
     }
 
-    fn file_backup(settings: &BackupConfig) -> anyhow::Result<()> {
-
-        let binding = PathBuf::from(&settings.backup_source);
-
-        let source_file_name = match binding.file_name() {
-            None => { error!("Error to retrieve backup source file name"); bail!(BackupSourceError) }
-            Some(file_name) => { file_name }
-        };
-
-        let destination_path = PathBuf::from(&settings.backup_destination)
-            .join("emergency-backup");
-
-        match fs::create_dir_all(&destination_path) {
-            Ok(_) => {
-                match fs::copy(PathBuf::from(&settings.backup_source), destination_path.join(source_file_name)) {
-                    Ok(_) => { Ok(()) }
-                    Err(err) => { error!("{:?}", err); bail!(err) }
-                }
-            }
-            Err(err) => { error!("{:?}", err); bail!(err) }
+    fn publish_progress(state: &str, progress: Option<Progress>, summary: Option<String>) {
+        let published = PublishedProgress { state: state.to_string(), updated_at: SystemTime::now(), progress, summary };
+        if let Err(err) = publish(&published) {
+            debug!(target: "general", "Backup progress not published: {:?}", err);
         }
     }
-}
\ No newline at end of file
+}
@@ -1,6 +1,5 @@
 use std::ffi::OsString;
 use std::path::{PathBuf};
-use std::str::FromStr;
 use anyhow::bail;
 use log::{error, info};
 use notify_rust::{Notification};
@@ -41,10 +40,9 @@ pub fn setup() -> anyhow::Result<Box<dyn ServiceManager>> {
 pub fn check_and_get_settings() -> anyhow::Result<BackupConfig> {
     let settings = match load_settings() {
         Ok(settings) => {
-            let source_path = PathBuf::from_str(&*settings.backup_source);
-            let destination_path = PathBuf::from_str(&*settings.backup_destination);
-            if source_path.is_err() || destination_path.is_err() ||
-                source_path.unwrap().as_os_str().is_empty() || destination_path.unwrap().as_os_str().is_empty() {
+            if settings.backup_sources.is_empty() || settings.backup_destinations.is_empty() ||
+                settings.backup_sources.iter().any(|source| { source.path.is_empty() }) ||
+                settings.backup_destinations.iter().any(|destination| { destination.name().is_empty() }) {
                 bail!(BackupPathNotConfigured)
             }
             settings
@@ -52,10 +50,10 @@ pub fn check_and_get_settings() -> anyhow::Result<BackupConfig> {
         Err(err) => { bail!(err) }
     };
 
-    let _ = match &settings.installation_dir {
-        Some(_) => { },
-        None => { error!(target: "general", "Installation directory not found in .emergency_backup"); bail!(InstallationDirectoryNotFound) }
-    };
+    if settings.installation_dir.is_none() {
+        error!(target: "general", "Installation directory not found in .emergency_backup");
+        bail!(InstallationDirectoryNotFound)
+    }
 
     Ok(settings)
 }
@@ -199,8 +197,11 @@ pub fn stop_and_uninstall() -> anyhow::Result<()> {
 
 //From notify-rust docs, summary and body are supported on Unix and Windows env
 pub fn notify(summary: String, body: String) {
-    Notification::new()
-        .summary(&*summary)
-        .body(&*body)
-        .show().unwrap();
+    match Notification::new()
+        .summary(&summary)
+        .body(&body)
+        .show() {
+        Ok(_) => {}
+        Err(err) => { error!(target: "general", "Error showing the notification {:?}: {:?}", summary, err) }
+    }
 }
\ No newline at end of file
@@ -1,3 +1,5 @@
+pub mod migration;
+pub mod validation;
 
 pub mod settings {
     use std::{fs};
@@ -6,13 +8,342 @@ pub mod settings {
     use std::path::{Path, PathBuf};
     use anyhow::{bail};
     use homedir::{get_my_home};
-    use log::{debug, error};
+    use log::{debug, error, info};
+    use crate::migration::{migrate, CONFIG_VERSION};
+    use crate::validation::{check_config_file, choice_suggestion, ConfigProblem, ValidationReport};
     use serde::{Deserialize, Serialize};
-    use errors::Error::{ApplySettingsError, ExtensionTypeArrayEmptyError, ExtensionTypeFormatError, FileProvidedFolderRequiredError, FolderProvidedFileRequiredError, HomeDirectoryError, LoadSettingsError, MillisUpdateFrequencyError, ParentPathError, ZeroTrackingWindowSecError};
+    use errors::Error::{ApplySettingsError, BackupDestinationError, ConfigParseError, ConfigValidationError, BackupSourceError, DestinationPolicyNotRecognizedError, ExcludePresetNotRecognizedError, GlobRuleFormatError, FileSizeFormatError, DurationFormatError, DateFormatError, PriorityNotRecognizedError, SameDevicePolicyNotRecognizedError, SymlinkPolicyNotRecognizedError, RemoteDestinationConfigError, HookConfigError, BackupPathNotConfigured, ExtensionTypeArrayEmptyError, ExtensionTypeFormatError, FileProvidedFolderRequiredError, FolderProvidedFileRequiredError, HomeDirectoryError, LoadSettingsError, MillisUpdateFrequencyError, ParentPathError, ZeroCopyWorkersError, ZeroTrackingWindowSecError};
     use regex::Regex;
+    use globset::Glob;
+    use std::time::{Duration, SystemTime};
+    use chrono::{DateTime, Local, NaiveDate, TimeZone};
 
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+    pub struct BackupSource {
+        /// Absolute path of the file or directory to back up
+        pub path: String,
+
+        /// 'Folder' or 'File', if the entry is a single file or an entire directory. 'Git' saves the uncommitted
+        /// work of the git repositories found in the directory instead of its files.
+        pub mode: String,
+
+        /// Name of the entry inside the snapshot, if not set the file name of `path` is used.
+        pub name: Option<String>,
+
+        /// Per-source override of `BackupConfig::extension_only`
+        pub extension_only: Option<bool>,
+
+        /// Per-source override of `BackupConfig::extension_type`
+        pub extension_type: Option<Vec<String>>,
+
+        /// Include rules added to `BackupConfig::include` for this source only
+        pub include: Option<Vec<String>>,
+
+        /// Exclude rules added to `BackupConfig::exclude` for this source only
+        pub exclude: Option<Vec<String>>,
+    }
+
+    impl BackupSource {
+        /// Builds a source entry from an existing path, the mode is detected from the file system.
+        pub fn from_path(path: PathBuf) -> anyhow::Result<Self> {
+            let full_path = path.canonicalize()?;
+            Ok(BackupSource {
+                mode: if full_path.is_dir() { "Folder".to_string() } else { "File".to_string() },
+                path: full_path.to_string_lossy().to_string(),
+                ..BackupSource::default()
+            })
+        }
+
+        /// Name used when no explicit one has been configured
+        fn default_name(&self) -> String {
+            match PathBuf::from(&self.path).file_name() {
+                Some(file_name) => { file_name.to_string_lossy().to_string() }
+                None => { String::from("source") }
+            }
+        }
+
+        /// Extensions to filter this source by, `None` if every file has to be copied
+        pub fn extension_filter(&self, config: &BackupConfig) -> Option<Vec<String>> {
+            if self.extension_only.unwrap_or(config.extension_only) {
+                Some(self.extension_type.clone().unwrap_or(config.extension_type.clone()))
+            } else {
+                None
+            }
+        }
+
+        /// Include globs applied to this source: the global and per-source rules plus one `*.<ext>` rule
+        /// for each extension of the extension filter. An empty list means that every file is included.
+        pub fn include_rules(&self, config: &BackupConfig) -> Vec<String> {
+            let mut rules = config.include.clone();
+            rules.extend(self.include.clone().unwrap_or_default());
+            if let Some(extension_type) = self.extension_filter(config) {
+                rules.extend(extension_type.iter().map(|ext| { format!("*.{}", ext) }));
+            }
+            rules
+        }
+
+        /// Gitignore-style exclude rules applied to this source: the global and per-source rules
+        /// followed by the rules of every enabled preset.
+        pub fn exclude_rules(&self, config: &BackupConfig) -> Vec<String> {
+            let mut rules = config.exclude.clone();
+            rules.extend(self.exclude.clone().unwrap_or_default());
+            for preset in &config.exclude_presets {
+                if let Some((_, preset_rules)) = EXCLUDE_PRESETS.iter().find(|(name, _)| { name == preset }) {
+                    rules.extend(preset_rules.iter().map(|rule| { rule.to_string() }));
+                }
+            }
+            rules
+        }
+    }
+
+    /// A backup destination: a plain string is a local directory (a mounted drive or any folder),
+    /// a map with a `type` key is a remote destination or a removable drive chosen at backup time.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(untagged)]
+    pub enum BackupDestination {
+        Path(String),
+
+        Remote(RemoteDestination),
+    }
+
+    /// Destinations described by a map, tagged by their `type`: servers reached through the network
+    /// and removable drives
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(tag = "type", rename_all = "lowercase")]
+    pub enum RemoteDestination {
+        Sftp(SftpDestination),
+
+        S3(S3Destination),
+
+        WebDav(WebDavDestination),
+
+        Removable(RemovableDestination),
+    }
+
+    /// Directory on an SSH server, written through the OpenSSH `sftp` client
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct SftpDestination {
+        pub host: String,
+
+        /// SSH port, 22 if not set
+        pub port: Option<u16>,
+
+        pub user: String,
+
+        /// Private key used to log in, it must not be protected by a passphrase
+        pub key_path: String,
+
+        /// Directory the emergency-backup/ folder is created in, absolute or relative to the user home
+        pub remote_dir: String,
+
+        /// Known hosts file the server key is verified against, `~/.ssh/known_hosts` if not set.
+        /// Unknown or changed host keys are always refused.
+        pub known_hosts: Option<String>,
+    }
+
+    /// Bucket of an S3-compatible object storage (AWS S3, MinIO, ...)
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct S3Destination {
+        /// Base URL of the service, e.g. 'https://s3.eu-west-1.amazonaws.com' or 'http://minio.local:9000'
+        pub endpoint: String,
+
+        pub bucket: String,
+
+        /// Key prefix the emergency-backup/ objects are written under, the bucket root if not set
+        pub prefix: Option<String>,
+
+        /// Region the requests are signed for, 'us-east-1' if not set (the MinIO default)
+        pub region: Option<String>,
+
+        /// AWS shared credentials file holding the access keys. If not set the keys are read from the
+        /// AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN environment variables.
+        pub credentials_file: Option<String>,
+
+        /// Profile of the credentials file, 'default' if not set
+        pub profile: Option<String>,
+    }
+
+    /// Folder on a WebDAV server (Nextcloud, ownCloud, ...), with basic authentication
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct WebDavDestination {
+        /// URL of the folder the emergency-backup/ folder is created in,
+        /// e.g. 'https://cloud.example.com/remote.php/dav/files/alice/backups'. Files larger than 10 MB are
+        /// uploaded in resumable chunks only on Nextcloud and ownCloud (URLs under /remote.php/), other servers
+        /// receive every file in a single request that starts over when it is interrupted.
+        pub url: String,
+
+        pub user: String,
+
+        /// File holding the password, an app password for Nextcloud and ownCloud accounts.
+        /// If not set the password is read from the WEBDAV_PASSWORD environment variable.
+        pub password_file: Option<String>,
+    }
+
+    /// Removable drive (USB stick, external disk, SD card) picked among the mounted ones when the backup starts.
+    /// A drive must match every criterion set, without criteria the first writable removable drive is used.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+    pub struct RemovableDestination {
+        /// File system label of the drive
+        pub label: Option<String>,
+
+        /// File system UUID of the drive
+        pub uuid: Option<String>,
+
+        /// If true, the drive must hold a `.emergency-backup-target` file in its root
+        #[serde(default)]
+        pub marker: bool,
+
+        /// Directory of the drive the emergency-backup/ folder is created in, the root of the drive if not set
+        pub dir: Option<String>,
+    }
+
+    /// Command run before or after a backup
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    pub struct BackupHook {
+        /// Command line, run by `sh -c` (`cmd /C` on Windows)
+        pub command: String,
+
+        /// Seconds after which the command is killed, 60 if not set
+        #[serde(default)]
+        pub timeout_sec: Option<u64>,
+
+        /// If true a failure (exit status other than 0, timeout) of a pre-backup hook aborts the backup,
+        /// of a post-backup hook skips the following hooks. Otherwise the failure is only reported.
+        #[serde(default)]
+        pub abort_on_failure: bool,
+    }
+
+    impl BackupHook {
+        fn checks(&self) -> anyhow::Result<()> {
+            if self.command.trim().is_empty() {
+                bail!(HookConfigError(String::from("the command is empty")))
+            }
+            if self.timeout_sec == Some(0) {
+                bail!(HookConfigError(format!("the timeout of '{}' cannot be zero", self.command)))
+            }
+            Ok(())
+        }
+    }
+
+    impl BackupDestination {
+        /// Name of the destination in logs, reports and commands: the path of a local destination,
+        /// a URL for a remote one (e.g. 'sftp://user@host:22/srv/backups', 'davs://user@host/remote.php/dav/files/user'),
+        /// the criteria for a removable drive (e.g. 'removable:label=EMERGENCY,marker')
+        pub fn name(&self) -> String {
+            match self {
+                BackupDestination::Path(path) => { path.clone() }
+                BackupDestination::Remote(RemoteDestination::Sftp(sftp)) => {
+                    // Same convention of curl: a directory relative to the user home starts with '~'
+                    let remote_dir = if sftp.remote_dir.starts_with('/') { sftp.remote_dir.clone() } else { format!("/~/{}", sftp.remote_dir) };
+                    format!("sftp://{}@{}:{}{}", sftp.user, sftp.host, sftp.port.unwrap_or(22), remote_dir)
+                }
+                BackupDestination::Remote(RemoteDestination::S3(s3)) => {
+                    let host = s3.endpoint.split("://").last().unwrap_or_default().trim_end_matches('/');
+                    match s3.prefix.as_deref().map(|prefix| { prefix.trim_matches('/') }).filter(|prefix| { !prefix.is_empty() }) {
+                        Some(prefix) => { format!("s3://{}/{}/{}", host, s3.bucket, prefix) }
+                        None => { format!("s3://{}/{}", host, s3.bucket) }
+                    }
+                }
+                BackupDestination::Remote(RemoteDestination::WebDav(webdav)) => {
+                    // Same convention of GVfs: dav:// over http, davs:// over https
+                    let (scheme, location) = match webdav.url.split_once("://") {
+                        Some(("https", location)) => { ("davs", location) }
+                        Some((_, location)) => { ("dav", location) }
+                        None => { ("dav", webdav.url.as_str()) }
+                    };
+                    format!("{}://{}@{}", scheme, webdav.user, location.trim_end_matches('/'))
+                }
+                BackupDestination::Remote(RemoteDestination::Removable(removable)) => {
+                    let mut criteria: Vec<String> = vec![];
+                    if let Some(label) = &removable.label {
+                        criteria.push(format!("label={}", label));
+                    }
+                    if let Some(uuid) = &removable.uuid {
+                        criteria.push(format!("uuid={}", uuid));
+                    }
+                    if removable.marker {
+                        criteria.push(String::from("marker"));
+                    }
+                    if criteria.is_empty() {
+                        criteria.push(String::from("any"));
+                    }
+                    match &removable.dir {
+                        Some(dir) => { format!("removable:{}/{}", criteria.join(","), dir.trim_matches('/')) }
+                        None => { format!("removable:{}", criteria.join(",")) }
+                    }
+                }
+            }
+        }
+
+        /// Directory of a local destination
+        pub fn local_path(&self) -> Option<&str> {
+            match self {
+                BackupDestination::Path(path) => { Some(path) }
+                BackupDestination::Remote(_) => { None }
+            }
+        }
+
+        fn checks(&self) -> anyhow::Result<()> {
+            match self {
+                BackupDestination::Path(path) if path.is_empty() => { bail!(BackupPathNotConfigured) }
+                BackupDestination::Path(_) => { Ok(()) }
+                BackupDestination::Remote(RemoteDestination::Sftp(sftp)) => {
+                    if sftp.host.is_empty() || sftp.user.is_empty() || sftp.key_path.is_empty() || sftp.remote_dir.is_empty() {
+                        bail!(RemoteDestinationConfigError(String::from("an sftp destination requires host, user, key_path and remote_dir")))
+                    }
+                    Ok(())
+                }
+                BackupDestination::Remote(RemoteDestination::S3(s3)) => {
+                    if s3.bucket.is_empty() {
+                        bail!(RemoteDestinationConfigError(String::from("an s3 destination requires a bucket")))
+                    }
+                    if !s3.endpoint.starts_with("http://") && !s3.endpoint.starts_with("https://") {
+                        bail!(RemoteDestinationConfigError(String::from("the endpoint of an s3 destination must start with http:// or https://")))
+                    }
+                    Ok(())
+                }
+                BackupDestination::Remote(RemoteDestination::Removable(_)) => { Ok(()) }
+                BackupDestination::Remote(RemoteDestination::WebDav(webdav)) => {
+                    if webdav.user.is_empty() {
+                        bail!(RemoteDestinationConfigError(String::from("a webdav destination requires a user")))
+                    }
+                    if !webdav.url.starts_with("http://") && !webdav.url.starts_with("https://") {
+                        bail!(RemoteDestinationConfigError(String::from("the url of a webdav destination must start with http:// or https://")))
+                    }
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    impl From<String> for BackupDestination {
+        fn from(path: String) -> Self {
+            BackupDestination::Path(path)
+        }
+    }
+
+    /// Built-in exclude rule sets that can be enabled through `BackupConfig::exclude_presets`
+    pub const EXCLUDE_PRESETS: [(&str, &[&str]); 3] = [
+        ("rust-target", &["target/"]),
+        ("node-modules", &["node_modules/"]),
+        ("git-objects", &["**/.git/objects/"]),
+    ];
+
+    /// Name of the gitignore-style files that can be placed in the source tree to exclude files from the backup
+    pub const IGNORE_FILE_NAME: &str = ".ebignore";
+
+    /// Directory of the snapshot root holding the files written by the tool, no source is stored under this name
+    pub const SNAPSHOT_METADATA_DIR_NAME: &str = ".emergency-backup";
+
+    /// Fields missing from the configuration file get their default value, so that files written before
+    /// a field was added can still be read. Changes that are not the addition of a field need a migration
+    /// (see `migration`).
     #[derive(Serialize, Deserialize, Debug, Clone)]
+    #[serde(default)]
     pub struct BackupConfig {
+        /// Version of the configuration file layout, see `migration::CONFIG_VERSION`
+        pub version: u64,
+
         /// Mouse position sampling frequency. Higher frequency results in higher CPU usage.
         /// A higher value increases tracking precision but may also negatively impact performance.
         pub millis_update_frequency: usize,
@@ -26,18 +357,100 @@ pub mod settings {
         /// Even with tolerance enabled, it is unlikely that unintentional commands will occur.
         pub tolerance: u32,
 
-        /// Backup source path
-        pub backup_source: String,
+        /// Files and directories to back up, each one is saved under its own name inside the snapshot.
+        pub backup_sources: Vec<BackupSource>,
 
-        /// Backup destination path, a new emergency-backup/ folder will be created containing the content of the backup_source.
-        pub backup_destination: String,
+        /// Ordered list of backup destination paths, a new emergency-backup/ folder will be created in each used destination
+        /// containing the content of the backup_sources.
+        pub backup_destinations: Vec<BackupDestination>,
+
+        /// 'first-available' to write the snapshot only in the first reachable and writable destination,
+        /// 'all' to write the same snapshot to every destination.
+        pub destination_policy: String,
 
         /// If true, only files with an extension contained in extension_type vector will be copied.
+        /// Used by every source that does not define its own filter.
         pub extension_only: bool,
         pub extension_type: Vec<String>,
 
-        /// 'Folder' or 'File', if the backup has as objective a file or an entire directory
-        pub mode: String,
+        /// Glob rules a file must match to be copied (e.g. `*.rs`, `docs/**`), if empty every file is included.
+        /// Rules without a '/' are matched against the file name at any depth.
+        pub include: Vec<String>,
+
+        /// Gitignore-style rules of files and directories to skip (e.g. `*.tmp`, `build/`).
+        /// `.ebignore` files found in the source tree are applied as well.
+        pub exclude: Vec<String>,
+
+        /// Built-in exclude rule sets: 'rust-target', 'node-modules', 'git-objects'
+        pub exclude_presets: Vec<String>,
+
+        /// Files bigger than this size are skipped (e.g. '100mb'), no limit if not set.
+        pub max_file_size: Option<String>,
+
+        /// Only files modified within this time window are copied (e.g. '12h', '7days').
+        pub modified_within: Option<String>,
+
+        /// Only files modified at or after this date are copied ('YYYY-MM-DD' or RFC 3339).
+        pub min_mtime: Option<String>,
+
+        /// Only files modified at or before this date are copied ('YYYY-MM-DD' or RFC 3339).
+        pub max_mtime: Option<String>,
+
+        /// What to do when a destination is on the same physical device as a source: 'warn' (default)
+        /// copies anyway reporting a warning, 'refuse' skips the destination.
+        pub same_device_policy: String,
+
+        /// Criteria files are copied by, the first one has the highest weight: 'paths' (files under
+        /// `priority_paths` first), 'recent' (most recently modified first), 'smallest' (smallest first).
+        /// If empty, files are copied in walk order.
+        pub priority: Vec<String>,
+
+        /// Paths copied first when the 'paths' criterion is enabled, in order of importance.
+        /// Absolute paths or paths relative to the source root.
+        pub priority_paths: Vec<String>,
+
+        /// Maximum time spent copying a snapshot (e.g. '30s', '2min'), the files left are skipped
+        /// and the snapshot is marked as partial.
+        pub time_budget: Option<String>,
+
+        /// Maximum amount of data copied in a snapshot (e.g. '500mb'), the files that do not fit are
+        /// skipped and the snapshot is marked as partial.
+        pub byte_budget: Option<String>,
+
+        /// Number of files copied at the same time. If not set it depends on the destination: one per CPU
+        /// (at most 8) on SSDs, sequential copy on spinning disks and USB 2 drives.
+        pub copy_workers: Option<u32>,
+
+        /// Maximum amount of data written per second by a backup (e.g. '20mb' for 20 MB/s), no limit if not set.
+        pub bandwidth_limit: Option<String>,
+
+        /// If true the backup runs with the lowest CPU priority and idle I/O priority, so the machine
+        /// stays responsive during a long copy.
+        pub low_priority: bool,
+
+        /// How symbolic links are backed up and restored: 'follow' (default) copies the file or directory
+        /// they point to, 'preserve' recreates the link, 'skip' leaves them out.
+        /// Special files (FIFOs, sockets, devices) are always left out.
+        pub symlink_policy: String,
+
+        /// If true the copies keep the permissions of the original files, otherwise they get the
+        /// permissions of a newly created file.
+        pub preserve_permissions: bool,
+
+        /// If true the copies keep the access and modification time of the original files.
+        pub preserve_mtime: bool,
+
+        /// If true the extended attributes of the original files are copied (Unix only).
+        pub preserve_xattrs: bool,
+
+        /// If true the POSIX ACLs of the original files are copied (Linux only).
+        pub preserve_acls: bool,
+
+        /// Commands run in order before the files are selected, e.g. a database dump into a source directory.
+        pub pre_backup_hooks: Vec<BackupHook>,
+
+        /// Commands run in order once the backup is over, whatever its outcome, e.g. ejecting the drive.
+        pub post_backup_hooks: Vec<BackupHook>,
 
         /// If the mouse_tracker daemon service is active
         pub active: bool,
@@ -48,19 +461,85 @@ pub mod settings {
     impl Default for BackupConfig {
         fn default() -> Self {
             BackupConfig {
-                backup_source: "".to_string(),
-                backup_destination: "".to_string(),
+                version: CONFIG_VERSION,
+                backup_sources: vec![],
+                backup_destinations: vec![],
+                destination_policy: "first-available".to_string(),
                 millis_update_frequency: 200,
                 tracking_window_sec: 15,
                 tolerance: 5,
                 extension_only: false,
                 extension_type: vec![],
+                include: vec![],
+                exclude: vec![],
+                exclude_presets: vec![],
+                max_file_size: None,
+                modified_within: None,
+                min_mtime: None,
+                max_mtime: None,
+                same_device_policy: "warn".to_string(),
+                priority: vec![],
+                priority_paths: vec![],
+                time_budget: None,
+                byte_budget: None,
+                copy_workers: None,
+                bandwidth_limit: None,
+                low_priority: false,
+                symlink_policy: "follow".to_string(),
+                preserve_permissions: true,
+                preserve_mtime: true,
+                preserve_xattrs: false,
+                preserve_acls: false,
+                pre_backup_hooks: vec![],
+                post_backup_hooks: vec![],
                 active: false,
-                mode: "Folder".to_string(),
                 installation_dir: None
             }
         }
     }
+
+    /// Values accepted by `BackupConfig::destination_policy`
+    pub const DESTINATION_POLICIES: [&str; 2] = ["first-available", "all"];
+
+    /// Values accepted by `BackupConfig::same_device_policy`
+    pub const SAME_DEVICE_POLICIES: [&str; 2] = ["warn", "refuse"];
+
+    /// Criteria accepted by `BackupConfig::priority`
+    pub const PRIORITY_CRITERIA: [&str; 3] = ["paths", "recent", "smallest"];
+
+    /// Values accepted by `BackupConfig::symlink_policy`
+    pub const SYMLINK_POLICIES: [&str; 3] = ["follow", "preserve", "skip"];
+
+    /// Parses a size like '500kb' or '1gb'
+    fn parse_size(size: &Option<String>) -> anyhow::Result<Option<u64>> {
+        match size {
+            Some(size) => {
+                match byte_unit::Byte::parse_str(size, true) {
+                    Ok(bytes) => { Ok(Some(bytes.as_u64())) }
+                    Err(_) => { bail!(FileSizeFormatError) }
+                }
+            }
+            None => { Ok(None) }
+        }
+    }
+
+    /// Parses a 'YYYY-MM-DD' (local time) or RFC 3339 date, a plain date stands for the start of
+    /// the day, or for its end if `end_of_day` is true.
+    fn parse_date(date: &str, end_of_day: bool) -> anyhow::Result<SystemTime> {
+        if let Ok(date_time) = DateTime::parse_from_rfc3339(date) {
+            return Ok(SystemTime::from(date_time));
+        }
+        let day = match NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+            Ok(day) => { day }
+            Err(_) => { bail!(DateFormatError) }
+        };
+        let time = if end_of_day { day.and_hms_opt(23, 59, 59) } else { day.and_hms_opt(0, 0, 0) };
+        match time.and_then(|time| { Local.from_local_datetime(&time).earliest() }) {
+            Some(date_time) => { Ok(SystemTime::from(date_time)) }
+            None => { bail!(DateFormatError) }
+        }
+    }
+
     pub fn ensure_config_dir() -> anyhow::Result<()>{
         match get_config_dir() {
             Ok(config_path) => { Ok(fs::create_dir_all(config_path)?) }
@@ -69,40 +548,228 @@ pub mod settings {
     }
 
     impl BackupConfig {
+        /// Returns the configuration if it is valid, otherwise `ConfigValidationError` listing every problem
         pub fn field_checks(backup_config: BackupConfig) -> anyhow::Result<Self> {
-            if backup_config.millis_update_frequency <= 0 {
-                bail!(MillisUpdateFrequencyError)
+            let report = backup_config.validate();
+            if !report.is_valid() {
+                bail!(ConfigValidationError(report.to_string()))
+            }
+            Ok(backup_config)
+        }
+
+        /// Checks every value of the configuration, the report lists all the problems found with the
+        /// path of the field and a suggested fix
+        pub fn validate(&self) -> ValidationReport {
+            let mut report = ValidationReport::default();
+
+            if self.millis_update_frequency == 0 {
+                report.push(ConfigProblem::new("millis_update_frequency", MillisUpdateFrequencyError, Some(String::from("use a number of milliseconds, e.g. 200 (the default)"))));
+            }
+
+            if self.tracking_window_sec == 0 {
+                report.push(ConfigProblem::new("tracking_window_sec", ZeroTrackingWindowSecError, Some(String::from("use a number of seconds, e.g. 15 (the default)"))));
+            }
+
+            if self.extension_only {
+                if let Err(err) = Self::extension_checks(&self.extension_type) {
+                    report.push(ConfigProblem::new("extension_type", err, Some(String::from("list extensions without the dot, e.g. [pdf, docx], or disable extension_only"))));
+                }
+            }
+
+            if !DESTINATION_POLICIES.contains(&self.destination_policy.to_lowercase().as_str()) {
+                report.push(ConfigProblem::new("destination_policy", DestinationPolicyNotRecognizedError, Some(choice_suggestion(&self.destination_policy, &DESTINATION_POLICIES))));
+            }
+
+            for (index, rule) in self.include.iter().enumerate() {
+                if Glob::new(rule).is_err() {
+                    report.push(ConfigProblem::new(&format!("include[{}]", index), GlobRuleFormatError, Some(String::from("close the brackets and braces of the rule"))));
+                }
+            }
+
+            let preset_names: Vec<&str> = EXCLUDE_PRESETS.iter().map(|(name, _)| { *name }).collect();
+            for (index, preset) in self.exclude_presets.iter().enumerate() {
+                if !preset_names.contains(&preset.as_str()) {
+                    report.push(ConfigProblem::new(&format!("exclude_presets[{}]", index), ExcludePresetNotRecognizedError, Some(choice_suggestion(preset, &preset_names))));
+                }
             }
 
-            if backup_config.extension_only {
-                let regex = Regex::new(r"^(\w+|\|)+$").unwrap();
-                if !regex.is_match(backup_config.extension_type.join("|").as_str()) {
-                    bail!(ExtensionTypeFormatError)
+            // The messages give the format, the suggestions show the value to replace
+            for (field, size) in [("max_file_size", &self.max_file_size), ("byte_budget", &self.byte_budget), ("bandwidth_limit", &self.bandwidth_limit)] {
+                if let (Err(err), Some(size)) = (parse_size(size), size) {
+                    report.push(ConfigProblem::new(field, err, Some(format!("replace '{}' with a number followed by a unit, e.g. '100mb'", size))));
+                }
+            }
+            for (field, duration) in [("modified_within", &self.modified_within), ("time_budget", &self.time_budget)] {
+                if let Some(duration) = duration.as_ref().filter(|duration| { humantime::parse_duration(duration).is_err() }) {
+                    report.push(ConfigProblem::new(field, DurationFormatError, Some(format!("replace '{}' with a number followed by a unit, e.g. '12h'", duration))));
                 }
-                if backup_config.extension_type.is_empty() {
-                    bail!(ExtensionTypeArrayEmptyError)
+            }
+            for (field, date, end_of_day) in [("min_mtime", &self.min_mtime, false), ("max_mtime", &self.max_mtime, true)] {
+                if let Some((date, Err(err))) = date.as_ref().map(|date| { (date, parse_date(date, end_of_day)) }) {
+                    report.push(ConfigProblem::new(field, err, Some(format!("replace '{}' with a date like '2024-07-01'", date))));
+                }
+            }
+
+            if !SAME_DEVICE_POLICIES.contains(&self.same_device_policy.to_lowercase().as_str()) {
+                report.push(ConfigProblem::new("same_device_policy", SameDevicePolicyNotRecognizedError, Some(choice_suggestion(&self.same_device_policy, &SAME_DEVICE_POLICIES))));
+            }
+
+            for (index, destination) in self.backup_destinations.iter().enumerate() {
+                if let Err(err) = destination.checks() {
+                    let suggestion = match destination {
+                        BackupDestination::Path(_) => { String::from("set the directory of the destination, or remove it with 'embctl remove-destination'") }
+                        BackupDestination::Remote(_) => { String::from("complete the settings of the destination, or add it again with 'embctl add-<type>-destination'") }
+                    };
+                    report.push(ConfigProblem::new(&format!("backup_destinations[{}]", index), err, Some(suggestion)));
+                }
+            }
+
+            if !SYMLINK_POLICIES.contains(&self.symlink_policy.to_lowercase().as_str()) {
+                report.push(ConfigProblem::new("symlink_policy", SymlinkPolicyNotRecognizedError, Some(choice_suggestion(&self.symlink_policy, &SYMLINK_POLICIES))));
+            }
+
+            if self.copy_workers == Some(0) {
+                report.push(ConfigProblem::new("copy_workers", ZeroCopyWorkersError, Some(String::from("remove copy_workers to choose them for each destination, or use a positive number"))));
+            }
+
+            for (hooks_field, hooks) in [("pre_backup_hooks", &self.pre_backup_hooks), ("post_backup_hooks", &self.post_backup_hooks)] {
+                for (index, hook) in hooks.iter().enumerate() {
+                    if let Err(err) = hook.checks() {
+                        let (field, suggestion) = if hook.command.trim().is_empty() {
+                            (format!("{}[{}].command", hooks_field, index), String::from("set the command, or remove the hook with 'embctl clear-hooks'"))
+                        } else {
+                            (format!("{}[{}].timeout_sec", hooks_field, index), String::from("remove timeout_sec to use the default of 60 seconds"))
+                        };
+                        report.push(ConfigProblem::new(&field, err, Some(suggestion)));
+                    }
                 }
             }
 
-            if backup_config.tracking_window_sec <= 0 {
-                bail!(ZeroTrackingWindowSecError)
+            for (index, criterion) in self.priority.iter().enumerate() {
+                if !PRIORITY_CRITERIA.contains(&criterion.as_str()) {
+                    report.push(ConfigProblem::new(&format!("priority[{}]", index), PriorityNotRecognizedError, Some(choice_suggestion(criterion, &PRIORITY_CRITERIA))));
+                }
             }
 
-            match backup_config.mode.to_lowercase().as_str() {
-                "file" => {
-                    if PathBuf::from(backup_config.clone().backup_source).is_dir() {
-                        bail!(FolderProvidedFileRequiredError)
+            for (index, source) in self.backup_sources.iter().enumerate() {
+                let field = format!("backup_sources[{}]", index);
+                // The extensions shared with the configuration are already checked above
+                if source.extension_type.is_some() {
+                    if let Some(Err(err)) = source.extension_filter(self).map(|extension_type| { Self::extension_checks(&extension_type) }) {
+                        report.push(ConfigProblem::new(&format!("{}.extension_type", field), err, Some(String::from("list extensions without the dot, e.g. [pdf, docx]"))));
                     }
-                },
-                "folder" => {
-                    if !PathBuf::from(backup_config.clone().backup_source).is_dir() {
-                        bail!(FileProvidedFolderRequiredError)
+                }
+
+                for (rule_index, rule) in source.include.iter().flatten().enumerate() {
+                    if Glob::new(rule).is_err() {
+                        report.push(ConfigProblem::new(&format!("{}.include[{}]", field, rule_index), GlobRuleFormatError, Some(String::from("close the brackets and braces of the rule"))));
+                    }
+                }
+
+                let path = PathBuf::from(&source.path);
+                match source.mode.to_lowercase().as_str() {
+                    _ if !path.exists() => {
+                        report.push(ConfigProblem::new(&format!("{}.path", field), format!("{} does not exist", source.path),
+                                                       Some(String::from("fix the path, or remove the source with 'embctl remove-source'"))));
+                    }
+                    "file" if path.is_dir() => {
+                        report.push(ConfigProblem::new(&format!("{}.mode", field), FolderProvidedFileRequiredError, Some(String::from("set the mode to 'Folder'"))));
+                    },
+                    "folder" | "git" if !path.is_dir() => {
+                        report.push(ConfigProblem::new(&format!("{}.mode", field), FileProvidedFolderRequiredError, Some(String::from("set the mode to 'File'"))));
+                    },
+                    "file" | "folder" | "git" => {}
+                    mode => {
+                        report.push(ConfigProblem::new(&format!("{}.mode", field), format!("Unknown mode '{}'", source.mode), Some(choice_suggestion(mode, &["Folder", "File", "Git"]))));
                     }
-                },
-                _ => {}
+                }
             }
 
-            Ok(backup_config)
+            report
+        }
+
+        fn extension_checks(extension_type: &[String]) -> anyhow::Result<()> {
+            let regex = Regex::new(r"^(\w+|\|)+$").unwrap();
+            if !regex.is_match(extension_type.join("|").as_str()) {
+                bail!(ExtensionTypeFormatError)
+            }
+            if extension_type.is_empty() {
+                bail!(ExtensionTypeArrayEmptyError)
+            }
+            Ok(())
+        }
+
+        /// Parsed `max_file_size`
+        pub fn max_file_size_bytes(&self) -> anyhow::Result<Option<u64>> {
+            parse_size(&self.max_file_size)
+        }
+
+        /// Parsed `byte_budget`
+        pub fn byte_budget_bytes(&self) -> anyhow::Result<Option<u64>> {
+            parse_size(&self.byte_budget)
+        }
+
+        /// Parsed `bandwidth_limit`, in bytes per second
+        pub fn bandwidth_limit_bytes(&self) -> anyhow::Result<Option<u64>> {
+            parse_size(&self.bandwidth_limit)
+        }
+
+        /// Parsed `time_budget`
+        pub fn time_budget_duration(&self) -> anyhow::Result<Option<Duration>> {
+            match &self.time_budget {
+                Some(budget) => {
+                    match humantime::parse_duration(budget) {
+                        Ok(duration) => { Ok(Some(duration)) }
+                        Err(_) => { bail!(DurationFormatError) }
+                    }
+                }
+                None => { Ok(None) }
+            }
+        }
+
+        /// Oldest modification time a file can have to be copied, the most recent between
+        /// `min_mtime` and now minus `modified_within`.
+        pub fn modified_after(&self) -> anyhow::Result<Option<SystemTime>> {
+            let within = match &self.modified_within {
+                Some(window) => {
+                    match humantime::parse_duration(window) {
+                        Ok(duration) => { SystemTime::now().checked_sub(duration) }
+                        Err(_) => { bail!(DurationFormatError) }
+                    }
+                }
+                None => { None }
+            };
+            let min_mtime = match &self.min_mtime {
+                Some(date) => { Some(parse_date(date, false)?) }
+                None => { None }
+            };
+            Ok(within.max(min_mtime))
+        }
+
+        /// Newest modification time a file can have to be copied, parsed from `max_mtime`
+        pub fn modified_before(&self) -> anyhow::Result<Option<SystemTime>> {
+            match &self.max_mtime {
+                Some(date) => { Ok(Some(parse_date(date, true)?)) }
+                None => { Ok(None) }
+            }
+        }
+
+        /// Returns the name each source is stored under inside the snapshot, in the same order of `backup_sources`.
+        /// Clashing names get a numeric suffix, so that two sources never end up in the same folder and no source
+        /// ends up in `SNAPSHOT_METADATA_DIR_NAME`.
+        pub fn snapshot_names(&self) -> Vec<String> {
+            let mut names: Vec<String> = vec![];
+            for source in &self.backup_sources {
+                let base_name = source.name.clone().unwrap_or(source.default_name());
+                let mut name = base_name.clone();
+                let mut counter = 2;
+                while names.contains(&name) || name == SNAPSHOT_METADATA_DIR_NAME {
+                    name = format!("{}-{}", base_name, counter);
+                    counter += 1;
+                }
+                names.push(name);
+            }
+            names
         }
     }
 
@@ -112,10 +779,15 @@ pub mod settings {
         let config_path = get_config_path()?;
         match read_or_create_config(&config_path) {
             Ok(config) => { debug!(target: "general", "Loading settings OK"); Ok( config ) }
-            Err(_) => { error!(target: "general", "Loading settings KO"); bail!(LoadSettingsError) }
+            Err(err) => { error!(target: "general", "Loading settings KO: {:#}", err); Err(err.context(LoadSettingsError)) }
         }
     }
 
+    /// Checks the configuration file without changing it, see `validation::check_config_file`
+    pub fn check_settings() -> anyhow::Result<ValidationReport> {
+        Ok(check_config_file(&get_config_path()?))
+    }
+
     ///This function return the absolute path to the config yaml
     pub(crate) fn get_config_path() -> anyhow::Result<PathBuf> {
         debug!(target: "general", "get_config_path START");
@@ -169,28 +841,43 @@ pub mod settings {
 
     }
 
-    fn read_or_create_config(config_path: &PathBuf) -> anyhow::Result<BackupConfig> {
+    fn read_or_create_config(config_path: &Path) -> anyhow::Result<BackupConfig> {
         debug!(target: "general", "read_or_create_config START");
         if config_path.exists() {
             debug!(target: "general", "Config file already exists");
-            read_config(&config_path)
+            read_config(config_path)
         } else {
             debug!(target: "general", "Config file NOT exists");
-            create_config(&config_path)
+            create_config(config_path)
         }
     }
 
-    fn read_config(path: &Path) -> anyhow::Result<BackupConfig> {
+    /// Reads the configuration file at `path`. A file written by an older version of the tool is upgraded
+    /// in place to `CONFIG_VERSION`, the original is kept next to it as `<file name>.v<version>.bak`.
+    pub fn read_config(path: &Path) -> anyhow::Result<BackupConfig> {
         debug!(target: "general", "read_config START");
         let mut file = File::open(path)?;
         let mut yaml_string = String::new();
         let _ = file.read_to_string(&mut yaml_string);
-        //let reader = BufReader::new(file);
 
-        match serde_yaml::from_str(&*yaml_string) {
-            Ok(config) => { debug!(target: "general", "Config read: {:?}", config); Ok(config) }
-            Err(_) => { bail!(LoadSettingsError) }
+        let mut document: serde_yaml::Value = match serde_yaml::from_str(&yaml_string) {
+            Ok(document) => { document }
+            Err(err) => { bail!(ConfigParseError(err.to_string())) }
+        };
+        let found_version = migrate(&mut document)?;
+        let config: BackupConfig = match serde_yaml::from_value(document) {
+            Ok(config) => { debug!(target: "general", "Config read: {:?}", config); config }
+            Err(err) => { bail!(ConfigParseError(err.to_string())) }
+        };
+
+        if found_version < CONFIG_VERSION {
+            let file_name = path.file_name().map(|file_name| { file_name.to_string_lossy().to_string() }).unwrap_or_default();
+            let backup_path = path.with_file_name(format!("{}.v{}.bak", file_name, found_version));
+            fs::copy(path, &backup_path)?;
+            write_config(path, &config)?;
+            info!(target: "general", "Configuration upgraded from version {} to {}, the previous file is saved as {:?}", found_version, CONFIG_VERSION, backup_path);
         }
+        Ok(config)
     }
     fn create_config(path: &Path) -> anyhow::Result<BackupConfig> {
         debug!(target: "general", "create_config START");
@@ -234,6 +921,10 @@ pub mod settings {
     pub fn apply_settings(config: &BackupConfig) -> anyhow::Result<()> {
         debug!(target: "general", "apply_settings START");
         let path = get_config_path()?;
+        write_config(&path, config)
+    }
+
+    fn write_config(path: &Path, config: &BackupConfig) -> anyhow::Result<()> {
         debug!(target: "general", "Saving settings into config file: {:?}", path);
 
         //1. Open file and write the settings
@@ -251,7 +942,7 @@ pub mod settings {
     }
 
     pub fn set_millis_update_time(time: usize) -> anyhow::Result<()>{
-        if time <= 0 {
+        if time == 0 {
             bail!(MillisUpdateFrequencyError)
         }
         match load_settings() {
@@ -273,12 +964,13 @@ pub mod settings {
         }
     }
 
+    /// Replaces every configured source with the given path
     pub fn set_source(source: PathBuf) -> anyhow::Result<()>{
         match load_settings() {
             Ok(mut config) => {
-                match source.canonicalize() {
-                    Ok(full_path) => {
-                        config.backup_source = full_path.to_string_lossy().to_string();
+                match BackupSource::from_path(source) {
+                    Ok(backup_source) => {
+                        config.backup_sources = vec![backup_source];
                         apply_settings(&config)
                     }
                     Err(err) => { bail!(err)}
@@ -288,12 +980,45 @@ pub mod settings {
         }
     }
 
+    pub fn add_source(source: BackupSource) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.backup_sources.push(source);
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Removes the sources whose snapshot name or path matches `source`
+    pub fn remove_source(source: String) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                let names = config.snapshot_names();
+                let sources = config.backup_sources.clone();
+                let kept: Vec<BackupSource> = sources.into_iter()
+                    .zip(names)
+                    .filter(|(backup_source, name)| { *name != source && backup_source.path != source })
+                    .map(|(backup_source, _)| { backup_source })
+                    .collect();
+
+                if kept.len() == config.backup_sources.len() {
+                    bail!(BackupSourceError)
+                }
+                config.backup_sources = kept;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Replaces every configured destination with the given path
     pub fn set_destination(destination: PathBuf) -> anyhow::Result<()>{
         match load_settings() {
             Ok(mut config) => {
                 match destination.canonicalize() {
                     Ok(full_path) => {
-                        config.backup_destination = full_path.to_string_lossy().to_string();
+                        config.backup_destinations = vec![BackupDestination::Path(full_path.to_string_lossy().to_string())];
                         apply_settings(&config)
                     }
                     Err(err) => { bail!(err) }
@@ -304,20 +1029,67 @@ pub mod settings {
         }
     }
 
-    pub fn set_extension_only(extension_only: bool) -> anyhow::Result<()>{
+    /// Appends a destination, it will be the last one tried by the 'first-available' policy
+    pub fn add_destination(destination: PathBuf) -> anyhow::Result<()>{
         match load_settings() {
             Ok(mut config) => {
-                config.extension_only = extension_only;
+                match destination.canonicalize() {
+                    Ok(full_path) => {
+                        config.backup_destinations.push(BackupDestination::Path(full_path.to_string_lossy().to_string()));
+                        apply_settings(&config)
+                    }
+                    Err(err) => { bail!(err) }
+                }
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Appends a remote destination, it will be the last one tried by the 'first-available' policy
+    pub fn add_remote_destination(destination: RemoteDestination) -> anyhow::Result<()>{
+        let destination = BackupDestination::Remote(destination);
+        destination.checks()?;
+        match load_settings() {
+            Ok(mut config) => {
+                config.backup_destinations.push(destination);
                 apply_settings(&config)
             }
             Err(err) => { bail!(err) }
         }
     }
 
-    pub fn set_mode(mode: String) -> anyhow::Result<()>{
+    /// Removes a destination, given its path or, for a remote one, its name (see `BackupDestination::name`)
+    pub fn remove_destination(destination: String) -> anyhow::Result<()>{
         match load_settings() {
             Ok(mut config) => {
-                config.mode = mode;
+                let destinations_len = config.backup_destinations.len();
+                config.backup_destinations.retain(|backup_destination| { backup_destination.name() != destination });
+                if config.backup_destinations.len() == destinations_len {
+                    bail!(BackupDestinationError)
+                }
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_destination_policy(policy: String) -> anyhow::Result<()>{
+        if !DESTINATION_POLICIES.contains(&policy.to_lowercase().as_str()) {
+            bail!(DestinationPolicyNotRecognizedError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.destination_policy = policy;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_extension_only(extension_only: bool) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.extension_only = extension_only;
                 apply_settings(&config)
             }
             Err(err) => { bail!(err) }
@@ -325,7 +1097,7 @@ pub mod settings {
     }
 
     pub fn set_tracking_window_sec(secs: usize) -> anyhow::Result<()>{
-        if secs <= 0 {
+        if secs == 0 {
             bail!(ZeroTrackingWindowSecError)
         }
         match load_settings() {
@@ -347,6 +1119,207 @@ pub mod settings {
         }
     }
 
+    pub fn set_include(rules: Vec<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.include = rules;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_exclude(rules: Vec<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.exclude = rules;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_exclude_presets(presets: Vec<String>) -> anyhow::Result<()>{
+        if presets.iter().any(|preset| { !EXCLUDE_PRESETS.iter().any(|(name, _)| { name == preset }) }) {
+            bail!(ExcludePresetNotRecognizedError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.exclude_presets = presets;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_max_file_size(size: Option<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.max_file_size = size;
+                config.max_file_size_bytes()?;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_modified_within(window: Option<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.modified_within = window;
+                config.modified_after()?;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_mtime_range(min_mtime: Option<String>, max_mtime: Option<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.min_mtime = min_mtime;
+                config.max_mtime = max_mtime;
+                config.modified_after()?;
+                config.modified_before()?;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_same_device_policy(policy: String) -> anyhow::Result<()>{
+        if !SAME_DEVICE_POLICIES.contains(&policy.to_lowercase().as_str()) {
+            bail!(SameDevicePolicyNotRecognizedError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.same_device_policy = policy;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_priority(criteria: Vec<String>) -> anyhow::Result<()>{
+        if criteria.iter().any(|criterion| { !PRIORITY_CRITERIA.contains(&criterion.as_str()) }) {
+            bail!(PriorityNotRecognizedError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.priority = criteria;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_priority_paths(paths: Vec<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.priority_paths = paths;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_budget(time_budget: Option<String>, byte_budget: Option<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.time_budget = time_budget;
+                config.byte_budget = byte_budget;
+                config.time_budget_duration()?;
+                config.byte_budget_bytes()?;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_copy_workers(copy_workers: Option<u32>) -> anyhow::Result<()>{
+        if copy_workers == Some(0) {
+            bail!(ZeroCopyWorkersError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.copy_workers = copy_workers;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_bandwidth_limit(limit: Option<String>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.bandwidth_limit = limit;
+                config.bandwidth_limit_bytes()?;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_low_priority(low_priority: bool) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.low_priority = low_priority;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Adds a hook after the pre-backup hooks (`pre` true) or the post-backup ones
+    pub fn add_hook(pre: bool, hook: BackupHook) -> anyhow::Result<()>{
+        hook.checks()?;
+        match load_settings() {
+            Ok(mut config) => {
+                if pre { config.pre_backup_hooks.push(hook) } else { config.post_backup_hooks.push(hook) }
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Removes every pre-backup hook (`pre` true) or post-backup hook
+    pub fn clear_hooks(pre: bool) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                if pre { config.pre_backup_hooks.clear() } else { config.post_backup_hooks.clear() }
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    pub fn set_symlink_policy(policy: String) -> anyhow::Result<()>{
+        if !SYMLINK_POLICIES.contains(&policy.to_lowercase().as_str()) {
+            bail!(SymlinkPolicyNotRecognizedError)
+        }
+        match load_settings() {
+            Ok(mut config) => {
+                config.symlink_policy = policy;
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
+    /// Updates the metadata preserved by backups and restores, the options not given are left unchanged
+    pub fn set_preserve(permissions: Option<bool>, mtime: Option<bool>, xattrs: Option<bool>, acls: Option<bool>) -> anyhow::Result<()>{
+        match load_settings() {
+            Ok(mut config) => {
+                config.preserve_permissions = permissions.unwrap_or(config.preserve_permissions);
+                config.preserve_mtime = mtime.unwrap_or(config.preserve_mtime);
+                config.preserve_xattrs = xattrs.unwrap_or(config.preserve_xattrs);
+                config.preserve_acls = acls.unwrap_or(config.preserve_acls);
+                apply_settings(&config)
+            }
+            Err(err) => { bail!(err) }
+        }
+    }
+
     pub fn set_installation_dir(path: String) -> anyhow::Result<()>{
         match load_settings() {
             Ok(mut config) => {
@@ -0,0 +1,97 @@
+use anyhow::bail;
+use log::info;
+use serde_yaml::{Mapping, Value};
+use errors::Error::{ConfigMigrationError, ConfigVersionError};
+
+/// Version of the configuration file written by this version of the tool
+pub const CONFIG_VERSION: u64 = 2;
+
+/// Key of the version in the configuration file. Files without it come from version 1.0.0 of the tool.
+pub const VERSION_KEY: &str = "version";
+
+/// A migration upgrades a configuration file of version `index + 1` to the next version
+type Migration = fn(&mut Mapping) -> anyhow::Result<()>;
+
+const MIGRATIONS: [Migration; 1] = [
+    migrate_v1_to_v2,
+];
+
+/// Version of a configuration document, 1 if it has no version key
+pub fn config_version(config: &Mapping) -> anyhow::Result<u64> {
+    match config.get(&Value::from(VERSION_KEY)) {
+        None => { Ok(1) }
+        Some(version) => {
+            match version.as_u64() {
+                Some(version) if version > 0 => { Ok(version) }
+                _ => { bail!(ConfigMigrationError(format!("'{}' must be a positive integer, found {:?}", VERSION_KEY, version))) }
+            }
+        }
+    }
+}
+
+/// Upgrades the configuration document to `CONFIG_VERSION` applying the migrations in order, returns the
+/// version it had. Documents of a newer version are refused, they may hold settings this version would drop.
+pub fn migrate(document: &mut Value) -> anyhow::Result<u64> {
+    let config = match document.as_mapping_mut() {
+        Some(config) => { config }
+        None => { bail!(ConfigMigrationError(String::from("the configuration is not a YAML mapping"))) }
+    };
+    let found = config_version(config)?;
+    if found > CONFIG_VERSION {
+        bail!(ConfigVersionError { found, supported: CONFIG_VERSION })
+    }
+
+    for version in found..CONFIG_VERSION {
+        info!(target: "general", "Upgrading the configuration from version {} to {}", version, version + 1);
+        MIGRATIONS[(version - 1) as usize](config)?;
+        config.insert(Value::from(VERSION_KEY), Value::from(version + 1));
+    }
+    Ok(found)
+}
+
+/// 1.0.0 had a single source, with its mode at the top level, and a single destination. Only the legacy keys
+/// are converted: a file without a version that already has the lists keeps them, one with both is refused.
+fn migrate_v1_to_v2(config: &mut Mapping) -> anyhow::Result<()> {
+    for (legacy, current) in [("backup_source", "backup_sources"), ("backup_destination", "backup_destinations")] {
+        if config.contains_key(&Value::from(legacy)) && config.contains_key(&Value::from(current)) {
+            bail!(ConfigMigrationError(format!("both '{}' and '{}' are set, remove one of them", legacy, current)))
+        }
+    }
+
+    let mode = take_string(config, "mode")?;
+    if let Some(source) = take_string_key(config, "backup_source")? {
+        let mut sources: Vec<Value> = vec![];
+        if !source.is_empty() {
+            let mut backup_source = Mapping::new();
+            backup_source.insert(Value::from("path"), Value::from(source));
+            backup_source.insert(Value::from("mode"), Value::from(mode.unwrap_or(String::from("Folder"))));
+            sources.push(Value::Mapping(backup_source));
+        }
+        config.insert(Value::from("backup_sources"), Value::Sequence(sources));
+    }
+    if let Some(destination) = take_string_key(config, "backup_destination")? {
+        let mut destinations: Vec<Value> = vec![];
+        if !destination.is_empty() {
+            destinations.push(Value::from(destination));
+        }
+        config.insert(Value::from("backup_destinations"), Value::Sequence(destinations));
+    }
+    Ok(())
+}
+
+/// Like `take_string`, but a key that is present with a null value is returned as an empty string
+fn take_string_key(config: &mut Mapping, key: &str) -> anyhow::Result<Option<String>> {
+    if !config.contains_key(&Value::from(key)) {
+        return Ok(None);
+    }
+    Ok(Some(take_string(config, key)?.unwrap_or_default()))
+}
+
+/// Removes `key` from the configuration, returning its value if it is a string
+fn take_string(config: &mut Mapping, key: &str) -> anyhow::Result<Option<String>> {
+    match config.remove(&Value::from(key)) {
+        None | Some(Value::Null) => { Ok(None) }
+        Some(Value::String(value)) => { Ok(Some(value)) }
+        Some(value) => { bail!(ConfigMigrationError(format!("'{}' must be a string, found {:?}", key, value))) }
+    }
+}
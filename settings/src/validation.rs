@@ -0,0 +1,266 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::path::Path;
+use serde_yaml::{Mapping, Value};
+use errors::Error;
+use crate::migration::{migrate, CONFIG_VERSION, VERSION_KEY};
+use crate::settings::{BackupConfig, BackupHook, BackupSource};
+
+/// A problem found in the configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    /// Path of the field, e.g. 'backup_sources[1].mode', empty if the problem is not about a field
+    pub field: String,
+
+    /// Line and column in the configuration file, starting from 1. Only known for the problems found
+    /// reading the file.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+
+    pub message: String,
+
+    /// How to fix the problem, e.g. "did you mean 'recent'?"
+    pub suggestion: Option<String>,
+}
+
+impl ConfigProblem {
+    pub fn new(field: &str, message: impl ToString, suggestion: Option<String>) -> Self {
+        ConfigProblem { field: field.to_string(), line: None, column: None, message: message.to_string(), suggestion }
+    }
+
+    /// The problem is about `field` or one of its items and subfields, e.g. 'backup_sources' for 'backup_sources[1].mode'
+    pub fn is_about(&self, field: &str) -> bool {
+        match self.field.strip_prefix(field) {
+            Some(rest) => { rest.is_empty() || rest.starts_with('[') || rest.starts_with('.') }
+            None => { false }
+        }
+    }
+}
+
+impl Display for ConfigProblem {
+    /// e.g. 'priority[0] (line 12, column 5): Invalid priority criterion. Must be ... - did you mean 'recent'?'
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let location = match (self.line, self.column) {
+            (Some(line), Some(column)) => { format!("{} (line {}, column {})", self.field, line, column) }
+            (Some(line), None) => { format!("{} (line {})", self.field, line) }
+            _ => { self.field.clone() }
+        };
+        if !location.trim().is_empty() {
+            write!(f, "{}: ", location.trim())?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " - {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem found in a configuration
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ValidationReport {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn push(&mut self, problem: ConfigProblem) {
+        self.problems.push(problem);
+    }
+
+    /// Problems about `field`, see `ConfigProblem::is_about`
+    pub fn problems_about(&self, field: &str) -> Vec<&ConfigProblem> {
+        self.problems.iter().filter(|problem| { problem.is_about(field) }).collect()
+    }
+}
+
+impl Display for ValidationReport {
+    /// One line per problem
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let lines: Vec<String> = self.problems.iter().map(|problem| { problem.to_string() }).collect();
+        write!(f, "{}", lines.join("\n"))
+    }
+}
+
+/// Checks the configuration file at `path` without changing it: YAML syntax and types, with the line and
+/// column of the problem, settings that are not known (misspelled), then the values (see `BackupConfig::validate`).
+/// A file of an older version is checked as it would be once upgraded.
+pub fn check_config_file(path: &Path) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let yaml = match fs::read_to_string(path) {
+        Ok(yaml) => { yaml }
+        Err(err) => {
+            report.push(ConfigProblem::new("", format!("Cannot read {:?}: {}", path, err),
+                                           Some(String::from("the file is created with the default settings the first time embctl or embgui runs"))));
+            return report;
+        }
+    };
+
+    let mut document: Value = match serde_yaml::from_str(&yaml) {
+        Ok(document) => { document }
+        Err(err) => { report.push(yaml_problem(&err)); return report; }
+    };
+    let found_version = match migrate(&mut document) {
+        Ok(found_version) => { found_version }
+        Err(err) => {
+            let suggestion = match err.downcast_ref::<Error>() {
+                Some(Error::ConfigVersionError { .. }) => { String::from("update the tool to the version that wrote the file, or restore one of the config.yaml.v<version>.bak copies") }
+                _ => { format!("set '{}' to {}", VERSION_KEY, CONFIG_VERSION) }
+            };
+            let mut problem = ConfigProblem::new(VERSION_KEY, err, Some(suggestion));
+            problem.line = key_line(&yaml, VERSION_KEY);
+            report.push(problem);
+            return report;
+        }
+    };
+
+    if let Some(config) = document.as_mapping() {
+        unknown_keys(&yaml, config, &mut report);
+    }
+    // Files of the current version are parsed again from the text, to know where the problem is
+    let config: Result<BackupConfig, serde_yaml::Error> = if found_version == CONFIG_VERSION { serde_yaml::from_str(&yaml) } else { serde_yaml::from_value(document) };
+    match config {
+        Ok(config) => { report.problems.extend(config.validate().problems) }
+        Err(err) => { report.push(yaml_problem(&err)) }
+    }
+    report
+}
+
+/// Problem of a YAML syntax or type error, e.g. 'tolerance: invalid type: string "abc", expected u32 at line 2 column 12'
+fn yaml_problem(err: &serde_yaml::Error) -> ConfigProblem {
+    let mut message = err.to_string();
+    if let Some(location) = err.location() {
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    let (field, message) = match message.split_once(": ") {
+        Some((field, rest)) if !field.is_empty() && field.chars().all(|c| { c.is_alphanumeric() || "_.[]".contains(c) }) => { (field.to_string(), rest.to_string()) }
+        _ => { (String::new(), message) }
+    };
+
+    let suggestion = if message.starts_with("invalid type") || message.starts_with("invalid value") {
+        if message.ends_with("expected u32") || message.ends_with("expected u64") || message.ends_with("expected usize") {
+            Some(String::from("use a positive whole number, without quotes"))
+        } else if message.ends_with("expected a boolean") {
+            Some(String::from("use true or false"))
+        } else if message.ends_with("expected a string") {
+            Some(String::from("use a single value, quoted if it contains ': ' or starts with a special character"))
+        } else if message.ends_with("expected a sequence") {
+            Some(String::from("use a list, one '- value' line per item or [value, value]"))
+        } else {
+            None
+        }
+    } else if message.contains("untagged enum BackupDestination") {
+        Some(String::from("a destination is a path, or a map with a 'type' (sftp, s3, webdav, removable) and the settings of that type"))
+    } else if let Some(missing) = message.strip_prefix("missing field `").and_then(|rest| { rest.strip_suffix('`') }) {
+        Some(format!("add the '{}' setting", missing))
+    } else if field.is_empty() {
+        Some(String::from("check the indentation, and quote the values holding ': ' or starting with '*', '&', '[' or '{'"))
+    } else {
+        None
+    };
+
+    ConfigProblem {
+        field,
+        line: err.location().map(|location| { location.line() }),
+        column: err.location().map(|location| { location.column() }),
+        message,
+        suggestion,
+    }
+}
+
+/// Reports the settings the tool does not know, they are ignored when the file is read
+fn unknown_keys(yaml: &str, config: &Mapping, report: &mut ValidationReport) {
+    let known = known_keys(&BackupConfig::default());
+    check_keys(yaml, config, "", &known, report);
+
+    let source_keys = known_keys(&BackupSource::default());
+    if let Some(Value::Sequence(sources)) = config.get(&Value::from("backup_sources")) {
+        for (index, source) in sources.iter().enumerate() {
+            if let Some(source) = source.as_mapping() {
+                check_keys(yaml, source, &format!("backup_sources[{}].", index), &source_keys, report);
+            }
+        }
+    }
+
+    let hook_keys = known_keys(&BackupHook { command: String::new(), timeout_sec: None, abort_on_failure: false });
+    for hooks_key in ["pre_backup_hooks", "post_backup_hooks"] {
+        if let Some(Value::Sequence(hooks)) = config.get(&Value::from(hooks_key)) {
+            for (index, hook) in hooks.iter().enumerate() {
+                if let Some(hook) = hook.as_mapping() {
+                    check_keys(yaml, hook, &format!("{}[{}].", hooks_key, index), &hook_keys, report);
+                }
+            }
+        }
+    }
+}
+
+fn check_keys(yaml: &str, mapping: &Mapping, prefix: &str, known: &[String], report: &mut ValidationReport) {
+    for key in mapping.iter().filter_map(|(key, _)| { key.as_str() }) {
+        if !known.iter().any(|known_key| { known_key == key }) {
+            let suggestion = match closest(key, known) {
+                Some(known_key) => { format!("did you mean '{}'?", known_key) }
+                None => { String::from("remove it") }
+            };
+            let mut problem = ConfigProblem::new(&format!("{}{}", prefix, key), "Unknown setting, it is ignored", Some(suggestion));
+            problem.line = key_line(yaml, key);
+            report.push(problem);
+        }
+    }
+}
+
+/// Keys of the YAML representation of `value`
+fn known_keys<T: serde::Serialize>(value: &T) -> Vec<String> {
+    match serde_yaml::to_value(value) {
+        Ok(Value::Mapping(mapping)) => { mapping.iter().filter_map(|(key, _)| { key.as_str().map(str::to_string) }).collect() }
+        _ => { vec![] }
+    }
+}
+
+/// First line holding `key`, starting from 1
+fn key_line(yaml: &str, key: &str) -> Option<usize> {
+    yaml.lines()
+        .position(|line| {
+            let line = line.trim_start().trim_start_matches("- ");
+            line.strip_prefix(key).map(|rest| { rest.trim_start().starts_with(':') }).unwrap_or(false)
+        })
+        .map(|index| { index + 1 })
+}
+
+/// Option closest to `value`, if it differs by at most a third of its characters (a typo)
+pub fn closest<'a>(value: &str, options: &'a [impl AsRef<str>]) -> Option<&'a str> {
+    let value = value.to_lowercase();
+    options.iter()
+        .map(|option| { (option.as_ref(), edit_distance(&value, &option.as_ref().to_lowercase())) })
+        .filter(|(option, distance)| { *distance > 0 && *distance <= option.chars().count().div_ceil(3) })
+        .min_by_key(|(_, distance)| { *distance })
+        .map(|(option, _)| { option })
+}
+
+/// "did you mean 'x'?" for a value close to one of `options`, the list of the options otherwise
+pub fn choice_suggestion(value: &str, options: &[impl AsRef<str>]) -> String {
+    match closest(value, options) {
+        Some(option) => { format!("did you mean '{}'?", option) }
+        None => { format!("use one of: {}", options.iter().map(|option| { format!("'{}'", option.as_ref()) }).collect::<Vec<String>>().join(", ")) }
+    }
+}
+
+/// Levenshtein distance
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + if a_char == *b_char { 0 } else { 1 };
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
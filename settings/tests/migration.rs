@@ -0,0 +1,128 @@
+//! Configuration files written by every released version of the tool, read and upgraded from a temporary directory.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use errors::Error;
+use settings::migration::CONFIG_VERSION;
+use settings::settings::{read_config, BackupDestination, BackupSource};
+use tempfile::TempDir;
+
+/// Copies the fixture into a temporary directory as `config.yaml`
+fn config_file(fixture: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(fixture), &path).unwrap();
+    (dir, path)
+}
+
+#[test]
+fn config_of_1_0_0_is_upgraded_in_place() {
+    let (dir, path) = config_file("config-1.0.0.yaml");
+    let original = fs::read_to_string(&path).unwrap();
+
+    let config = read_config(&path).unwrap();
+
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!((config.millis_update_frequency, config.tracking_window_sec, config.tolerance), (150, 20, 8));
+    assert_eq!(config.backup_sources, vec![BackupSource {
+        path: String::from("/home/alice/Documents"),
+        mode: String::from("Folder"),
+        ..BackupSource::default()
+    }]);
+    assert_eq!(config.backup_destinations, vec![BackupDestination::Path(String::from("/media/alice/USB"))]);
+    assert!(config.extension_only && config.active);
+    assert_eq!(config.extension_type, vec!["pdf", "docx"]);
+    assert_eq!(config.installation_dir.as_deref(), Some("/home/alice/.emergency-backup/bin"));
+    // Settings added after 1.0.0 get their default
+    assert_eq!(config.destination_policy, "first-available");
+    assert_eq!(config.symlink_policy, "follow");
+    assert!(config.preserve_permissions && config.pre_backup_hooks.is_empty());
+
+    // The original is kept and the file now has the current layout
+    assert_eq!(fs::read_to_string(dir.path().join("config.yaml.v1.bak")).unwrap(), original);
+    let upgraded = fs::read_to_string(&path).unwrap();
+    assert!(upgraded.contains(&format!("version: {}", CONFIG_VERSION)) && !upgraded.contains("backup_source:"), "{}", upgraded);
+    fs::remove_file(dir.path().join("config.yaml.v1.bak")).unwrap();
+    assert_eq!(read_config(&path).unwrap().backup_sources, config.backup_sources);
+    assert!(!dir.path().join("config.yaml.v1.bak").exists());
+}
+
+#[test]
+fn default_config_of_1_0_0_has_no_source_nor_destination() {
+    let (_dir, path) = config_file("config-1.0.0-default.yaml");
+
+    let config = read_config(&path).unwrap();
+
+    assert!(config.backup_sources.is_empty() && config.backup_destinations.is_empty());
+    assert!(!config.active && config.installation_dir.is_none());
+}
+
+#[test]
+fn config_without_version_keeps_its_lists() {
+    let (dir, path) = config_file("config-unversioned-lists.yaml");
+
+    let config = read_config(&path).unwrap();
+
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.backup_sources.iter().map(|source| { source.path.as_str() }).collect::<Vec<_>>(),
+               vec!["/home/alice/Documents", "/home/alice/notes.txt"]);
+    assert_eq!(config.backup_sources[1].mode, "File");
+    assert_eq!(config.backup_destinations, vec![BackupDestination::Path(String::from("/media/alice/USB"))]);
+    assert_eq!(read_config(&path).unwrap().backup_sources, config.backup_sources);
+    assert!(dir.path().join("config.yaml.v1.bak").exists());
+}
+
+#[test]
+fn config_with_both_layouts_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    let original = "backup_source: /home/alice\nbackup_sources:\n  - path: /home/alice/Documents\n    mode: Folder\n";
+    fs::write(&path, original).unwrap();
+
+    let err = read_config(&path).unwrap_err();
+
+    assert!(matches!(err.downcast_ref::<Error>(), Some(Error::ConfigMigrationError(_))), "{}", err);
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn current_config_is_read_without_changes() {
+    let (dir, path) = config_file("config-2.yaml");
+    let original = fs::read_to_string(&path).unwrap();
+
+    let config = read_config(&path).unwrap();
+
+    assert_eq!(config.backup_sources[0].name.as_deref(), Some("documents"));
+    assert_eq!(config.backup_destinations.len(), 2);
+    assert_eq!((config.destination_policy.as_str(), config.copy_workers), ("all", Some(4)));
+    assert_eq!(config.pre_backup_hooks[0].timeout_sec, Some(300));
+    assert_eq!(fs::read_to_string(&path).unwrap(), original);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn missing_fields_get_their_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, format!("version: {}\ntolerance: 3\n", CONFIG_VERSION)).unwrap();
+
+    let config = read_config(&path).unwrap();
+
+    assert_eq!((config.tolerance, config.millis_update_frequency, config.tracking_window_sec), (3, 200, 15));
+    assert_eq!(config.same_device_policy, "warn");
+}
+
+#[test]
+fn newer_or_invalid_config_is_refused() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, format!("version: {}\ntolerance: 3\n", CONFIG_VERSION + 1)).unwrap();
+    let err = read_config(&path).unwrap_err();
+    assert_eq!(err.downcast_ref::<Error>(), Some(&Error::ConfigVersionError { found: CONFIG_VERSION + 1, supported: CONFIG_VERSION }));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+    fs::write(&path, "tolerance: [3\n").unwrap();
+    let err = read_config(&path).unwrap_err();
+    assert!(matches!(err.downcast_ref::<Error>(), Some(Error::ConfigParseError(_))), "{}", err);
+}
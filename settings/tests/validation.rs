@@ -0,0 +1,78 @@
+//! Validation report of configuration files written into a temporary directory.
+
+use std::fs;
+use std::path::PathBuf;
+use settings::settings::{BackupConfig, BackupSource};
+use settings::validation::{check_config_file, closest};
+use tempfile::TempDir;
+
+fn config_file(yaml: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    fs::write(&path, yaml).unwrap();
+    (dir, path)
+}
+
+#[test]
+fn valid_config_has_no_problems() {
+    let source = tempfile::tempdir().unwrap();
+    let (_dir, path) = config_file(&format!("version: 2\nbackup_sources:\n  - path: {}\n    mode: Folder\nbackup_destinations:\n  - /media/usb\nmax_file_size: 100mb\n", source.path().display()));
+
+    let report = check_config_file(&path);
+
+    assert!(report.is_valid(), "{}", report);
+}
+
+#[test]
+fn every_problem_is_reported_with_its_field() {
+    let source = tempfile::tempdir().unwrap();
+    let config = BackupConfig {
+        millis_update_frequency: 0,
+        destination_policy: String::from("al"),
+        max_file_size: Some(String::from("10 parsecs")),
+        priority: vec![String::from("recnet")],
+        backup_sources: vec![BackupSource {
+            path: source.path().to_string_lossy().to_string(),
+            mode: String::from("Folders"),
+            ..BackupSource::default()
+        }],
+        ..BackupConfig::default()
+    };
+
+    let report = config.validate();
+
+    let fields: Vec<&str> = report.problems.iter().map(|problem| { problem.field.as_str() }).collect();
+    for field in ["millis_update_frequency", "destination_policy", "max_file_size", "priority[0]", "backup_sources[0].mode"] {
+        assert!(fields.contains(&field), "{} not in {:?}", field, fields);
+    }
+    assert_eq!(report.problems_about("backup_sources").len(), 1);
+    let priority = &report.problems_about("priority")[0];
+    assert_eq!(priority.suggestion.as_deref(), Some("did you mean 'recent'?"));
+    assert!(report.problems_about("max_file_size")[0].suggestion.as_ref().unwrap().contains("10 parsecs"));
+}
+
+#[test]
+fn syntax_errors_have_line_and_column() {
+    let (_dir, path) = config_file("version: 2\ntolerance: abc\n");
+
+    let report = check_config_file(&path);
+
+    assert_eq!(report.problems.len(), 1);
+    let problem = &report.problems[0];
+    assert_eq!(problem.field, "tolerance");
+    assert_eq!((problem.line, problem.column), (Some(2), Some(12)));
+    assert!(problem.suggestion.is_some());
+}
+
+#[test]
+fn unknown_settings_suggest_the_closest_one() {
+    let (_dir, path) = config_file("version: 2\ntolerence: 5\nbackup_destinations: []\nfoo: bar\n");
+
+    let report = check_config_file(&path);
+
+    let misspelled = &report.problems_about("tolerence")[0];
+    assert_eq!(misspelled.line, Some(2));
+    assert_eq!(misspelled.suggestion.as_deref(), Some("did you mean 'tolerance'?"));
+    assert_eq!(report.problems_about("foo")[0].suggestion.as_deref(), Some("remove it"));
+    assert_eq!(closest("Flder", &["Folder", "File"]), Some("Folder"));
+}
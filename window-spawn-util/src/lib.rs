@@ -98,20 +98,17 @@ pub fn pop_out_error_window(error: String, details: Option<String>) {
         Err(_) => { (1920f32, 1080f32) }
     };
 
-    match build_error_window() {
-        Ok(win_instance) => {
-            let _ = win_instance.set_property("error", Value::from(SharedString::from(error)));
-            if details.is_some() {
-                let _ = win_instance.set_property("details", Value::from(SharedString::from(details.unwrap())));
-            }
-            let error_icon = get_error_icon();
-            let _ = win_instance.set_property("error_icon", Value::Image(error_icon));
-            let window = win_instance.window();
-            let (x, y) = app_config().pop_up_window_size;
-            window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
-            let _ = win_instance.run();
-        },
-        Err(_) => { }
+    if let Ok(win_instance) = build_error_window() {
+        let _ = win_instance.set_property("error", Value::from(SharedString::from(error)));
+        if let Some(details) = details {
+            let _ = win_instance.set_property("details", Value::from(SharedString::from(details)));
+        }
+        let error_icon = get_error_icon();
+        let _ = win_instance.set_property("error_icon", Value::Image(error_icon));
+        let window = win_instance.window();
+        let (x, y) = app_config().pop_up_window_size;
+        window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
+        let _ = win_instance.run();
     }
 }
 
@@ -138,17 +135,14 @@ pub fn pop_out_success_window(message: String) {
         Err(_) => { (1920f32, 1080f32) }
     };
 
-    match build_success_window() {
-        Ok(win_instance) => {
-            let _ = win_instance.set_property("message", Value::from(SharedString::from(message)));
-            let success_icon = get_success_icon();
-            let _ = win_instance.set_property("success_icon", Value::Image(success_icon));
-            let window = win_instance.window();
-            let (x, y) = app_config().pop_up_window_size;
-            window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
-            let _ = win_instance.run();
-        },
-        Err(_) => { }
+    if let Ok(win_instance) = build_success_window() {
+        let _ = win_instance.set_property("message", Value::from(SharedString::from(message)));
+        let success_icon = get_success_icon();
+        let _ = win_instance.set_property("success_icon", Value::Image(success_icon));
+        let window = win_instance.window();
+        let (x, y) = app_config().pop_up_window_size;
+        window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
+        let _ = win_instance.run();
     }
 }
 
@@ -170,32 +164,32 @@ async fn build_deny_window() -> anyhow::Result<ComponentInstance> {
     }
 }
 
-pub fn pop_out_deny_window(message: String) {
+/// Shows the window letting the user cancel the second command listening, `on_cancel` is called before
+/// the process exits when the user cancels or closes the window
+pub fn pop_out_deny_window(message: String, on_cancel: fn()) {
     let (max_x, max_y) = match get_screen_size() {
         Ok((x,y)) => { (x,y) }
         Err(_) => { (1920f32, 1080f32) }
     };
 
-    match build_deny_window() {
-        Ok(win_instance) => {
-
-            let _ = win_instance.set_property("message", Value::from(SharedString::from(message)));
-            let deny_icon = get_deny_icon();
-            let _ = win_instance.set_property("warning_icon", Value::Image(deny_icon));
-            let _ = win_instance.set_callback("cancel_backup",  move | _ : &[Value]| -> Value {
-                //This will stop the mouse_tracker service, the service manager will take care to restart it automatically
-                process::exit(0);
-            });
-            let window = win_instance.window();
-            let (x, y) = app_config().pop_up_window_size;
-            window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
-            window.on_close_requested(|| {
-                process::exit(0);
-            });
-
-            let _ = win_instance.run();
-        },
-        Err(_) => {  }
+    if let Ok(win_instance) = build_deny_window() {
+        let _ = win_instance.set_property("message", Value::from(SharedString::from(message)));
+        let deny_icon = get_deny_icon();
+        let _ = win_instance.set_property("warning_icon", Value::Image(deny_icon));
+        let _ = win_instance.set_callback("cancel_backup",  move | _ : &[Value]| -> Value {
+            //This will stop the mouse_tracker service, the service manager will take care to restart it automatically
+            on_cancel();
+            process::exit(0);
+        });
+        let window = win_instance.window();
+        let (x, y) = app_config().pop_up_window_size;
+        window.set_position(WindowPosition::Logical(LogicalPosition::new((max_x - x)/2f32, (max_y - y) / 2f32)));
+        window.on_close_requested(move || {
+            on_cancel();
+            process::exit(0);
+        });
+
+        let _ = win_instance.run();
     }
 }
 